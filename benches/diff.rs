@@ -0,0 +1,29 @@
+//! Times each diff algorithm on the large corpus in `src/testdata/bench`.
+//!
+//! Run with `cargo bench`. The corpus is a 100k row SQL dump with scattered
+//! edits, deletions and insertions, and a changed first and last line so that
+//! nearly every row ends up in the unique-line matching step.
+
+use std::time::Instant;
+
+use pratdiff::Algorithm;
+use pratdiff::diff;
+
+fn split(content: &[u8]) -> Vec<&[u8]> {
+  content.split(|&b| b == b'\n').collect()
+}
+
+fn main() {
+  let lhs = split(include_bytes!("../src/testdata/bench/old.sql"));
+  let rhs = split(include_bytes!("../src/testdata/bench/new.sql"));
+  for algorithm in Algorithm::ALL {
+    let start = Instant::now();
+    let diffs = diff(&lhs, &rhs, algorithm);
+    println!(
+      "{:<10} {:>5} items in {:?}",
+      format!("{algorithm:?}"),
+      diffs.len(),
+      start.elapsed()
+    );
+  }
+}
@@ -0,0 +1,157 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+use crate::diff::DiffItem;
+use crate::diff::Diffs;
+use crate::similarity::MIN_PAIR_SIMILARITY;
+use crate::similarity::similarity;
+
+/// Blocks needing more line comparisons than this are not aligned.
+const MAX_ALIGN_CELLS: usize = 1 << 16;
+
+/// Lines up the lines of a mutation block by similarity.
+///
+/// `lhs` and `rhs` hold the tokens of each deleted and inserted line. In the
+/// result, `Match` items pair each lhs line with the rhs line it was edited
+/// into, while `Mutation` items cover lines without a counterpart. Pairs never
+/// cross, and they are chosen to maximize the total similarity.
+///
+/// Returns `None` if the block is too large to align.
+pub fn pair_lines<T: Hash + Eq>(
+  lhs: &[Vec<T>],
+  rhs: &[Vec<T>],
+) -> Option<Vec<DiffItem>> {
+  let (n, m) = (lhs.len(), rhs.len());
+  if n.saturating_mul(m) > MAX_ALIGN_CELLS {
+    return None;
+  }
+  let lhs_counts: Vec<_> = lhs.iter().map(|l| counts(l)).collect();
+  let rhs_counts: Vec<_> = rhs.iter().map(|r| counts(r)).collect();
+
+  // best[i][j] is the highest total similarity of pairs among the first `i`
+  // lhs lines and the first `j` rhs lines.
+  let mut best = vec![vec![0.0; m + 1]; n + 1];
+  let mut sim = vec![vec![0.0; m]; n];
+  for i in 0..n {
+    for j in 0..m {
+      sim[i][j] = token_similarity(&lhs_counts[i], &rhs_counts[j]);
+      let mut b = f64::max(best[i][j + 1], best[i + 1][j]);
+      if sim[i][j] >= MIN_PAIR_SIMILARITY {
+        b = b.max(best[i][j] + sim[i][j]);
+      }
+      best[i + 1][j + 1] = b;
+    }
+  }
+
+  let mut steps = vec![];
+  let (mut i, mut j) = (n, m);
+  while i > 0 && j > 0 {
+    if sim[i - 1][j - 1] >= MIN_PAIR_SIMILARITY
+      && best[i][j] == best[i - 1][j - 1] + sim[i - 1][j - 1]
+    {
+      steps.push((1, 1));
+      (i, j) = (i - 1, j - 1);
+    } else if best[i][j] == best[i - 1][j] {
+      steps.push((1, 0));
+      i -= 1;
+    } else {
+      steps.push((0, 1));
+      j -= 1;
+    }
+  }
+  steps.extend(std::iter::repeat_n((0, 1), j));
+  steps.extend(std::iter::repeat_n((1, 0), i));
+
+  let mut diffs = Diffs::default();
+  for &(l, r) in steps.iter().rev() {
+    if l == r {
+      diffs.add_match(1);
+    } else {
+      diffs.add_mutation(l, r);
+    }
+  }
+  Some(diffs.vec)
+}
+
+fn counts<T: Hash + Eq>(tokens: &[T]) -> HashMap<&T, usize> {
+  let mut counts = HashMap::new();
+  for t in tokens {
+    *counts.entry(t).or_default() += 1;
+  }
+  counts
+}
+
+/// How alike two lines are by the tokens they have in common, regardless of
+/// order.
+fn token_similarity<T: Hash + Eq>(
+  lhs: &HashMap<&T, usize>,
+  rhs: &HashMap<&T, usize>,
+) -> f64 {
+  let total: usize = lhs.values().sum::<usize>() + rhs.values().sum::<usize>();
+  let common: usize = lhs
+    .iter()
+    .map(|(t, &c)| c.min(rhs.get(t).copied().unwrap_or(0)))
+    .sum();
+  similarity(common, total)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::diff::DiffItem::*;
+
+  fn words(lines: &[&str]) -> Vec<Vec<String>> {
+    lines
+      .iter()
+      .map(|l| l.split_whitespace().map(str::to_owned).collect())
+      .collect()
+  }
+
+  fn pair(lhs: &[&str], rhs: &[&str]) -> Vec<DiffItem> {
+    pair_lines(&words(lhs), &words(rhs)).unwrap()
+  }
+
+  #[test]
+  fn edits_and_insertions() {
+    assert_eq!(
+      pair(
+        &["let a = 1 ;", "let b = 2 ;", "let c = 3 ;"],
+        &[
+          "let a = 10 ;",
+          "println! ( ) ;",
+          "let b = 2 + x ;",
+          "let c = 3 ;",
+          "return ;",
+        ],
+      ),
+      &[
+        Match { lhs: 0..1, rhs: 0..1 },
+        Mutation { lhs: 1..1, rhs: 1..2 },
+        Match { lhs: 1..3, rhs: 2..4 },
+        Mutation { lhs: 3..3, rhs: 4..5 },
+      ]
+    );
+  }
+
+  #[test]
+  fn dissimilar_lines_are_not_paired() {
+    assert_eq!(
+      pair(&["alpha beta gamma"], &["one two three"]),
+      &[Mutation { lhs: 0..1, rhs: 0..1 }]
+    );
+  }
+
+  #[test]
+  fn best_partner_wins() {
+    assert_eq!(
+      pair(&["x = f ( a , b )"], &["y = 0", "x = f ( a , c )"]),
+      &[Mutation { lhs: 0..0, rhs: 0..1 }, Match { lhs: 0..1, rhs: 1..2 }]
+    );
+  }
+
+  #[test]
+  fn large_blocks_are_not_aligned() {
+    let lines = vec![vec![0u8]; 1000];
+    assert_eq!(pair_lines(&lines, &lines), None);
+  }
+}
@@ -0,0 +1,98 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use regex::bytes::Regex;
+
+use crate::diff::longest_common_subseq;
+
+/// Selects lines that must be matched up with their counterpart, like
+/// `git diff --anchored`.
+///
+/// Only lines that occur exactly once on each side are pinned. When pinned
+/// lines appear in a different order on the two sides, as many as possible
+/// are kept.
+#[derive(Clone, Debug)]
+pub enum Anchor {
+  /// Lines starting with this text.
+  Prefix(Vec<u8>),
+  /// Lines containing a match of this pattern.
+  Regex(Regex),
+}
+
+impl Anchor {
+  pub fn matches(&self, line: &[u8]) -> bool {
+    match self {
+      Anchor::Prefix(p) => line.starts_with(p),
+      Anchor::Regex(r) => r.is_match(line),
+    }
+  }
+}
+
+/// Parses `/pattern/` as a regex and anything else as a line prefix.
+impl FromStr for Anchor {
+  type Err = regex::Error;
+
+  fn from_str(s: &str) -> Result<Anchor, regex::Error> {
+    match s.strip_prefix('/').and_then(|s| s.strip_suffix('/')) {
+      Some(pattern) => Ok(Anchor::Regex(Regex::new(pattern)?)),
+      None => Ok(Anchor::Prefix(s.as_bytes().to_vec())),
+    }
+  }
+}
+
+/// Finds the pairs of lines `anchors` pin together, as increasing
+/// `(lhs, rhs)` indices.
+pub fn anchored_pairs(
+  lhs: &[&[u8]],
+  rhs: &[&[u8]],
+  anchors: &[Anchor],
+) -> Vec<(usize, usize)> {
+  if anchors.is_empty() {
+    return vec![];
+  }
+  let mut m: HashMap<&[u8], (Vec<usize>, Vec<usize>)> = HashMap::new();
+  for (i, l) in lhs.iter().enumerate() {
+    if anchors.iter().any(|a| a.matches(l)) {
+      m.entry(l).or_default().0.push(i);
+    }
+  }
+  for (i, r) in rhs.iter().enumerate() {
+    if let Some((_, rs)) = m.get_mut(r) {
+      rs.push(i);
+    }
+  }
+  let mut pairs: Vec<_> = m
+    .into_values()
+    .filter(|(l, r)| l.len() == 1 && r.len() == 1)
+    .map(|(l, r)| (l[0], r[0]))
+    .collect();
+  if pairs.is_empty() {
+    return pairs;
+  }
+  pairs.sort();
+  longest_common_subseq(&pairs)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn parse() {
+    assert!(matches!("fn ".parse(), Ok(Anchor::Prefix(p)) if p == b"fn "));
+    assert!(matches!("/^fn \\w+/".parse(), Ok(Anchor::Regex(_))));
+    assert!("/(/".parse::<Anchor>().is_err());
+    assert!(matches!("/".parse(), Ok(Anchor::Prefix(p)) if p == b"/"));
+  }
+
+  #[test]
+  fn pairs() {
+    let lhs: [&[u8]; 5] = [b"fn a", b"x", b"fn b", b"fn c", b"fn c"];
+    let rhs: [&[u8]; 5] = [b"fn b", b"y", b"fn a", b"fn c", b"fn d"];
+    let anchors = ["fn ".parse().unwrap()];
+    // "fn c" is not unique and "fn a"/"fn b" cross, so only one survives.
+    assert_eq!(anchored_pairs(&lhs, &rhs, &anchors), &[(2, 0)]);
+    let anchors = ["/a$/".parse().unwrap()];
+    assert_eq!(anchored_pairs(&lhs, &rhs, &anchors), &[(0, 2)]);
+  }
+}
@@ -0,0 +1,549 @@
+use std::fs;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+
+use crate::diff::DiffItem;
+use crate::diff::DiffItem::*;
+use crate::patch::FilePatch;
+use crate::patch::Patch;
+use crate::patch::PatchHunk;
+
+/// Controls how `apply` finds the files and lines a patch changes.
+#[derive(Clone, Debug)]
+pub struct ApplyOptions {
+  /// How many leading components to strip from paths in the patch, like
+  /// `patch -p`. Defaults to 1 for git patches, whose paths start with `a/`
+  /// and `b/`, and 0 otherwise.
+  pub strip: Option<usize>,
+  /// How many context lines at each end of a hunk may be ignored when it
+  /// doesn't apply with all of them.
+  pub fuzz: usize,
+  /// Undo the patch instead of applying it.
+  pub reverse: bool,
+  /// Only report what would happen, without changing any files.
+  pub dry_run: bool,
+}
+
+impl Default for ApplyOptions {
+  fn default() -> ApplyOptions {
+    ApplyOptions {
+      strip: None,
+      fuzz: 2,
+      reverse: false,
+      dry_run: false,
+    }
+  }
+}
+
+/// How a hunk was applied.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HunkStatus {
+  /// Applied where the patch said.
+  Applied,
+  /// Applied `offset` lines away from where the patch said, ignoring `fuzz`
+  /// context lines at each end.
+  Offset { offset: isize, fuzz: usize },
+  /// Couldn't be applied, and was saved to the rejects file.
+  Rejected,
+}
+
+/// What happened to one file of a patch.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileReport {
+  /// The file patched, relative to the directory the patch was applied in.
+  pub path: PathBuf,
+  pub hunks: Vec<HunkStatus>,
+  /// Where rejected hunks were saved (or on a dry run, would be), if any
+  /// were rejected.
+  pub rejects: Option<PathBuf>,
+  /// Why the file couldn't be patched at all.
+  pub error: Option<String>,
+}
+
+impl FileReport {
+  /// Whether every hunk applied.
+  pub fn is_ok(&self) -> bool {
+    self.error.is_none() && !self.hunks.contains(&HunkStatus::Rejected)
+  }
+}
+
+/// Applies `patch` to the files under `dir`.
+///
+/// Hunks that no longer apply at the line the patch says are searched for
+/// throughout the file, preferring the nearest match, and then again with
+/// up to `options.fuzz` context lines at each end ignored. Hunks that still
+/// don't apply are written to a `.rej` file next to the file being patched.
+pub fn apply(
+  patch: &Patch,
+  dir: &Path,
+  options: &ApplyOptions,
+) -> Vec<FileReport> {
+  patch
+    .files
+    .iter()
+    .map(|file| {
+      let file = if options.reverse { file.reverse() } else { file.clone() };
+      let mut report = FileReport {
+        path: PathBuf::new(),
+        hunks: vec![],
+        rejects: None,
+        error: None,
+      };
+      if let Err(e) = apply_file(&file, dir, options, &mut report) {
+        report.error = Some(e);
+      }
+      report
+    })
+    .collect()
+}
+
+fn apply_file(
+  file: &FilePatch,
+  dir: &Path,
+  options: &ApplyOptions,
+  report: &mut FileReport,
+) -> Result<(), String> {
+  let strip = options.strip.unwrap_or(usize::from(file.git));
+  let old = file.old_path.as_deref().map(|p| strip_path(p, strip));
+  let old = old.transpose()?;
+  let new = file.new_path.as_deref().map(|p| strip_path(p, strip));
+  let new = new.transpose()?;
+  // Only git renames and copies read one file and write another. Otherwise
+  // the old and new paths name the same file, such as `a.c.orig` and `a.c`,
+  // and like `patch` we use whichever exists, preferring the old one.
+  let two_files = file.rename || file.copy;
+  let (old, new) = match (old, new) {
+    (Some(old), Some(new)) if !two_files => {
+      let path = if !dir.join(&old).exists() && dir.join(&new).exists() {
+        new
+      } else {
+        old
+      };
+      (Some(path.clone()), Some(path))
+    }
+    paths => paths,
+  };
+  report.path = new.clone().or(old.clone()).unwrap_or_default();
+  if file.binary {
+    return Err("binary patches are not supported".into());
+  }
+
+  let content = match &old {
+    Some(old) => fs::read(dir.join(old))
+      .map_err(|e| format!("can't read {}: {e}", old.display()))?,
+    None if dir.join(&report.path).exists() => {
+      return Err("file to be created already exists".into());
+    }
+    None => vec![],
+  };
+  let mut text = Text::new(&content);
+  let mut rejected = vec![];
+  // How much earlier hunks grew the file, and how far from their stated
+  // position the last of them applied.
+  let (mut delta, mut last_offset) = (0, 0);
+  let mut from = 0;
+  for hunk in &file.hunks {
+    let expected = hunk.hunk.lhs().start as isize + delta;
+    let Some(place) =
+      text.locate(hunk, expected + last_offset, from, options.fuzz)
+    else {
+      report.hunks.push(HunkStatus::Rejected);
+      rejected.push(hunk);
+      continue;
+    };
+    let offset = place.start as isize - expected;
+    report.hunks.push(match (offset, place.fuzz) {
+      (0, 0) => HunkStatus::Applied,
+      (offset, fuzz) => HunkStatus::Offset { offset, fuzz },
+    });
+    from = text.replace(hunk, &place);
+    delta += hunk.new_lines.len() as isize - hunk.old_lines.len() as isize;
+    last_offset = offset;
+  }
+
+  if !rejected.is_empty() {
+    let mut path = report.path.clone().into_os_string();
+    path.push(".rej");
+    report.rejects = Some(path.into());
+  }
+  if options.dry_run {
+    return Ok(());
+  }
+  if let Some(rejects) = &report.rejects {
+    let mut out = vec![];
+    for (prefix, path) in [("---", &file.old_path), ("+++", &file.new_path)] {
+      let path = path.as_deref().unwrap_or(Path::new("/dev/null"));
+      out
+        .extend_from_slice(format!("{prefix} {}\n", path.display()).as_bytes());
+    }
+    for hunk in rejected {
+      hunk.write_unified(&mut out);
+    }
+    write(&dir.join(rejects), &out)?;
+  }
+
+  match (&old, &new) {
+    (Some(old), None) if text.lines.is_empty() => {
+      fs::remove_file(dir.join(old))
+        .map_err(|e| format!("can't delete {}: {e}", old.display()))?
+    }
+    (Some(old), None) => write(&dir.join(old), &text.content())?,
+    (_, Some(new)) => {
+      write(&dir.join(new), &text.content())?;
+      if let Some(old) = old.as_ref().filter(|&old| file.rename && old != new) {
+        fs::remove_file(dir.join(old))
+          .map_err(|e| format!("can't delete {}: {e}", old.display()))?;
+      }
+      if let Some(mode) = file.new_mode.filter(|&m| Some(m) != file.old_mode) {
+        set_mode(&dir.join(new), mode)?;
+      }
+    }
+    (None, None) => {}
+  }
+  Ok(())
+}
+
+/// Removes `n` leading components from `path`, making sure what is left
+/// stays inside the directory being patched.
+fn strip_path(path: &Path, n: usize) -> Result<PathBuf, String> {
+  let components: Vec<_> = path
+    .components()
+    .filter(|c| !matches!(c, Component::CurDir))
+    .collect();
+  if components.len() <= n {
+    return Err(format!("can't strip {n} components from {}", path.display()));
+  }
+  let stripped: PathBuf = components[n..].iter().collect();
+  if !components[n..]
+    .iter()
+    .all(|c| matches!(c, Component::Normal(_)))
+  {
+    return Err(format!("{} is outside the directory", stripped.display()));
+  }
+  Ok(stripped)
+}
+
+fn write(path: &Path, content: &[u8]) -> Result<(), String> {
+  if let Some(parent) = path.parent() {
+    fs::create_dir_all(parent)
+      .map_err(|e| format!("can't create {}: {e}", parent.display()))?;
+  }
+  fs::write(path, content)
+    .map_err(|e| format!("can't write {}: {e}", path.display()))
+}
+
+fn set_mode(path: &Path, mode: u32) -> Result<(), String> {
+  cfg_if::cfg_if! {
+    if #[cfg(unix)] {
+      use std::os::unix::fs::PermissionsExt;
+      fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o777))
+        .map_err(|e| format!("can't set mode of {}: {e}", path.display()))
+    } else {
+      let _ = (path, mode);
+      Ok(())
+    }
+  }
+}
+
+/// Where a hunk applies: its old lines, less `fuzz` context lines at either
+/// end, start at line `start` (or would, for the ignored context lines).
+struct Place {
+  start: usize,
+  fuzz: usize,
+  skip_start: usize,
+  skip_end: usize,
+}
+
+/// A file being patched, split into lines without their newlines.
+struct Text {
+  lines: Vec<Vec<u8>>,
+  /// Whether the last line ends with a newline.
+  newline_at_end: bool,
+}
+
+impl Text {
+  fn new(content: &[u8]) -> Text {
+    let mut lines: Vec<Vec<u8>> =
+      content.split(|&b| b == b'\n').map(|l| l.to_vec()).collect();
+    let newline_at_end = content.ends_with(b"\n");
+    if newline_at_end || content.is_empty() {
+      lines.pop();
+    }
+    Text { lines, newline_at_end }
+  }
+
+  fn content(&self) -> Vec<u8> {
+    let mut out = self.lines.join(&b'\n');
+    if self.newline_at_end && !self.lines.is_empty() {
+      out.push(b'\n');
+    }
+    out
+  }
+
+  /// Finds where `hunk` applies at or after line `from`, nearest to line
+  /// `near`.
+  fn locate(
+    &self,
+    hunk: &PatchHunk,
+    near: isize,
+    from: usize,
+    max_fuzz: usize,
+  ) -> Option<Place> {
+    let context = |d: Option<&DiffItem>| match d {
+      Some(Match { lhs, .. }) => lhs.len(),
+      _ => 0,
+    };
+    let leading = context(hunk.hunk.diffs.first());
+    let trailing = context(hunk.hunk.diffs.last());
+    for fuzz in 0..=max_fuzz.min(leading.max(trailing)) {
+      let (skip_start, skip_end) = (fuzz.min(leading), fuzz.min(trailing));
+      let old = &hunk.old_lines[skip_start..hunk.old_lines.len() - skip_end];
+      let Some(last) = self.lines.len().checked_sub(old.len()) else {
+        continue;
+      };
+      let found = (from..=last)
+        .filter(|&pos| {
+          if self.lines[pos..pos + old.len()] != *old {
+            return false;
+          }
+          // A last line without a newline has to be the file's last line.
+          let at_end = pos + old.len() == self.lines.len();
+          skip_end > 0
+            || !hunk.old_no_newline
+            || (at_end && !self.newline_at_end)
+        })
+        .min_by_key(|&pos| (pos as isize - skip_start as isize).abs_diff(near));
+      if let Some(pos) = found {
+        return Some(Place {
+          start: pos - skip_start,
+          fuzz,
+          skip_start,
+          skip_end,
+        });
+      }
+    }
+    None
+  }
+
+  /// Replaces the old lines of `hunk` with its new ones, returning the line
+  /// after them.
+  fn replace(&mut self, hunk: &PatchHunk, place: &Place) -> usize {
+    let pos = place.start + place.skip_start;
+    let old_len = hunk.old_lines.len() - place.skip_start - place.skip_end;
+    let new =
+      &hunk.new_lines[place.skip_start..hunk.new_lines.len() - place.skip_end];
+    let at_end = pos + old_len == self.lines.len();
+    self.lines.splice(pos..pos + old_len, new.iter().cloned());
+    if at_end && place.skip_end == 0 {
+      self.newline_at_end = !hunk.new_no_newline;
+    }
+    pos + new.len()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Writes `files` into a fresh directory.
+  fn tree(files: &[(&str, &str)]) -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    for (path, content) in files {
+      write(&dir.path().join(path), content.as_bytes()).unwrap();
+    }
+    dir
+  }
+
+  fn read(dir: &tempfile::TempDir, path: &str) -> String {
+    fs::read_to_string(dir.path().join(path)).unwrap()
+  }
+
+  fn run(
+    dir: &tempfile::TempDir,
+    patch: &str,
+    options: ApplyOptions,
+  ) -> Vec<FileReport> {
+    let patch = Patch::parse(patch.as_bytes()).unwrap();
+    apply(&patch, dir.path(), &options)
+  }
+
+  const LINES: &str = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+
+  const PATCH: &str = "\
+diff --git a/f.txt b/f.txt
+--- a/f.txt
++++ b/f.txt
+@@ -2,5 +2,5 @@
+ 2
+ 3
+-4
++four
+ 5
+ 6
+";
+
+  #[test]
+  fn applies_in_place() {
+    let dir = tree(&[("f.txt", LINES)]);
+    let reports = run(&dir, PATCH, ApplyOptions::default());
+    assert_eq!(
+      reports,
+      &[FileReport {
+        path: "f.txt".into(),
+        hunks: vec![HunkStatus::Applied],
+        rejects: None,
+        error: None,
+      }]
+    );
+    assert_eq!(read(&dir, "f.txt"), "1\n2\n3\nfour\n5\n6\n7\n8\n9\n");
+  }
+
+  #[test]
+  fn offset_and_fuzz() {
+    let dir = tree(&[("f.txt", &format!("0\n0\n{LINES}"))]);
+    let reports = run(&dir, PATCH, ApplyOptions::default());
+    assert_eq!(reports[0].hunks, &[HunkStatus::Offset { offset: 2, fuzz: 0 }]);
+    assert_eq!(read(&dir, "f.txt"), "0\n0\n1\n2\n3\nfour\n5\n6\n7\n8\n9\n");
+
+    // The first context line no longer matches.
+    let dir = tree(&[("f.txt", "1\nTWO\n3\n4\n5\n6\n7\n8\n9\n")]);
+    let reports = run(&dir, PATCH, ApplyOptions::default());
+    assert_eq!(reports[0].hunks, &[HunkStatus::Offset { offset: 0, fuzz: 1 }]);
+    assert_eq!(read(&dir, "f.txt"), "1\nTWO\n3\nfour\n5\n6\n7\n8\n9\n");
+  }
+
+  #[test]
+  fn rejects() {
+    let dir = tree(&[("f.txt", "1\n2\n3\nFOUR\n5\n6\n")]);
+    let reports = run(&dir, PATCH, ApplyOptions::default());
+    assert_eq!(reports[0].hunks, &[HunkStatus::Rejected]);
+    assert_eq!(reports[0].rejects, Some("f.txt.rej".into()));
+    assert!(!reports[0].is_ok());
+    assert_eq!(read(&dir, "f.txt"), "1\n2\n3\nFOUR\n5\n6\n");
+    assert_eq!(read(&dir, "f.txt.rej"), PATCH.split_once('\n').unwrap().1);
+  }
+
+  #[test]
+  fn dry_run_and_reverse() {
+    let dir = tree(&[("f.txt", LINES)]);
+    let dry_run = ApplyOptions { dry_run: true, ..Default::default() };
+    let reports = run(&dir, PATCH, dry_run);
+    assert!(reports[0].is_ok());
+    assert_eq!(read(&dir, "f.txt"), LINES);
+
+    run(&dir, PATCH, ApplyOptions::default());
+    let reverse = ApplyOptions { reverse: true, ..Default::default() };
+    let reports = run(&dir, PATCH, reverse);
+    assert_eq!(reports[0].hunks, &[HunkStatus::Applied]);
+    assert_eq!(read(&dir, "f.txt"), LINES);
+  }
+
+  #[test]
+  fn create_delete_and_rename() {
+    let dir = tree(&[("gone.txt", "x\ny\n"), ("old.txt", "a\nb\n")]);
+    let patch = "\
+diff --git a/new/n.txt b/new/n.txt
+new file mode 100644
+--- /dev/null
++++ b/new/n.txt
+@@ -0,0 +1,2 @@
++hello
++world
+\\ No newline at end of file
+diff --git a/gone.txt b/gone.txt
+deleted file mode 100644
+--- a/gone.txt
++++ /dev/null
+@@ -1,2 +0,0 @@
+-x
+-y
+diff --git a/old.txt b/moved.txt
+similarity index 50%
+rename from old.txt
+rename to moved.txt
+--- a/old.txt
++++ b/moved.txt
+@@ -1,2 +1,2 @@
+ a
+-b
++c
+";
+    let reports = run(&dir, patch, ApplyOptions::default());
+    assert!(reports.iter().all(FileReport::is_ok));
+    assert_eq!(read(&dir, "new/n.txt"), "hello\nworld");
+    assert!(!dir.path().join("gone.txt").exists());
+    assert!(!dir.path().join("old.txt").exists());
+    assert_eq!(read(&dir, "moved.txt"), "a\nc\n");
+
+    // Applying it again fails to create the existing file.
+    let reports = run(&dir, patch, ApplyOptions::default());
+    assert_eq!(
+      reports[0].error.as_deref(),
+      Some("file to be created already exists")
+    );
+  }
+
+  #[test]
+  fn patches_whichever_file_exists() {
+    let patch = "\
+--- f.txt.orig
++++ f.txt
+@@ -2,5 +2,5 @@
+ 2
+ 3
+-4
++four
+ 5
+ 6
+";
+    // Without the `.orig`, the new path is patched.
+    let dir = tree(&[("f.txt", LINES)]);
+    let reports = run(&dir, patch, ApplyOptions::default());
+    assert!(reports[0].is_ok(), "{reports:?}");
+    assert_eq!(reports[0].path, Path::new("f.txt"));
+    assert_eq!(read(&dir, "f.txt"), "1\n2\n3\nfour\n5\n6\n7\n8\n9\n");
+    assert!(!dir.path().join("f.txt.orig").exists());
+
+    // With it, the `.orig` is patched in place and the other file is left
+    // alone.
+    let dir = tree(&[("f.txt", "x\n"), ("f.txt.orig", LINES)]);
+    let reports = run(&dir, patch, ApplyOptions::default());
+    assert!(reports[0].is_ok(), "{reports:?}");
+    assert_eq!(reports[0].path, Path::new("f.txt.orig"));
+    assert_eq!(read(&dir, "f.txt.orig"), "1\n2\n3\nfour\n5\n6\n7\n8\n9\n");
+    assert_eq!(read(&dir, "f.txt"), "x\n");
+  }
+
+  #[test]
+  fn tree_patch_without_stripping() {
+    let patch = "\
+diff -ru old/f.txt new/f.txt
+--- old/f.txt
++++ new/f.txt
+@@ -2,5 +2,5 @@
+ 2
+ 3
+-4
++four
+ 5
+ 6
+";
+    let dir = tree(&[("new/f.txt", LINES)]);
+    let p0 = ApplyOptions { strip: Some(0), ..Default::default() };
+    let reports = run(&dir, patch, p0);
+    assert!(reports[0].is_ok(), "{reports:?}");
+    assert_eq!(reports[0].path, Path::new("new/f.txt"));
+    assert_eq!(read(&dir, "new/f.txt"), "1\n2\n3\nfour\n5\n6\n7\n8\n9\n");
+    assert!(!dir.path().join("old").exists());
+  }
+
+  #[test]
+  fn strip_paths() {
+    assert_eq!(strip_path(Path::new("a/b/c"), 1), Ok("b/c".into()));
+    assert_eq!(strip_path(Path::new("./b/c"), 0), Ok("b/c".into()));
+    assert!(strip_path(Path::new("a"), 1).is_err());
+    assert!(strip_path(Path::new("a/../../etc"), 1).is_err());
+    assert!(strip_path(Path::new("/etc/passwd"), 0).is_err());
+  }
+}
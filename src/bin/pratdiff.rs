@@ -1,11 +1,30 @@
 use std::error::Error;
+use std::fs::File;
+use std::io::BufWriter;
+use std::io::Write;
 use std::path::PathBuf;
+use std::process::ExitCode;
+use std::time::Duration;
 
 use clap::ColorChoice;
 use clap::CommandFactory;
 use clap::Parser;
+use clap::Subcommand;
+use clap::ValueEnum;
+use clap::error::ErrorKind;
 use clap_complete_command::Shell;
 use common_path::common_path;
+use pratdiff::Algorithm;
+use pratdiff::Anchor;
+use pratdiff::ApplyOptions;
+use pratdiff::ConflictStyle;
+use pratdiff::Format;
+use pratdiff::HunkStatus;
+use pratdiff::Intraline;
+use pratdiff::MergeOptions;
+use pratdiff::Normalization;
+use pratdiff::Patch;
+use pratdiff::WalkOptions;
 
 #[derive(Parser, Debug)]
 #[command(version = concat!(
@@ -17,19 +36,95 @@ use common_path::common_path;
     ")"
 ))]
 #[command(about = "Diff files using patience algorithm")]
+#[command(args_conflicts_with_subcommands = true)]
 struct Args {
+  #[command(subcommand)]
+  command: Option<Command>,
+
   /// Path to old file, directory tree, or `-` for stdin.
-  #[clap(name = "OLD_FILE", required_unless_present = "shell")]
+  #[clap(name = "OLD_FILE", required_unless_present_any = ["shell", "pager"])]
   lhs: Option<PathBuf>,
 
   /// Path to new file, directory tree, or `-` for stdin.
-  #[clap(name = "NEW_FILE", required_unless_present = "shell")]
+  #[clap(name = "NEW_FILE", required_unless_present_any = ["shell", "pager"])]
   rhs: Option<PathBuf>,
 
   /// Display NUM lines of unchanged context before and after changes
   #[clap(short, long, value_name = "NUM", default_value_t = 3)]
   context: usize,
 
+  /// Diff algorithm used to line up old and new content
+  #[clap(long, value_enum, default_value_t = Algorithm::Patience)]
+  algorithm: Algorithm,
+
+  /// Always match up lines starting with TEXT, or matching /REGEX/, if they
+  /// occur once on each side
+  #[clap(long, value_name = "TEXT|/REGEX/")]
+  anchor: Vec<Anchor>,
+
+  /// How finely to split changed lines when highlighting edits within them
+  #[clap(long, value_enum, default_value_t = Intraline::Token)]
+  intraline: Intraline,
+
+  /// Highlight blocks of lines that moved
+  #[clap(long)]
+  moves: bool,
+
+  /// Settle for a coarser diff of a file after about NUM line comparisons
+  #[clap(long, value_name = "NUM")]
+  max_cost: Option<u64>,
+
+  /// Settle for a coarser diff of a file after MS milliseconds
+  #[clap(long, value_name = "MS")]
+  timeout: Option<u64>,
+
+  /// Keep hunk boundaries where the diff algorithm placed them instead of
+  /// moving them to blank lines and indentation changes
+  #[clap(long)]
+  no_indent_heuristic: bool,
+
+  /// Ignore all whitespace
+  #[clap(short = 'w', long)]
+  ignore_all_space: bool,
+
+  /// Ignore changes in the amount of whitespace
+  #[clap(short = 'b', long)]
+  ignore_space_change: bool,
+
+  /// Ignore whitespace at the end of lines
+  #[clap(long)]
+  ignore_space_at_eol: bool,
+
+  /// Ignore changes whose lines are all blank
+  #[clap(short = 'B', long)]
+  ignore_blank_lines: bool,
+
+  /// Ignore differences in letter case
+  #[clap(short = 'i', long)]
+  ignore_case: bool,
+
+  /// Treat canonically equivalent Unicode (e.g. NFC and NFD) as equal
+  #[clap(long)]
+  unicode_normalize: bool,
+
+  /// Mark context lines that only match because of ignored differences
+  #[clap(long)]
+  flag_normalized: bool,
+
+  /// Memory map files and only load the lines that differ, for files too big
+  /// to read into memory
+  #[clap(long)]
+  large_files: bool,
+
+  /// Skip files that would need more than SIZE bytes (K, M and G suffixes
+  /// allowed) to diff, implies --large-files
+  #[clap(long, value_name = "SIZE", value_parser = parse_size)]
+  max_memory: Option<u64>,
+
+  /// Read and diff up to NUM file pairs at once [default: number of CPUs]
+  #[clap(short, long, value_name = "NUM")]
+  jobs: Option<usize>,
+
   /// Print full paths instead of stripping a common prefix
   #[clap(short, long)]
   verbose_paths: bool,
@@ -37,21 +132,140 @@ struct Args {
   #[clap(long, default_value_t = ColorChoice::Auto)]
   color: ColorChoice,
 
+  /// Write colored diffs, plain patches that `git apply` or `patch` accept,
+  /// JSON described in docs/json.md, or an HTML report
+  #[clap(long, value_enum, default_value_t = Format::Color)]
+  format: Format,
+
+  /// Write to OUT instead of stdout
+  #[clap(short, long, value_name = "OUT")]
+  output: Option<PathBuf>,
+
+  /// Show old and new line numbers before each line
+  #[clap(short = 'n', long)]
+  line_numbers: bool,
+
+  /// Show old and new lines next to each other in two columns
+  #[clap(short = 'y', long, conflicts_with = "pager")]
+  side_by_side: bool,
+
+  /// Fill NUM terminal columns with side-by-side output [default: terminal
+  /// width]
+  #[clap(short = 'W', long, value_name = "NUM")]
+  width: Option<usize>,
+
+  /// Wrap lines too long for their side-by-side column instead of cutting
+  /// them short
+  #[clap(long)]
+  wrap: bool,
+
   /// Group diffs into clusters by change signature
   #[clap(long)]
   cluster: bool,
 
+  /// Read a unified diff from stdin and highlight it, e.g. as git's pager
+  /// with `git config core.pager 'pratdiff --pager --color=always | less -R'`
+  #[clap(long, conflicts_with_all = ["OLD_FILE", "NEW_FILE", "cluster"])]
+  pager: bool,
+
   /// The shell to generate the completions for
   #[arg(long = "completions", value_name = "SHELL", value_enum)]
   shell: Option<Shell>,
 }
 
-fn main() -> Result<(), Box<dyn Error>> {
+#[derive(Subcommand, Debug)]
+enum Command {
+  /// Merge the changes from BASE to OURS and from BASE to THEIRS
+  ///
+  /// Exits with status 1 if there are conflicts. To use as a git merge
+  /// driver, add to your git config:
+  ///
+  ///     [merge "pratdiff"]
+  ///         driver = pratdiff merge %O %A %B -o %A --marker-size %L
+  Merge(MergeArgs),
+
+  /// Apply a unified diff to the files in a directory
+  ///
+  /// Hunks that don't apply where the patch says are looked for elsewhere in
+  /// the file, and with some of their context ignored. Hunks that still
+  /// don't apply are saved to FILE.rej, and the exit status is 1.
+  Apply(ApplyArgs),
+}
+
+#[derive(clap::Args, Debug)]
+struct MergeArgs {
+  /// The common ancestor of both sides
+  base: PathBuf,
+
+  /// Our side, whose version wins where both sides changed the same way
+  ours: PathBuf,
+
+  /// Their side
+  theirs: PathBuf,
+
+  /// Write the merged result to OUT instead of stdout
+  #[clap(short, long, value_name = "OUT")]
+  output: Option<PathBuf>,
+
+  /// How to write out conflicts
+  #[clap(long, value_enum, default_value_t = ConflictStyle::Merge)]
+  conflict_style: ConflictStyle,
+
+  /// Diff algorithm used to line up each side with the base
+  #[clap(long, value_enum, default_value_t = Algorithm::Patience)]
+  algorithm: Algorithm,
+
+  /// Length of conflict markers
+  #[clap(long, value_name = "NUM", default_value_t = 7)]
+  marker_size: usize,
+
+  /// Labels for the ours, base and theirs conflict markers, in that order,
+  /// instead of file names
+  #[clap(short = 'L', long = "label", value_name = "LABEL")]
+  labels: Vec<String>,
+}
+
+#[derive(clap::Args, Debug)]
+struct ApplyArgs {
+  /// The patch, or `-` for stdin
+  #[clap(default_value = "-")]
+  patch: PathBuf,
+
+  /// Strip NUM leading components from paths in the patch [default: 1 for
+  /// git patches, 0 otherwise]
+  #[clap(short = 'p', long, value_name = "NUM")]
+  strip: Option<usize>,
+
+  /// Ignore up to NUM context lines at each end of hunks that don't apply
+  /// as they are
+  #[clap(short = 'F', long, value_name = "NUM", default_value_t = 2)]
+  fuzz: usize,
+
+  /// Undo the patch
+  #[clap(short = 'R', long)]
+  reverse: bool,
+
+  /// Report what would happen without changing any files
+  #[clap(long)]
+  dry_run: bool,
+
+  /// Apply the patch to files in DIR
+  #[clap(short, long, value_name = "DIR", default_value = ".")]
+  directory: PathBuf,
+}
+
+fn main() -> Result<ExitCode, Box<dyn Error>> {
   let args = Args::parse();
   if let Some(shell) = args.shell {
     shell.generate(&mut Args::command(), &mut std::io::stdout());
-    return Ok(());
+    return Ok(ExitCode::SUCCESS);
   }
+  match args.command {
+    Some(Command::Merge(args)) => return merge(args),
+    Some(Command::Apply(args)) => return apply(args),
+    None => {}
+  }
+  check_format(&args).unwrap_or_else(|e| e.exit());
 
   match args.color {
     ColorChoice::Auto => anstream::ColorChoice::Auto,
@@ -60,23 +274,197 @@ fn main() -> Result<(), Box<dyn Error>> {
   }
   .write_global();
 
-  let lhs = args.lhs.unwrap();
-  let rhs = args.rhs.unwrap();
+  let lhs = args.lhs.unwrap_or_default();
+  let rhs = args.rhs.unwrap_or_default();
   let common_prefix = if args.verbose_paths {
     PathBuf::new()
   } else {
     common_path(&lhs, &rhs).unwrap_or_default()
   };
 
-  let mut stdout = anstream::stdout();
+  let mut output: Box<dyn Write> = match &args.output {
+    Some(path) => Box::new(BufWriter::new(anstream::AutoStream::auto(
+      File::create(path).map_err(|e| format!("{}: {e}", path.display()))?,
+    ))),
+    None => Box::new(anstream::stdout()),
+  };
   let mut p =
-    pratdiff::Printer::default(&mut stdout, args.context, common_prefix);
+    pratdiff::Printer::default(&mut output, args.context, common_prefix)
+      .with_algorithm(args.algorithm)
+      .with_moves(args.moves)
+      .with_intraline(args.intraline)
+      .with_anchors(args.anchor)
+      .with_indent_heuristic(!args.no_indent_heuristic)
+      .with_budget(args.max_cost, args.timeout.map(Duration::from_millis))
+      .with_normalization(Normalization {
+        ignore_all_space: args.ignore_all_space,
+        ignore_space_change: args.ignore_space_change,
+        ignore_space_at_eol: args.ignore_space_at_eol,
+        ignore_blank_lines: args.ignore_blank_lines,
+        ignore_case: args.ignore_case,
+        unicode_normalize: args.unicode_normalize,
+      })
+      .with_flag_normalized(args.flag_normalized)
+      .with_format(args.format)
+      .with_roots(lhs.clone(), rhs.clone())
+      .with_line_numbers(args.line_numbers);
+  if args.side_by_side {
+    let width = args.width.unwrap_or_else(terminal_width);
+    p = p.with_side_by_side(width, args.wrap);
+  }
 
-  if args.cluster {
-    let clusters = pratdiff::cluster_files(&lhs, &rhs);
+  if args.pager {
+    match p.print_unified_diff(&mut std::io::stdin().lock()) {
+      // Whoever reads our output, like `less`, may quit before the end.
+      Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => {}
+      result => result?,
+    }
+  } else if args.cluster {
+    let clusters =
+      pratdiff::cluster_files(&lhs, &rhs, args.algorithm, args.intraline);
     p.print_clusters(&clusters)?;
   } else {
-    pratdiff::diff_files(&mut p, &lhs, &rhs)?;
+    let options = WalkOptions {
+      large_files: args.large_files,
+      max_memory: args.max_memory,
+      jobs: args.jobs.unwrap_or_else(|| {
+        std::thread::available_parallelism().map_or(1, |n| n.get())
+      }),
+    };
+    pratdiff::diff_files(&mut p, &lhs, &rhs, options)?;
+  }
+  output.flush()?;
+  Ok(ExitCode::SUCCESS)
+}
+
+/// Rejects options that only work with colored output, since the other
+/// formats have no way to show them.
+fn check_format(args: &Args) -> Result<(), clap::Error> {
+  let flag = if args.format == Format::Color {
+    return Ok(());
+  } else if args.side_by_side {
+    "--side-by-side"
+  } else if args.line_numbers {
+    "--line-numbers"
+  } else if args.pager {
+    "--pager"
+  } else if args.cluster && args.format != Format::Html {
+    "--cluster"
+  } else {
+    return Ok(());
+  };
+  let format = args.format.to_possible_value().unwrap();
+  Err(Args::command().error(
+    ErrorKind::ArgumentConflict,
+    format!("{flag} can't be used with --format={}", format.get_name()),
+  ))
+}
+
+/// The width of the terminal we're printing to, falling back to `$COLUMNS`
+/// and then 80 columns.
+fn terminal_width() -> usize {
+  cfg_if::cfg_if! {
+    if #[cfg(unix)] {
+      // Our output may be piped to a pager, so also ask about stderr.
+      for fd in [libc::STDOUT_FILENO, libc::STDERR_FILENO] {
+        let mut size: libc::winsize = unsafe { std::mem::zeroed() };
+        // SAFETY: TIOCGWINSZ only writes a `winsize` to the pointer.
+        let ok = unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } == 0;
+        if ok && size.ws_col > 0 {
+          return size.ws_col.into();
+        }
+      }
+    }
+  }
+  std::env::var("COLUMNS")
+    .ok()
+    .and_then(|c| c.parse().ok())
+    .unwrap_or(80)
+}
+
+fn merge(args: MergeArgs) -> Result<ExitCode, Box<dyn Error>> {
+  if args.labels.len() > 3 {
+    return Err("at most three labels can be given".into());
+  }
+  let mut labels =
+    [&args.ours, &args.base, &args.theirs].map(|p| p.display().to_string());
+  for (label, arg) in labels.iter_mut().zip(args.labels) {
+    *label = arg;
+  }
+  let options = MergeOptions {
+    algorithm: args.algorithm,
+    style: args.conflict_style,
+    marker_size: args.marker_size,
+    labels,
+  };
+  let merged = pratdiff::merge(
+    &std::fs::read(&args.base)?,
+    &std::fs::read(&args.ours)?,
+    &std::fs::read(&args.theirs)?,
+    &options,
+  );
+  match &args.output {
+    Some(path) => std::fs::write(path, &merged.content)?,
+    None => std::io::stdout().write_all(&merged.content)?,
+  }
+  Ok(if merged.conflicts > 0 { ExitCode::FAILURE } else { ExitCode::SUCCESS })
+}
+
+fn apply(args: ApplyArgs) -> Result<ExitCode, Box<dyn Error>> {
+  let patch = if args.patch.as_os_str() == "-" {
+    let mut patch = vec![];
+    std::io::Read::read_to_end(&mut std::io::stdin(), &mut patch)?;
+    patch
+  } else {
+    std::fs::read(&args.patch)?
+  };
+  let patch = Patch::parse(&patch)
+    .map_err(|e| format!("{}: {e}", args.patch.display()))?;
+  let options = ApplyOptions {
+    strip: args.strip,
+    fuzz: args.fuzz,
+    reverse: args.reverse,
+    dry_run: args.dry_run,
+  };
+  let reports = pratdiff::apply(&patch, &args.directory, &options);
+  let verb = if args.dry_run { "checking" } else { "patching" };
+  for report in &reports {
+    println!("{verb} file {}", report.path.display());
+    if let Some(error) = &report.error {
+      println!("  failed: {error}");
+    }
+    for (i, status) in report.hunks.iter().enumerate() {
+      let status = match *status {
+        HunkStatus::Applied => "applied".into(),
+        HunkStatus::Offset { offset, fuzz: 0 } => {
+          format!("applied at offset {offset:+}")
+        }
+        HunkStatus::Offset { offset, fuzz } => {
+          format!("applied with fuzz {fuzz} at offset {offset:+}")
+        }
+        HunkStatus::Rejected => "REJECTED".into(),
+      };
+      println!("  hunk #{} {status}", i + 1);
+    }
+    if let Some(rejects) = &report.rejects {
+      let verb = if args.dry_run { "would be" } else { "were" };
+      println!("  rejected hunks {verb} saved to {}", rejects.display());
+    }
   }
-  Ok(())
+  let ok = reports.iter().all(|r| r.is_ok());
+  Ok(if ok { ExitCode::SUCCESS } else { ExitCode::FAILURE })
+}
+
+/// Parses a byte count like `4096`, `512K`, `64M` or `2G`.
+fn parse_size(s: &str) -> Result<u64, String> {
+  let (digits, shift) = match s.to_ascii_uppercase().chars().last() {
+    Some('K') => (&s[..s.len() - 1], 10),
+    Some('M') => (&s[..s.len() - 1], 20),
+    Some('G') => (&s[..s.len() - 1], 30),
+    _ => (s, 0),
+  };
+  let n: u64 = digits.parse().map_err(|e| format!("{e}"))?;
+  n.checked_shl(shift)
+    .filter(|v| v >> shift == n)
+    .ok_or_else(|| format!("{s} is too large"))
 }
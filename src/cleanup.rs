@@ -0,0 +1,139 @@
+use crate::diff::DiffItem;
+use crate::diff::DiffItem::*;
+use crate::diff::Diffs;
+use crate::similarity::MIN_HIGHLIGHT_SIMILARITY;
+use crate::similarity::similarity;
+use crate::tokens::is_whitespace_token;
+
+/// Makes a token level diff easier to read.
+///
+/// Short runs of matching tokens between two edits, like a lone space or
+/// parenthesis, are folded into the edits around them, as in
+/// diff-match-patch's semantic cleanup. If what is left matching is too
+/// little to be worth highlighting, the whole diff becomes a single mutation.
+pub fn cleanup_token_diffs(
+  lhs: &[&[u8]],
+  rhs: &[&[u8]],
+  mut diffs: Vec<DiffItem>,
+) -> Vec<DiffItem> {
+  loop {
+    let absorbed = absorb_equalities(lhs, rhs, &diffs);
+    // Absorbing merges items, so an unchanged length means nothing changed.
+    if absorbed.len() == diffs.len() {
+      break;
+    }
+    diffs = absorbed;
+  }
+  if text_similarity(lhs, rhs, &diffs) < MIN_HIGHLIGHT_SIMILARITY {
+    return vec![Mutation { lhs: 0..lhs.len(), rhs: 0..rhs.len() }];
+  }
+  diffs
+}
+
+/// Turns every match that is no longer than the edits on either side of it
+/// into a mutation.
+fn absorb_equalities(
+  lhs: &[&[u8]],
+  rhs: &[&[u8]],
+  diffs: &[DiffItem],
+) -> Vec<DiffItem> {
+  let edit_size = |d: &DiffItem| match d {
+    Mutation { lhs: l, rhs: r } => {
+      Some(size(&lhs[l.clone()]).max(size(&rhs[r.clone()])))
+    }
+    _ => None,
+  };
+  let mut out = Diffs::default();
+  for (i, d) in diffs.iter().enumerate() {
+    match d {
+      Match { lhs: l, .. } => {
+        let before = i.checked_sub(1).and_then(|p| edit_size(&diffs[p]));
+        let after = diffs.get(i + 1).and_then(edit_size);
+        let len = size(&lhs[l.clone()]);
+        if before.zip(after).is_some_and(|(b, a)| len <= b.min(a)) {
+          out.add_mutation(l.len(), l.len());
+        } else {
+          out.add_match(l.len());
+        }
+      }
+      Mutation { lhs: l, rhs: r } => out.add_mutation(l.len(), r.len()),
+      Moved { .. } => unreachable!(),
+    }
+  }
+  out.vec
+}
+
+/// How alike the two sides are by the non-whitespace text `diffs` matches.
+fn text_similarity(lhs: &[&[u8]], rhs: &[&[u8]], diffs: &[DiffItem]) -> f64 {
+  let text = |tokens: &[&[u8]]| {
+    tokens
+      .iter()
+      .filter(|t| !is_whitespace_token(t))
+      .map(|t| t.len())
+      .sum::<usize>()
+  };
+  let common: usize = diffs
+    .iter()
+    .filter(|d| matches!(d, Match { .. }))
+    .map(|d| text(&lhs[d.lhs()]))
+    .sum();
+  similarity(common, text(lhs) + text(rhs))
+}
+
+fn size(tokens: &[&[u8]]) -> usize {
+  tokens.iter().map(|t| t.len()).sum()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::diff::Algorithm;
+  use crate::diff::diff;
+  use crate::tokens::tokenize_lines;
+
+  fn cleanup(lhs: &[u8], rhs: &[u8]) -> Vec<DiffItem> {
+    let lhs = tokenize_lines(&[lhs]);
+    let rhs = tokenize_lines(&[rhs]);
+    let diffs = diff(&lhs, &rhs, Algorithm::default());
+    cleanup_token_diffs(&lhs, &rhs, diffs)
+  }
+
+  #[test]
+  fn single_edit_is_kept() {
+    assert_eq!(
+      cleanup(b"let v = f(x);", b"let v = f(y);"),
+      &[
+        Match { lhs: 0..8, rhs: 0..8 },
+        Mutation { lhs: 8..9, rhs: 8..9 },
+        Match { lhs: 9..11, rhs: 9..11 },
+      ]
+    );
+  }
+
+  #[test]
+  fn short_equalities_are_absorbed() {
+    // The "(" between "foo" and "1" is folded into one edit.
+    assert_eq!(
+      cleanup(b"a = foo(1, 2) + bar", b"a = qux(3, 2) + bar"),
+      &[
+        Match { lhs: 0..4, rhs: 0..4 },
+        Mutation { lhs: 4..7, rhs: 4..7 },
+        Match { lhs: 7..15, rhs: 7..15 },
+      ]
+    );
+  }
+
+  #[test]
+  fn dissimilar_lines_are_not_token_diffed() {
+    assert_eq!(
+      cleanup(b"foo(a, b)", b"bar(c, d)"),
+      &[Mutation { lhs: 0..7, rhs: 0..7 }]
+    );
+  }
+
+  #[test]
+  fn blank_lines() {
+    assert_eq!(cleanup(b"", b""), &[]);
+    assert_eq!(cleanup(b"  ", b""), &[Mutation { lhs: 0..1, rhs: 0..0 }]);
+  }
+}
@@ -4,12 +4,13 @@ use std::collections::hash_map::DefaultHasher;
 use std::hash::Hasher;
 use std::path::PathBuf;
 
+use crate::diff::Algorithm;
 use crate::diff::DiffItem;
 use crate::diff::diff;
 use crate::files::FilePairEvent;
+use crate::tokens::Intraline;
 use crate::tokens::is_whitespace_token;
 use crate::tokens::split_lines;
-use crate::tokens::tokenize_lines;
 
 /// A content-based signature for a single mutation.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
@@ -19,10 +20,17 @@ pub struct DiffSignature {
 }
 
 impl DiffSignature {
-  pub fn new(lhs_lines: &[&[u8]], rhs_lines: &[&[u8]]) -> DiffSignature {
-    let lhs_tokens = tokenize_lines(lhs_lines);
-    let rhs_tokens = tokenize_lines(rhs_lines);
-    let token_diffs = diff(&lhs_tokens, &rhs_tokens);
+  /// Hashes the tokens changed between `lhs_lines` and `rhs_lines`, split at
+  /// the `intraline` granularity.
+  pub fn new(
+    lhs_lines: &[&[u8]],
+    rhs_lines: &[&[u8]],
+    algorithm: Algorithm,
+    intraline: Intraline,
+  ) -> DiffSignature {
+    let lhs_tokens = intraline.tokenize(lhs_lines, false);
+    let rhs_tokens = intraline.tokenize(rhs_lines, false);
+    let token_diffs = diff(&lhs_tokens, &rhs_tokens, algorithm);
 
     let mut lhs_hasher = DefaultHasher::new();
     let mut rhs_hasher = DefaultHasher::new();
@@ -67,6 +75,8 @@ impl DiffCluster {
   /// Group files into clusters of mutations, sorted by cluster size.
   pub fn cluster(
     events: impl Iterator<Item = FilePairEvent>,
+    algorithm: Algorithm,
+    intraline: Intraline,
   ) -> Vec<DiffCluster> {
     let mut map: HashMap<DiffSignature, DiffCluster> = HashMap::new();
 
@@ -80,13 +90,13 @@ impl DiffCluster {
       {
         let lhs_lines = split_lines(&lhs_content);
         let rhs_lines = split_lines(&rhs_content);
-        let line_diffs = diff(&lhs_lines, &rhs_lines);
+        let line_diffs = diff(&lhs_lines, &rhs_lines, algorithm);
 
         for item in &line_diffs {
           if let DiffItem::Mutation { lhs, rhs } = item {
             let lhs = &lhs_lines[lhs.clone()];
             let rhs = &rhs_lines[rhs.clone()];
-            let sig = DiffSignature::new(lhs, rhs);
+            let sig = DiffSignature::new(lhs, rhs, algorithm, intraline);
             let cluster =
               map.entry(sig.clone()).or_insert_with(|| DiffCluster {
                 signature: sig,
@@ -132,7 +142,12 @@ mod tests {
   fn sig(lhs: &[u8], rhs: &[u8]) -> DiffSignature {
     let lhs_lines = split_lines(lhs);
     let rhs_lines = split_lines(rhs);
-    DiffSignature::new(&lhs_lines, &rhs_lines)
+    DiffSignature::new(
+      &lhs_lines,
+      &rhs_lines,
+      Algorithm::default(),
+      Intraline::default(),
+    )
   }
 
   fn sizes(clusters: &[DiffCluster]) -> Vec<usize> {
@@ -177,7 +192,11 @@ mod tests {
       },
     ];
 
-    let clusters = DiffCluster::cluster(events.into_iter());
+    let clusters = DiffCluster::cluster(
+      events.into_iter(),
+      Algorithm::default(),
+      Intraline::default(),
+    );
     assert_eq!(sizes(&clusters), [2, 1]);
   }
 
@@ -192,7 +211,11 @@ mod tests {
       rhs_content: b"bar\nkeep\nbar\n".to_vec(),
     }];
 
-    let clusters = DiffCluster::cluster(events.into_iter());
+    let clusters = DiffCluster::cluster(
+      events.into_iter(),
+      Algorithm::default(),
+      Intraline::default(),
+    );
     assert_eq!(sizes(&clusters), [2]);
   }
 }
@@ -0,0 +1,264 @@
+use std::borrow::Cow;
+
+use owo_colors::Style;
+use unicode_normalization::char::is_combining_mark;
+use unicode_segmentation::UnicodeSegmentation;
+
+/// A line of text in pieces with their own styles.
+pub(crate) type Spans<'a> = Vec<(Cow<'a, str>, Style)>;
+
+const TAB_WIDTH: usize = 8;
+
+/// Ranges of characters that terminals show two columns wide: East Asian
+/// wide and fullwidth characters, and emoji.
+const WIDE: &[(u32, u32)] = &[
+  (0x1100, 0x115f),
+  (0x231a, 0x231b),
+  (0x2329, 0x232a),
+  (0x23e9, 0x23ec),
+  (0x23f0, 0x23f0),
+  (0x23f3, 0x23f3),
+  (0x25fd, 0x25fe),
+  (0x2614, 0x2615),
+  (0x2648, 0x2653),
+  (0x267f, 0x267f),
+  (0x2693, 0x2693),
+  (0x26a1, 0x26a1),
+  (0x26aa, 0x26ab),
+  (0x26bd, 0x26be),
+  (0x26c4, 0x26c5),
+  (0x26ce, 0x26ce),
+  (0x26d4, 0x26d4),
+  (0x26ea, 0x26ea),
+  (0x26f2, 0x26f3),
+  (0x26f5, 0x26f5),
+  (0x26fa, 0x26fa),
+  (0x26fd, 0x26fd),
+  (0x2705, 0x2705),
+  (0x270a, 0x270b),
+  (0x2728, 0x2728),
+  (0x274c, 0x274c),
+  (0x274e, 0x274e),
+  (0x2753, 0x2755),
+  (0x2757, 0x2757),
+  (0x2795, 0x2797),
+  (0x27b0, 0x27b0),
+  (0x27bf, 0x27bf),
+  (0x2b1b, 0x2b1c),
+  (0x2b50, 0x2b50),
+  (0x2b55, 0x2b55),
+  (0x2e80, 0x303e),
+  (0x3041, 0x33ff),
+  (0x3400, 0x4dbf),
+  (0x4e00, 0x9fff),
+  (0xa000, 0xa4cf),
+  (0xa960, 0xa97f),
+  (0xac00, 0xd7a3),
+  (0xf900, 0xfaff),
+  (0xfe10, 0xfe19),
+  (0xfe30, 0xfe6f),
+  (0xff00, 0xff60),
+  (0xffe0, 0xffe6),
+  (0x16fe0, 0x16fe4),
+  (0x17000, 0x18aff),
+  (0x1b000, 0x1b2ff),
+  (0x1f004, 0x1f004),
+  (0x1f0cf, 0x1f0cf),
+  (0x1f18e, 0x1f18e),
+  (0x1f191, 0x1f19a),
+  (0x1f200, 0x1f251),
+  (0x1f300, 0x1f64f),
+  (0x1f680, 0x1f6ff),
+  (0x1f7e0, 0x1f7eb),
+  (0x1f900, 0x1f9ff),
+  (0x1fa70, 0x1faff),
+  (0x20000, 0x2fffd),
+  (0x30000, 0x3fffd),
+];
+
+/// How many terminal columns `c` takes up on its own.
+fn char_width(c: char) -> usize {
+  let zero_width = is_combining_mark(c)
+    || matches!(
+      c,
+      '\u{200b}'..='\u{200f}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{feff}'
+        | '\u{1160}'..='\u{11ff}'
+    );
+  if zero_width {
+    return 0;
+  }
+  let c = c as u32;
+  let wide = WIDE
+    .binary_search_by(|&(lo, hi)| {
+      if hi < c {
+        std::cmp::Ordering::Less
+      } else if lo > c {
+        std::cmp::Ordering::Greater
+      } else {
+        std::cmp::Ordering::Equal
+      }
+    })
+    .is_ok();
+  if wide { 2 } else { 1 }
+}
+
+/// How many terminal columns a grapheme cluster takes up.
+fn grapheme_width(g: &str) -> usize {
+  // An emoji presentation selector turns text symbols into wide emoji.
+  if g.contains('\u{fe0f}') {
+    return 2;
+  }
+  g.chars().next().map_or(0, char_width)
+}
+
+/// Lays `line` out in rows exactly `width` columns wide, expanding tabs. Long
+/// lines are wrapped onto more rows, or if `wrap` is false, cut short with
+/// an ellipsis.
+pub(crate) fn layout<'a>(
+  line: &Spans<'a>,
+  width: usize,
+  wrap: bool,
+) -> Vec<Spans<'a>> {
+  let width = width.max(2);
+  let mut cells = vec![];
+  let mut column = 0;
+  for (text, style) in line {
+    for g in text.graphemes(true) {
+      let (g, w, tab): (Cow<str>, usize, bool) = match g {
+        "\t" => {
+          let w = (TAB_WIDTH - column % TAB_WIDTH).min(width);
+          (" ".repeat(w).into(), w, true)
+        }
+        // Control characters would break the columns, or worse.
+        g if g.chars().any(|c| c.is_control()) => ("\u{fffd}".into(), 1, false),
+        g => (g.to_owned().into(), grapheme_width(g), false),
+      };
+      column += w;
+      cells.push((g, w, tab, *style));
+    }
+  }
+
+  let mut rows = vec![];
+  let mut row: Spans = vec![];
+  let mut row_width = 0;
+  let fits = column <= width;
+  // Leave room for the ellipsis unless everything fits.
+  let room = if wrap || fits { width } else { width - 1 };
+  for (mut g, mut w, tab, style) in cells {
+    if row_width + w > room {
+      if tab && row_width < room {
+        // Tabs that don't fit just fill the rest of the row.
+        w = room - row_width;
+        g = " ".repeat(w).into();
+      } else if !wrap {
+        row.push(("…".into(), style));
+        row_width += 1;
+        break;
+      } else if !row.is_empty() {
+        pad(&mut row, width - row_width);
+        rows.push(std::mem::take(&mut row));
+        row_width = 0;
+      }
+    }
+    push(&mut row, g, style);
+    row_width += w;
+  }
+  pad(&mut row, width - row_width);
+  rows.push(row);
+  rows
+}
+
+/// Appends `text` to `row`, merging it into the last span if it has the
+/// same style.
+fn push<'a>(row: &mut Spans<'a>, text: Cow<'a, str>, style: Style) {
+  match row.last_mut() {
+    Some((last, last_style)) if *last_style == style => {
+      last.to_mut().push_str(&text)
+    }
+    _ => row.push((text, style)),
+  }
+}
+
+fn pad(row: &mut Spans, n: usize) {
+  if n > 0 {
+    row.push((" ".repeat(n).into(), Style::new()));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn texts(rows: &[Spans]) -> Vec<String> {
+    rows
+      .iter()
+      .map(|r| r.iter().map(|(t, _)| t.as_ref()).collect())
+      .collect()
+  }
+
+  fn str_width(s: &str) -> usize {
+    s.graphemes(true).map(grapheme_width).sum()
+  }
+
+  fn plain(s: &str) -> Spans<'_> {
+    vec![(s.into(), Style::new())]
+  }
+
+  #[test]
+  fn widths() {
+    assert_eq!(str_width("abc"), 3);
+    assert_eq!(str_width("日本語"), 6);
+    assert_eq!(str_width("ｈｉ"), 4);
+    assert_eq!(str_width("e\u{301}"), 1);
+    assert_eq!(str_width("👍🏽!"), 3);
+    assert_eq!(str_width("\u{2764}\u{fe0f}"), 2);
+    assert_eq!(str_width("한국"), 4);
+  }
+
+  #[test]
+  fn pads_short_lines() {
+    assert_eq!(texts(&layout(&plain("ab"), 5, false)), &["ab   "]);
+    assert_eq!(texts(&layout(&plain(""), 3, true)), &["   "]);
+  }
+
+  #[test]
+  fn truncates() {
+    assert_eq!(texts(&layout(&plain("abcdefgh"), 5, false)), &["abcd…"]);
+    assert_eq!(texts(&layout(&plain("abcde"), 5, false)), &["abcde"]);
+    // A wide character that doesn't fit leaves a gap.
+    assert_eq!(texts(&layout(&plain("abc日本"), 5, false)), &["abc… "]);
+  }
+
+  #[test]
+  fn wraps() {
+    assert_eq!(
+      texts(&layout(&plain("abcdefgh"), 3, true)),
+      &["abc", "def", "gh "]
+    );
+    assert_eq!(texts(&layout(&plain("a日本語"), 4, true)), &["a日 ", "本語"]);
+    // Tabs never take up more than a row.
+    assert_eq!(texts(&layout(&plain("a\tb"), 4, true)), &["a   ", "b   "]);
+    assert_eq!(texts(&layout(&plain("\tb"), 3, true)), &["   ", "b  "]);
+  }
+
+  #[test]
+  fn tabs_and_controls() {
+    assert_eq!(texts(&layout(&plain("a\tb"), 10, false)), &["a       b "]);
+    assert_eq!(texts(&layout(&plain("\tb"), 3, false)), &["  …"]);
+    assert_eq!(
+      texts(&layout(&plain("a\x1b[0m"), 6, false)),
+      &["a\u{fffd}[0m "]
+    );
+  }
+
+  #[test]
+  fn keeps_styles() {
+    let bold = Style::new().bold();
+    let line = vec![("ab".into(), Style::new()), ("cd".into(), bold)];
+    let rows = layout(&line, 3, true);
+    assert_eq!(rows[0], vec![("ab".into(), Style::new()), ("c".into(), bold)]);
+    assert_eq!(rows[1], vec![("d".into(), bold), ("  ".into(), Style::new())]);
+  }
+}
@@ -1,6 +1,38 @@
 use std::collections::HashMap;
+use std::hash::Hash;
 use std::iter::zip;
 use std::ops::Range;
+use std::time::Duration;
+use std::time::Instant;
+
+use crate::anchor::Anchor;
+use crate::anchor::anchored_pairs;
+
+/// The strategy used to line up the two sides of a diff.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Algorithm {
+  /// Anchor on lines that occur equally often on both sides, falling back to
+  /// Myers for regions without any such lines.
+  #[default]
+  Patience,
+  /// Anchor on runs of the least frequent lines on the old side.
+  Histogram,
+  /// Myers' O(ND) algorithm, giving up on minimality for very costly inputs.
+  Myers,
+  /// Myers' O(ND) algorithm, always producing a minimal diff.
+  Minimal,
+}
+
+impl Algorithm {
+  /// Every algorithm, for trying each in turn.
+  pub const ALL: [Algorithm; 4] = [
+    Algorithm::Patience,
+    Algorithm::Histogram,
+    Algorithm::Myers,
+    Algorithm::Minimal,
+  ];
+}
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Side {
@@ -10,8 +42,22 @@ pub enum Side {
 
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum DiffItem {
-  Match { lhs: Range<usize>, rhs: Range<usize> },
-  Mutation { lhs: Range<usize>, rhs: Range<usize> },
+  Match {
+    lhs: Range<usize>,
+    rhs: Range<usize>,
+  },
+  Mutation {
+    lhs: Range<usize>,
+    rhs: Range<usize>,
+  },
+  /// A block of lines deleted on one side and inserted elsewhere on the other.
+  /// Exactly one of `lhs` and `rhs` is non-empty and `counterpart` is where the
+  /// block sits on the other side.
+  Moved {
+    lhs: Range<usize>,
+    rhs: Range<usize>,
+    counterpart: Range<usize>,
+  },
 }
 
 use DiffItem::*;
@@ -21,6 +67,7 @@ impl DiffItem {
     match self {
       Match { lhs, .. } => lhs.clone(),
       Mutation { lhs, .. } => lhs.clone(),
+      Moved { lhs, .. } => lhs.clone(),
     }
   }
 
@@ -28,6 +75,7 @@ impl DiffItem {
     match self {
       Match { rhs, .. } => rhs.clone(),
       Mutation { rhs, .. } => rhs.clone(),
+      Moved { rhs, .. } => rhs.clone(),
     }
   }
 
@@ -40,12 +88,12 @@ impl DiffItem {
 }
 
 #[derive(Clone, Debug, Default, Eq, PartialEq)]
-struct Diffs {
-  vec: Vec<DiffItem>,
+pub(crate) struct Diffs {
+  pub(crate) vec: Vec<DiffItem>,
 }
 
 impl Diffs {
-  fn add_match(&mut self, len: usize) {
+  pub(crate) fn add_match(&mut self, len: usize) {
     if len == 0 {
       return;
     }
@@ -66,7 +114,7 @@ impl Diffs {
     }
   }
 
-  fn add_mutation(&mut self, lhs: usize, rhs: usize) {
+  pub(crate) fn add_mutation(&mut self, lhs: usize, rhs: usize) {
     if lhs == 0 && rhs == 0 {
       return;
     }
@@ -87,6 +135,25 @@ impl Diffs {
     }
   }
 
+  pub(crate) fn add_moved(
+    &mut self,
+    lhs: usize,
+    rhs: usize,
+    counterpart: Range<usize>,
+  ) {
+    self.vec.push(Moved {
+      lhs: Range {
+        start: self.lhs_pos(),
+        end: self.lhs_pos() + lhs,
+      },
+      rhs: Range {
+        start: self.rhs_pos(),
+        end: self.rhs_pos() + rhs,
+      },
+      counterpart,
+    });
+  }
+
   fn lhs_pos(&self) -> usize {
     self.vec.last().map_or(0, |d| d.lhs().end)
   }
@@ -105,61 +172,241 @@ impl Diffs {
 // 3. Find all lines which occur exactly once on both sides, then do longest
 //    common subsequence on those lines, matching them up.
 // 4. Do steps 1-2 on each section between matched lines.
+// 5. If a section has no lines with matching occurrence counts, hand it to
+//    Myers.
+
+/// Diffs two sequences of lines.
+pub fn diff(
+  lhs: &[&[u8]],
+  rhs: &[&[u8]],
+  algorithm: Algorithm,
+) -> Vec<DiffItem> {
+  diff_seq(lhs, rhs, algorithm)
+}
+
+/// Diffs two sequences of arbitrary elements, comparing them with `Eq`.
+pub fn diff_seq<T: Hash + Eq>(
+  lhs: &[T],
+  rhs: &[T],
+  algorithm: Algorithm,
+) -> Vec<DiffItem> {
+  diff_seq_with_budget(lhs, rhs, algorithm, &mut Budget::default())
+}
+
+/// Like `diff_seq`, but stops looking for a good diff once `budget` is spent.
+///
+/// The result is always a valid diff. Check `Budget::exhausted` afterwards to
+/// find out whether it may be coarser than usual.
+pub fn diff_seq_with_budget<T: Hash + Eq>(
+  lhs: &[T],
+  rhs: &[T],
+  algorithm: Algorithm,
+  budget: &mut Budget,
+) -> Vec<DiffItem> {
+  diff_seq_pinned(lhs, rhs, algorithm, &[], budget)
+}
 
-pub fn diff(lhs: &[&[u8]], rhs: &[&[u8]]) -> Vec<DiffItem> {
+/// Diffs two sequences of lines, always matching up the lines `anchors` pick.
+pub fn diff_anchored(
+  lhs: &[&[u8]],
+  rhs: &[&[u8]],
+  algorithm: Algorithm,
+  anchors: &[Anchor],
+) -> Vec<DiffItem> {
+  let pins = anchored_pairs(lhs, rhs, anchors);
+  diff_seq_pinned(lhs, rhs, algorithm, &pins, &mut Budget::default())
+}
+
+/// The most general form of `diff_seq`: every `(lhs, rhs)` pair in `pins` is
+/// matched up, and the regions in between are diffed within `budget`.
+///
+/// Pins must be strictly increasing on both sides. Pins pairing unequal
+/// elements, or out of order with the ones before them, are ignored.
+pub fn diff_seq_pinned<T: Hash + Eq>(
+  lhs: &[T],
+  rhs: &[T],
+  algorithm: Algorithm,
+  pins: &[(usize, usize)],
+  budget: &mut Budget,
+) -> Vec<DiffItem> {
   let mut d = Diffs::default();
-  accumulate_partitions(&mut d, lhs, rhs);
+  let region = |l: Range<usize>, r: Range<usize>| match algorithm {
+    Algorithm::Patience => Task::Anchors(l, r),
+    Algorithm::Histogram => Task::Histogram(l, r),
+    Algorithm::Myers | Algorithm::Minimal => Task::Myers(l, r),
+  };
+  let mut tasks = vec![];
+  let (mut lhs_pos, mut rhs_pos) = (0, 0);
+  for &(l, r) in pins {
+    if l < lhs_pos || r < rhs_pos || l >= lhs.len() || r >= rhs.len() {
+      continue;
+    }
+    if lhs[l] != rhs[r] {
+      continue;
+    }
+    tasks.push(region(lhs_pos..l, rhs_pos..r));
+    tasks.push(Task::Match(1));
+    (lhs_pos, rhs_pos) = (l + 1, r + 1);
+  }
+  tasks.push(region(lhs_pos..lhs.len(), rhs_pos..rhs.len()));
+  let mut stack: Vec<_> = tasks.into_iter().rev().collect();
+  let heuristic = algorithm != Algorithm::Minimal;
+  while let Some(task) = stack.pop() {
+    let (l, r) = match &task {
+      Task::Match(len) => {
+        d.add_match(*len);
+        continue;
+      }
+      Task::Patience(l, r)
+      | Task::Anchors(l, r)
+      | Task::Histogram(l, r)
+      | Task::Myers(l, r) => (l.clone(), r.clone()),
+    };
+    if !budget.spend(l.len() + r.len()) {
+      let (l, r, trailing) = strip_common(&mut d, lhs, rhs, l, r);
+      d.add_mutation(l.len(), r.len());
+      d.add_match(trailing);
+      continue;
+    }
+    match task {
+      Task::Patience(..) => patience_step(&mut stack, &mut d, lhs, rhs, l, r),
+      Task::Anchors(..) => anchors_step(&mut stack, lhs, rhs, l, r),
+      Task::Histogram(..) => histogram_step(&mut stack, &mut d, lhs, rhs, l, r),
+      Task::Myers(..) => {
+        myers_step(&mut stack, &mut d, lhs, rhs, l, r, heuristic, budget)
+      }
+      Task::Match(_) => unreachable!(),
+    }
+  }
   d.vec
 }
 
-fn accumulate_diffs(diffs: &mut Diffs, lhs: &[&[u8]], rhs: &[&[u8]]) {
-  let leading = leading_match_len(lhs, rhs);
-  diffs.add_match(leading);
-  if leading == lhs.len() && leading == rhs.len() {
-    return;
+/// Limits how much work a diff may do.
+///
+/// Once the budget is spent, regions that still need diffing are only
+/// stripped of common leading and trailing elements and the rest is reported
+/// as a single mutation. Unlimited by default.
+#[derive(Clone, Debug, Default)]
+pub struct Budget {
+  max_cost: Option<u64>,
+  deadline: Option<Instant>,
+  spent: u64,
+  exhausted: bool,
+}
+
+impl Budget {
+  /// Allows roughly `max_cost` element comparisons, and stops once `timeout`
+  /// has passed since the budget was created.
+  pub fn new(max_cost: Option<u64>, timeout: Option<Duration>) -> Budget {
+    Budget {
+      max_cost,
+      deadline: timeout.map(|t| Instant::now() + t),
+      ..Default::default()
+    }
   }
 
-  let trailing =
-    trailing_match_len(&lhs[leading..lhs.len()], &rhs[leading..rhs.len()]);
-  accumulate_partitions(
-    diffs,
-    &lhs[leading..lhs.len() - trailing],
-    &rhs[leading..rhs.len() - trailing],
-  );
-  diffs.add_match(trailing);
+  /// Whether a diff ran out of budget and may not be minimal.
+  pub fn exhausted(&self) -> bool {
+    self.exhausted
+  }
+
+  /// Records `cost` units of work, returning whether there was budget left.
+  pub(crate) fn spend(&mut self, cost: usize) -> bool {
+    if self.exhausted {
+      return false;
+    }
+    self.spent = self.spent.saturating_add(cost as u64);
+    self.exhausted = self.max_cost.is_some_and(|max| self.spent > max)
+      || self.deadline.is_some_and(|d| Instant::now() >= d);
+    !self.exhausted
+  }
 }
 
-fn leading_match_len(lhs: &[&[u8]], rhs: &[&[u8]]) -> usize {
+/// Pending work. The algorithms below push these onto a stack instead of
+/// recursing, so deeply nested inputs can not overflow the call stack. Tasks
+/// are popped in output order.
+enum Task {
+  /// Strip common lines, then diff the rest with `Anchors`.
+  Patience(Range<usize>, Range<usize>),
+  /// Split a region on its patience anchors.
+  Anchors(Range<usize>, Range<usize>),
+  Histogram(Range<usize>, Range<usize>),
+  Myers(Range<usize>, Range<usize>),
+  /// Emit matching lines.
+  Match(usize),
+}
+
+/// Adds the common leading lines of `lhs[l]` and `rhs[r]` to `diffs` and
+/// returns what is left in between, along with the common trailing length.
+fn strip_common<T: Eq>(
+  diffs: &mut Diffs,
+  lhs: &[T],
+  rhs: &[T],
+  l: Range<usize>,
+  r: Range<usize>,
+) -> (Range<usize>, Range<usize>, usize) {
+  let leading = leading_match_len(&lhs[l.clone()], &rhs[r.clone()]);
+  diffs.add_match(leading);
+  let (l, r) = (l.start + leading..l.end, r.start + leading..r.end);
+  let trailing = trailing_match_len(&lhs[l.clone()], &rhs[r.clone()]);
+  (l.start..l.end - trailing, r.start..r.end - trailing, trailing)
+}
+
+fn leading_match_len<T: Eq>(lhs: &[T], rhs: &[T]) -> usize {
   zip(lhs, rhs).take_while(|(l, r)| l == r).count()
 }
 
-fn trailing_match_len(lhs: &[&[u8]], rhs: &[&[u8]]) -> usize {
+fn trailing_match_len<T: Eq>(lhs: &[T], rhs: &[T]) -> usize {
   zip(lhs.iter().rev(), rhs.iter().rev())
     .take_while(|(l, r)| l == r)
     .count()
 }
 
-fn accumulate_partitions(diffs: &mut Diffs, lhs: &[&[u8]], rhs: &[&[u8]]) {
-  let matched = match_lines(lhs, rhs);
+fn patience_step<T: Eq>(
+  stack: &mut Vec<Task>,
+  diffs: &mut Diffs,
+  lhs: &[T],
+  rhs: &[T],
+  l: Range<usize>,
+  r: Range<usize>,
+) {
+  let (l, r, trailing) = strip_common(diffs, lhs, rhs, l, r);
+  stack.push(Task::Match(trailing));
+  if !l.is_empty() || !r.is_empty() {
+    stack.push(Task::Anchors(l, r));
+  }
+}
+
+fn anchors_step<T: Hash + Eq>(
+  stack: &mut Vec<Task>,
+  lhs: &[T],
+  rhs: &[T],
+  l: Range<usize>,
+  r: Range<usize>,
+) {
+  let matched = match_lines(&lhs[l.clone()], &rhs[r.clone()]);
   if matched.is_empty() {
-    diffs.add_mutation(lhs.len(), rhs.len());
+    stack.push(Task::Myers(l, r));
     return;
   }
   let matched = longest_common_subseq(&matched);
 
-  let mut lhs_pos: usize = 0;
-  let mut rhs_pos: usize = 0;
+  let mut tasks = vec![];
+  let mut lhs_pos = l.start;
+  let mut rhs_pos = r.start;
   for (lhs_next, rhs_next) in matched {
-    accumulate_diffs(diffs, &lhs[lhs_pos..lhs_next], &rhs[rhs_pos..rhs_next]);
-    diffs.add_match(1);
+    let (lhs_next, rhs_next) = (l.start + lhs_next, r.start + rhs_next);
+    tasks.push(Task::Patience(lhs_pos..lhs_next, rhs_pos..rhs_next));
+    tasks.push(Task::Match(1));
     lhs_pos = lhs_next + 1;
     rhs_pos = rhs_next + 1;
   }
-  accumulate_diffs(diffs, &lhs[lhs_pos..lhs.len()], &rhs[rhs_pos..rhs.len()]);
+  tasks.push(Task::Patience(lhs_pos..l.end, rhs_pos..r.end));
+  stack.extend(tasks.into_iter().rev());
 }
 
-fn match_lines(lhs: &[&[u8]], rhs: &[&[u8]]) -> Vec<(usize, usize)> {
-  let mut m: HashMap<&[u8], (Vec<usize>, Vec<usize>)> = HashMap::new();
+fn match_lines<T: Hash + Eq>(lhs: &[T], rhs: &[T]) -> Vec<(usize, usize)> {
+  let mut m: HashMap<&T, (Vec<usize>, Vec<usize>)> = HashMap::new();
   for (i, l) in lhs.iter().enumerate() {
     m.entry(l).or_default().0.push(i);
   }
@@ -186,15 +433,14 @@ fn match_lines(lhs: &[&[u8]], rhs: &[&[u8]]) -> Vec<(usize, usize)> {
   v
 }
 
-fn longest_common_subseq(pairings: &[(usize, usize)]) -> Vec<(usize, usize)> {
+pub(crate) fn longest_common_subseq(
+  pairings: &[(usize, usize)],
+) -> Vec<(usize, usize)> {
   type PairingStack = Vec<Vec<((usize, usize), usize)>>;
+  // The rhs positions on top of the stacks are always increasing from left to
+  // right, so the target stack can be found with a binary search.
   fn find_push_pos(stacks: &PairingStack, p: &(usize, usize)) -> usize {
-    for (pos, stack) in stacks.iter().enumerate() {
-      if p.1 < stack.last().unwrap().0.1 {
-        return pos;
-      }
-    }
-    stacks.len()
+    stacks.partition_point(|stack| stack.last().unwrap().0.1 <= p.1)
   }
 
   let mut stacks = PairingStack::new();
@@ -217,6 +463,228 @@ fn longest_common_subseq(pairings: &[(usize, usize)]) -> Vec<(usize, usize)> {
   r
 }
 
+// --- Histogram Diff algorithm ---
+//
+// 1. Strip common leading and trailing lines.
+// 2. Count how often each line occurs on the lhs.
+// 3. For every rhs line that also occurs on the lhs, extend the pairing into
+//    the longest run of matching lines around it. Keep the run whose rarest
+//    line is the rarest overall, preferring longer runs on ties.
+// 4. Recurse on the sections before and after that run. Sections without any
+//    common lines are handed to Myers.
+
+/// Lines occurring more often than this on the lhs are never used as anchors.
+const MAX_HISTOGRAM_CHAIN: usize = 64;
+
+fn histogram_step<T: Hash + Eq>(
+  stack: &mut Vec<Task>,
+  diffs: &mut Diffs,
+  lhs: &[T],
+  rhs: &[T],
+  l: Range<usize>,
+  r: Range<usize>,
+) {
+  let (l, r, trailing) = strip_common(diffs, lhs, rhs, l, r);
+  stack.push(Task::Match(trailing));
+  if let Some((a, b, len)) = histogram_run(&lhs[l.clone()], &rhs[r.clone()]) {
+    let (a, b) = (l.start + a, r.start + b);
+    stack.push(Task::Histogram(a + len..l.end, b + len..r.end));
+    stack.push(Task::Match(len));
+    stack.push(Task::Histogram(l.start..a, r.start..b));
+  } else {
+    stack.push(Task::Myers(l, r));
+  }
+}
+
+/// Finds the best anchoring run as `(lhs_start, rhs_start, len)`.
+fn histogram_run<T: Hash + Eq>(
+  lhs: &[T],
+  rhs: &[T],
+) -> Option<(usize, usize, usize)> {
+  let mut occurrences: HashMap<&T, Vec<usize>> = HashMap::new();
+  for (i, l) in lhs.iter().enumerate() {
+    occurrences.entry(l).or_default().push(i);
+  }
+  let count = |l: &T| occurrences.get(l).map_or(0, Vec::len);
+
+  let mut best: Option<(usize, usize, usize)> = None;
+  let mut best_count = MAX_HISTOGRAM_CHAIN;
+  let mut r = 0;
+  while r < rhs.len() {
+    let mut next = r + 1;
+    let Some(positions) = occurrences.get(&rhs[r]) else {
+      r = next;
+      continue;
+    };
+    if positions.len() > best_count {
+      r = next;
+      continue;
+    }
+    for &l in positions {
+      let before = zip(lhs[..l].iter().rev(), rhs[..r].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+      let after = zip(&lhs[l..], &rhs[r..])
+        .take_while(|(a, b)| a == b)
+        .count();
+      let (start_l, start_r) = (l - before, r - before);
+      let len = before + after;
+      next = next.max(start_r + len);
+
+      let run_count =
+        lhs[start_l..start_l + len].iter().map(count).min().unwrap();
+      let best_len = best.map_or(0, |b| b.2);
+      if run_count < best_count || (run_count == best_count && len > best_len) {
+        best = Some((start_l, start_r, len));
+        best_count = run_count;
+      }
+    }
+    r = next;
+  }
+  best
+}
+
+// --- Myers Diff algorithm ---
+//
+// Eugene W. Myers, "An O(ND) Difference Algorithm and Its Variations" (1986).
+// Searches forwards from the start and backwards from the end at the same time
+// until the two paths overlap, then recurses on both halves of that "middle
+// snake". Uses linear space.
+//
+// When `heuristic` is set and the edit distance grows past roughly the square
+// root of the input size, the search stops and splits at the furthest point
+// reached so far. The result is still a valid diff, but no longer minimal.
+
+/// The smallest edit cost at which the Myers heuristic may kick in.
+const MIN_MYERS_COST_LIMIT: usize = 256;
+
+#[allow(clippy::too_many_arguments)]
+fn myers_step<T: Eq>(
+  stack: &mut Vec<Task>,
+  diffs: &mut Diffs,
+  lhs: &[T],
+  rhs: &[T],
+  l: Range<usize>,
+  r: Range<usize>,
+  heuristic: bool,
+  budget: &mut Budget,
+) {
+  let (l, r, trailing) = strip_common(diffs, lhs, rhs, l, r);
+  stack.push(Task::Match(trailing));
+  let (lhs, rhs) = (&lhs[l.clone()], &rhs[r.clone()]);
+  if let Some((a, b)) = middle_snake(lhs, rhs, heuristic, budget) {
+    let (a, b) = (l.start + a, r.start + b);
+    stack.push(Task::Myers(a..l.end, b..r.end));
+    stack.push(Task::Myers(l.start..a, r.start..b));
+  } else {
+    diffs.add_mutation(lhs.len(), rhs.len());
+  }
+}
+
+/// Finds a point `(l, r)` on an optimal edit path that lies strictly inside
+/// the edit graph, or `None` if there is no such point worth splitting on.
+/// Running out of `budget` is treated like hitting the heuristic cost limit.
+///
+/// Assumes that `lhs` and `rhs` share no common prefix or suffix.
+fn middle_snake<T: Eq>(
+  lhs: &[T],
+  rhs: &[T],
+  heuristic: bool,
+  budget: &mut Budget,
+) -> Option<(usize, usize)> {
+  if lhs.is_empty() || rhs.is_empty() {
+    return None;
+  }
+  let (n, m) = (lhs.len() as isize, rhs.len() as isize);
+  let max_d = (n + m + 1) / 2;
+  let offset = max_d + 1;
+  let cost_limit = if heuristic {
+    ((n + m + 3) as f64).sqrt().max(MIN_MYERS_COST_LIMIT as f64) as isize
+  } else {
+    isize::MAX
+  };
+
+  // `fwd[k]` is the furthest lhs position reached on diagonal `k = l - r`
+  // searching from the start; `bwd[k]` the same measured from the end.
+  let mut fwd = vec![-1; 2 * offset as usize + 1];
+  let mut bwd = vec![-1; 2 * offset as usize + 1];
+  fwd[offset as usize + 1] = 0;
+  bwd[offset as usize + 1] = 0;
+  let idx = |k: isize| (offset + k) as usize;
+
+  let delta = n - m;
+  let odd = delta % 2 != 0;
+  let split = |l: isize, r: isize| {
+    ((l > 0 || r > 0) && (l < n || r < m)).then_some((l as usize, r as usize))
+  };
+
+  // Diagonals that have run off the edit graph are trimmed from the search.
+  let (mut fwd_lo, mut fwd_hi, mut bwd_lo, mut bwd_hi) = (0, 0, 0, 0);
+  for d in 0..max_d {
+    if d > cost_limit || !budget.spend(2 * d as usize + 1) {
+      return (-d + fwd_lo..=d - fwd_hi)
+        .step_by(2)
+        .map(|k| (fwd[idx(k)], fwd[idx(k)] - k))
+        .filter(|&(l, r)| l >= 0 && r >= 0 && l <= n && r <= m)
+        .max_by_key(|&(l, r)| l + r)
+        .and_then(|(l, r)| split(l, r));
+    }
+
+    for k in (-d + fwd_lo..=d - fwd_hi).step_by(2) {
+      let mut l = if k == -d || (k != d && fwd[idx(k - 1)] < fwd[idx(k + 1)]) {
+        fwd[idx(k + 1)]
+      } else {
+        fwd[idx(k - 1)] + 1
+      };
+      let mut r = l - k;
+      while l < n && r < m && lhs[l as usize] == rhs[r as usize] {
+        l += 1;
+        r += 1;
+      }
+      fwd[idx(k)] = l;
+      if l > n {
+        fwd_hi += 2;
+      } else if r > m {
+        fwd_lo += 2;
+      } else if odd {
+        let bk = delta - k;
+        if bk.abs() <= offset && bwd[idx(bk)] != -1 && l >= n - bwd[idx(bk)] {
+          return split(l, r);
+        }
+      }
+    }
+
+    for k in (-d + bwd_lo..=d - bwd_hi).step_by(2) {
+      let mut l = if k == -d || (k != d && bwd[idx(k - 1)] < bwd[idx(k + 1)]) {
+        bwd[idx(k + 1)]
+      } else {
+        bwd[idx(k - 1)] + 1
+      };
+      let mut r = l - k;
+      while l < n
+        && r < m
+        && lhs[(n - l - 1) as usize] == rhs[(m - r - 1) as usize]
+      {
+        l += 1;
+        r += 1;
+      }
+      bwd[idx(k)] = l;
+      if l > n {
+        bwd_hi += 2;
+      } else if r > m {
+        bwd_lo += 2;
+      } else if !odd {
+        let fk = delta - k;
+        if fk.abs() <= offset && fwd[idx(fk)] != -1 && fwd[idx(fk)] >= n - l {
+          let fl = fwd[idx(fk)];
+          return split(fl, fl - fk);
+        }
+      }
+    }
+  }
+  None
+}
+
 #[cfg(test)]
 mod tests {
   use std::ops::Range;
@@ -227,18 +695,18 @@ mod tests {
   fn diff_lines(lhs: &[u8], rhs: &[u8]) -> Vec<DiffItem> {
     let lhs_lines: Vec<_> = split_lines(lhs);
     let rhs_lines: Vec<_> = split_lines(rhs);
-    diff(&lhs_lines, &rhs_lines)
+    diff(&lhs_lines, &rhs_lines, Algorithm::Patience)
   }
 
   #[test]
   fn diff_empty() {
-    assert_eq!(diff(&[] as &[&[u8]], &[]), &[]);
+    assert_eq!(diff(&[] as &[&[u8]], &[], Algorithm::Patience), &[]);
   }
 
   #[test]
   fn diff_eq() {
     assert_eq!(
-      diff(&[b"a", b"b", b"c"], &[b"a", b"b", b"c"]),
+      diff(&[b"a", b"b", b"c"], &[b"a", b"b", b"c"], Algorithm::Patience),
       &[Match {
         lhs: Range { start: 0, end: 3 },
         rhs: Range { start: 0, end: 3 },
@@ -249,7 +717,7 @@ mod tests {
   #[test]
   fn diff_ne() {
     assert_eq!(
-      diff(&[b"a", b"b", b"c"], &[b"a", b"c"]),
+      diff(&[b"a", b"b", b"c"], &[b"a", b"c"], Algorithm::Patience),
       &[
         Match {
           lhs: Range { start: 0, end: 1 },
@@ -266,7 +734,7 @@ mod tests {
       ]
     );
     assert_eq!(
-      diff(&[b"z", b"a", b"b", b"c"], &[b"a", b"c"]),
+      diff(&[b"z", b"a", b"b", b"c"], &[b"a", b"c"], Algorithm::Patience),
       &[
         Mutation {
           lhs: Range { start: 0, end: 1 },
@@ -287,7 +755,11 @@ mod tests {
       ]
     );
     assert_eq!(
-      diff(&[b"z", b"a", b"e", b"b", b"c"], &[b"a", b"e", b"c"]),
+      diff(
+        &[b"z", b"a", b"e", b"b", b"c"],
+        &[b"a", b"e", b"c"],
+        Algorithm::Patience
+      ),
       &[
         Mutation {
           lhs: Range { start: 0, end: 1 },
@@ -312,7 +784,7 @@ mod tests {
   #[test]
   fn diff_only_non_unique() {
     assert_eq!(
-      diff(&[b"a", b"b", b"b", b"c"], &[b"b", b"b"]),
+      diff(&[b"a", b"b", b"b", b"c"], &[b"b", b"b"], Algorithm::Patience),
       &[
         Mutation {
           lhs: Range { start: 0, end: 1 },
@@ -330,6 +802,330 @@ mod tests {
     );
   }
 
+  #[test]
+  fn diff_seq_ids() {
+    assert_eq!(
+      diff_seq(&[1, 2, 3, 4], &[1, 3, 4, 5], Algorithm::Patience),
+      &[
+        Match {
+          lhs: Range { start: 0, end: 1 },
+          rhs: Range { start: 0, end: 1 },
+        },
+        Mutation {
+          lhs: Range { start: 1, end: 2 },
+          rhs: Range { start: 1, end: 1 },
+        },
+        Match {
+          lhs: Range { start: 2, end: 4 },
+          rhs: Range { start: 1, end: 3 },
+        },
+        Mutation {
+          lhs: Range { start: 4, end: 4 },
+          rhs: Range { start: 3, end: 4 },
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn diff_seq_records() {
+    #[derive(Debug, Eq, Hash, PartialEq)]
+    struct Row {
+      id: u32,
+      name: &'static str,
+    }
+    let row = |id, name| Row { id, name };
+    let lhs = [row(1, "ada"), row(2, "bob"), row(3, "cy")];
+    let rhs = [row(1, "ada"), row(2, "rob"), row(3, "cy")];
+    for algorithm in Algorithm::ALL {
+      assert_eq!(
+        diff_seq(&lhs, &rhs, algorithm),
+        &[
+          Match {
+            lhs: Range { start: 0, end: 1 },
+            rhs: Range { start: 0, end: 1 },
+          },
+          Mutation {
+            lhs: Range { start: 1, end: 2 },
+            rhs: Range { start: 1, end: 2 },
+          },
+          Match {
+            lhs: Range { start: 2, end: 3 },
+            rhs: Range { start: 2, end: 3 },
+          },
+        ]
+      );
+    }
+  }
+
+  #[test]
+  fn diff_patience_falls_back_to_myers() {
+    assert_eq!(
+      diff(&[b"x", b"}", b"}", b"y"], &[b"z", b"}", b"w"], Algorithm::Patience),
+      &[
+        Mutation {
+          lhs: Range { start: 0, end: 1 },
+          rhs: Range { start: 0, end: 1 },
+        },
+        Match {
+          lhs: Range { start: 1, end: 2 },
+          rhs: Range { start: 1, end: 2 },
+        },
+        Mutation {
+          lhs: Range { start: 2, end: 4 },
+          rhs: Range { start: 2, end: 3 },
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn diff_histogram() {
+    assert_eq!(
+      diff(
+        &[b"a", b"}", b"b", b"}", b"c"],
+        &[b"d", b"b", b"}", b"e"],
+        Algorithm::Histogram
+      ),
+      &[
+        Mutation {
+          lhs: Range { start: 0, end: 2 },
+          rhs: Range { start: 0, end: 1 },
+        },
+        Match {
+          lhs: Range { start: 2, end: 4 },
+          rhs: Range { start: 1, end: 3 },
+        },
+        Mutation {
+          lhs: Range { start: 4, end: 5 },
+          rhs: Range { start: 3, end: 4 },
+        },
+      ]
+    );
+  }
+
+  fn matched_len(diffs: &[DiffItem]) -> usize {
+    diffs
+      .iter()
+      .filter(|d| matches!(d, Match { .. }))
+      .map(|d| d.lhs().len())
+      .sum()
+  }
+
+  fn lcs_len(lhs: &[&[u8]], rhs: &[&[u8]]) -> usize {
+    let mut table = vec![vec![0; rhs.len() + 1]; lhs.len() + 1];
+    for (i, l) in lhs.iter().enumerate() {
+      for (j, r) in rhs.iter().enumerate() {
+        table[i + 1][j + 1] = if l == r {
+          table[i][j] + 1
+        } else {
+          table[i][j + 1].max(table[i + 1][j])
+        };
+      }
+    }
+    table[lhs.len()][rhs.len()]
+  }
+
+  fn assert_valid(lhs: &[&[u8]], rhs: &[&[u8]], diffs: &[DiffItem]) {
+    let (mut l, mut r) = (0, 0);
+    for d in diffs {
+      assert_eq!((d.lhs().start, d.rhs().start), (l, r), "{diffs:?}");
+      if let Match { lhs: ml, rhs: mr } = d {
+        assert_eq!(ml.len(), mr.len());
+        assert_eq!(&lhs[ml.clone()], &rhs[mr.clone()]);
+      }
+      (l, r) = (d.lhs().end, d.rhs().end);
+    }
+    assert_eq!((l, r), (lhs.len(), rhs.len()));
+  }
+
+  #[test]
+  fn diff_myers_classic() {
+    // The example from Myers' paper: an edit distance of 5.
+    let lhs: Vec<&[u8]> = [b"a", b"b", b"c", b"a", b"b", b"b", b"a"]
+      .into_iter()
+      .map(|l| l as &[u8])
+      .collect();
+    let rhs: Vec<&[u8]> = [b"c", b"b", b"a", b"b", b"a", b"c"]
+      .into_iter()
+      .map(|l| l as &[u8])
+      .collect();
+    for algorithm in [Algorithm::Myers, Algorithm::Minimal] {
+      let diffs = diff(&lhs, &rhs, algorithm);
+      assert_valid(&lhs, &rhs, &diffs);
+      assert_eq!(matched_len(&diffs), 4);
+    }
+  }
+
+  #[test]
+  fn diff_random_inputs() {
+    // A tiny xorshift generator keeps this deterministic without extra deps.
+    let mut state: u32 = 0x9e3779b9;
+    let mut next = move || {
+      state ^= state << 13;
+      state ^= state >> 17;
+      state ^= state << 5;
+      state
+    };
+    let alphabet: [&[u8]; 4] = [b"a", b"b", b"c", b"d"];
+    for _ in 0..500 {
+      let mut gen_lines = |max: u32| -> Vec<&[u8]> {
+        let len = next() % max;
+        (0..len).map(|_| alphabet[(next() % 4) as usize]).collect()
+      };
+      let lhs = gen_lines(20);
+      let rhs = gen_lines(20);
+      for algorithm in Algorithm::ALL {
+        let diffs = diff(&lhs, &rhs, algorithm);
+        assert_valid(&lhs, &rhs, &diffs);
+      }
+      assert_eq!(
+        matched_len(&diff(&lhs, &rhs, Algorithm::Minimal)),
+        lcs_len(&lhs, &rhs),
+        "{lhs:?} {rhs:?}"
+      );
+    }
+  }
+
+  #[test]
+  fn diff_myers_heuristic() {
+    // Large enough that the edit cost passes the Myers cost limit.
+    let lines: Vec<Vec<u8>> = (0..64).map(|i| format!("{i}").into()).collect();
+    let lhs: Vec<&[u8]> =
+      (0..3000).map(|i| lines[i * 7 % 61].as_slice()).collect();
+    let rhs: Vec<&[u8]> =
+      (0..3000).map(|i| lines[i * 11 % 63].as_slice()).collect();
+    let heuristic = diff(&lhs, &rhs, Algorithm::Myers);
+    let minimal = diff(&lhs, &rhs, Algorithm::Minimal);
+    assert_valid(&lhs, &rhs, &heuristic);
+    assert_valid(&lhs, &rhs, &minimal);
+    assert!(matched_len(&heuristic) <= matched_len(&minimal));
+  }
+
+  #[test]
+  fn diff_large_corpus() {
+    // 100k mostly unique lines; anything quadratic in here takes minutes.
+    let lhs = split_lines(include_bytes!("testdata/bench/old.sql"));
+    let rhs = split_lines(include_bytes!("testdata/bench/new.sql"));
+    let diffs = diff(&lhs, &rhs, Algorithm::Patience);
+    assert_valid(&lhs, &rhs, &diffs);
+    assert_eq!(matched_len(&diffs), 98_500);
+  }
+
+  #[test]
+  fn diff_budget_exhausted() {
+    let lhs: [&[u8]; 5] = [b"a", b"b", b"c", b"d", b"e"];
+    let rhs: [&[u8]; 5] = [b"a", b"c", b"b", b"d", b"e"];
+    for algorithm in Algorithm::ALL {
+      let mut budget = Budget::new(Some(0), None);
+      assert_eq!(
+        diff_seq_with_budget(&lhs, &rhs, algorithm, &mut budget),
+        &[
+          Match {
+            lhs: Range { start: 0, end: 1 },
+            rhs: Range { start: 0, end: 1 },
+          },
+          Mutation {
+            lhs: Range { start: 1, end: 3 },
+            rhs: Range { start: 1, end: 3 },
+          },
+          Match {
+            lhs: Range { start: 3, end: 5 },
+            rhs: Range { start: 3, end: 5 },
+          },
+        ]
+      );
+      assert!(budget.exhausted());
+
+      let mut budget = Budget::default();
+      assert_eq!(
+        diff_seq_with_budget(&lhs, &rhs, algorithm, &mut budget),
+        diff_seq(&lhs, &rhs, algorithm)
+      );
+      assert!(!budget.exhausted());
+    }
+  }
+
+  #[test]
+  fn diff_budget_stays_valid() {
+    let lines: Vec<Vec<u8>> = (0..16).map(|i| format!("{i}").into()).collect();
+    let lhs: Vec<&[u8]> =
+      (0..400).map(|i| lines[i * 7 % 13].as_slice()).collect();
+    let rhs: Vec<&[u8]> =
+      (0..400).map(|i| lines[i * 5 % 11].as_slice()).collect();
+    for algorithm in Algorithm::ALL {
+      for max_cost in [1, 100, 1_000, 10_000] {
+        let mut budget = Budget::new(Some(max_cost), None);
+        let diffs = diff_seq_with_budget(&lhs, &rhs, algorithm, &mut budget);
+        assert_valid(&lhs, &rhs, &diffs);
+        assert!(budget.exhausted(), "{algorithm:?} {max_cost}");
+      }
+    }
+  }
+
+  #[test]
+  fn diff_deep_histogram() {
+    // Every anchor leaves the rest of the input to the right of it, which
+    // nests as deep as the input is long. A small stack makes sure none of
+    // that ends up on the call stack.
+    let lhs: Vec<u32> = (0..500).collect();
+    let rhs: Vec<u32> = (0..500).flat_map(|i| [u32::MAX, i]).collect();
+    let diffs = std::thread::Builder::new()
+      .stack_size(64 * 1024)
+      .spawn(move || diff_seq(&lhs, &rhs, Algorithm::Histogram))
+      .unwrap()
+      .join()
+      .unwrap();
+    assert_eq!(diffs.len(), 1000);
+  }
+
+  #[test]
+  fn diff_anchored_lines() {
+    let lhs: [&[u8]; 3] = [b"a", b"b", b"c"];
+    let rhs: [&[u8]; 3] = [b"c", b"a", b"b"];
+    for algorithm in Algorithm::ALL {
+      assert_eq!(
+        diff_anchored(&lhs, &rhs, algorithm, &["c".parse().unwrap()]),
+        &[
+          Mutation {
+            lhs: Range { start: 0, end: 2 },
+            rhs: Range { start: 0, end: 0 },
+          },
+          Match {
+            lhs: Range { start: 2, end: 3 },
+            rhs: Range { start: 0, end: 1 },
+          },
+          Mutation {
+            lhs: Range { start: 3, end: 3 },
+            rhs: Range { start: 1, end: 3 },
+          },
+        ]
+      );
+    }
+  }
+
+  #[test]
+  fn diff_pins_are_checked() {
+    let lhs = [1, 2, 3, 4];
+    let rhs = [1, 2, 3, 4];
+    // Unequal, crossing and out of range pins are all dropped.
+    let pins = [(1, 2), (2, 2), (1, 3), (9, 9)];
+    let diffs = diff_seq_pinned(
+      &lhs,
+      &rhs,
+      Algorithm::Patience,
+      &pins,
+      &mut Budget::default(),
+    );
+    assert_eq!(
+      diffs,
+      &[Match {
+        lhs: Range { start: 0, end: 4 },
+        rhs: Range { start: 0, end: 4 },
+      }]
+    );
+  }
+
   #[test]
   fn match_lines_arity1() {
     assert_eq!(
@@ -375,7 +1171,11 @@ mod tests {
   #[test]
   fn lead_trail_overlap() {
     assert_eq!(
-      diff(&[b"a", b"b", b"d", b"b", b"c"], &[b"a", b"b", b"c"]),
+      diff(
+        &[b"a", b"b", b"d", b"b", b"c"],
+        &[b"a", b"b", b"c"],
+        Algorithm::Patience
+      ),
       &[
         Match {
           lhs: Range { start: 0, end: 2 },
@@ -0,0 +1,255 @@
+use std::hash::Hash;
+use std::ops::Range;
+
+use crate::diff::Algorithm;
+use crate::diff::DiffItem;
+use crate::diff::diff_seq;
+
+use Diff3Item::*;
+
+/// A region of a three-way diff, with the lines it covers in the common
+/// ancestor (`base`) and the two sides being merged (`ours` and `theirs`).
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Diff3Item {
+  /// Unchanged on both sides.
+  Stable {
+    base: Range<usize>,
+    ours: Range<usize>,
+    theirs: Range<usize>,
+  },
+  /// Only changed in `ours`.
+  Ours {
+    base: Range<usize>,
+    ours: Range<usize>,
+    theirs: Range<usize>,
+  },
+  /// Only changed in `theirs`.
+  Theirs {
+    base: Range<usize>,
+    ours: Range<usize>,
+    theirs: Range<usize>,
+  },
+  /// Changed the same way on both sides.
+  BothSame {
+    base: Range<usize>,
+    ours: Range<usize>,
+    theirs: Range<usize>,
+  },
+  /// Changed differently on the two sides.
+  Conflict {
+    base: Range<usize>,
+    ours: Range<usize>,
+    theirs: Range<usize>,
+  },
+}
+
+impl Diff3Item {
+  pub fn base(&self) -> Range<usize> {
+    match self {
+      Stable { base, .. }
+      | Ours { base, .. }
+      | Theirs { base, .. }
+      | BothSame { base, .. }
+      | Conflict { base, .. } => base.clone(),
+    }
+  }
+
+  pub fn ours(&self) -> Range<usize> {
+    match self {
+      Stable { ours, .. }
+      | Ours { ours, .. }
+      | Theirs { ours, .. }
+      | BothSame { ours, .. }
+      | Conflict { ours, .. } => ours.clone(),
+    }
+  }
+
+  pub fn theirs(&self) -> Range<usize> {
+    match self {
+      Stable { theirs, .. }
+      | Ours { theirs, .. }
+      | Theirs { theirs, .. }
+      | BothSame { theirs, .. }
+      | Conflict { theirs, .. } => theirs.clone(),
+    }
+  }
+}
+
+/// Three-way diffs lines of `ours` and `theirs` against their common ancestor
+/// `base`.
+pub fn diff3(
+  base: &[&[u8]],
+  ours: &[&[u8]],
+  theirs: &[&[u8]],
+  algorithm: Algorithm,
+) -> Vec<Diff3Item> {
+  diff3_seq(base, ours, theirs, algorithm)
+}
+
+/// Three-way diffs sequences of arbitrary elements, comparing them with `Eq`.
+///
+/// Both sides are diffed against `base`. Runs of base lines that both diffs
+/// match up are stable, and everything between them is classified by which
+/// sides changed it.
+pub fn diff3_seq<T: Hash + Eq>(
+  base: &[T],
+  ours: &[T],
+  theirs: &[T],
+  algorithm: Algorithm,
+) -> Vec<Diff3Item> {
+  let in_ours = matches(base.len(), &diff_seq(base, ours, algorithm));
+  let in_theirs = matches(base.len(), &diff_seq(base, theirs, algorithm));
+  let stable_at = |b: usize, o: usize, t: usize| {
+    in_ours[b] == Some(o) && in_theirs[b] == Some(t)
+  };
+
+  let mut out = vec![];
+  let (mut b, mut o, mut t) = (0, 0, 0);
+  while b < base.len() || o < ours.len() || t < theirs.len() {
+    let mut n = 0;
+    while b + n < base.len() && stable_at(b + n, o + n, t + n) {
+      n += 1;
+    }
+    if n > 0 {
+      out.push(Stable {
+        base: b..b + n,
+        ours: o..o + n,
+        theirs: t..t + n,
+      });
+      (b, o, t) = (b + n, o + n, t + n);
+      continue;
+    }
+
+    // The unstable region runs up to the next base line both sides kept.
+    let (b_end, o_end, t_end) = (b..base.len())
+      .find_map(|i| Some((i, in_ours[i]?, in_theirs[i]?)))
+      .unwrap_or((base.len(), ours.len(), theirs.len()));
+    let (base_r, ours_r, theirs_r) = (b..b_end, o..o_end, t..t_end);
+    let ours_changed = ours[ours_r.clone()] != base[base_r.clone()];
+    let theirs_changed = theirs[theirs_r.clone()] != base[base_r.clone()];
+    out.push(match (ours_changed, theirs_changed) {
+      (false, false) => Stable {
+        base: base_r,
+        ours: ours_r,
+        theirs: theirs_r,
+      },
+      (true, false) => Ours {
+        base: base_r,
+        ours: ours_r,
+        theirs: theirs_r,
+      },
+      (false, true) => Theirs {
+        base: base_r,
+        ours: ours_r,
+        theirs: theirs_r,
+      },
+      _ if ours[ours_r.clone()] == theirs[theirs_r.clone()] => BothSame {
+        base: base_r,
+        ours: ours_r,
+        theirs: theirs_r,
+      },
+      _ => Conflict {
+        base: base_r,
+        ours: ours_r,
+        theirs: theirs_r,
+      },
+    });
+    (b, o, t) = (b_end, o_end, t_end);
+  }
+  out
+}
+
+/// For every base line, the line a two-way diff matched it with, if any.
+fn matches(len: usize, diffs: &[DiffItem]) -> Vec<Option<usize>> {
+  let mut out = vec![None; len];
+  for d in diffs {
+    if let DiffItem::Match { lhs, rhs } = d {
+      for (l, r) in std::iter::zip(lhs.clone(), rhs.clone()) {
+        out[l] = Some(r);
+      }
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn diff3_str(base: &str, ours: &str, theirs: &str) -> Vec<Diff3Item> {
+    let lines = |s: &str| s.chars().collect::<Vec<_>>();
+    diff3_seq(&lines(base), &lines(ours), &lines(theirs), Algorithm::default())
+  }
+
+  #[test]
+  fn unchanged() {
+    assert_eq!(
+      diff3_str("abc", "abc", "abc"),
+      &[Stable { base: 0..3, ours: 0..3, theirs: 0..3 }]
+    );
+    assert_eq!(diff3_str("", "", ""), &[]);
+  }
+
+  #[test]
+  fn one_side_changed() {
+    assert_eq!(
+      diff3_str("abcde", "aXcde", "abcdeY"),
+      &[
+        Stable { base: 0..1, ours: 0..1, theirs: 0..1 },
+        Ours { base: 1..2, ours: 1..2, theirs: 1..2 },
+        Stable { base: 2..5, ours: 2..5, theirs: 2..5 },
+        Theirs { base: 5..5, ours: 5..5, theirs: 5..6 },
+      ]
+    );
+  }
+
+  #[test]
+  fn both_changed() {
+    assert_eq!(
+      diff3_str("abcde", "aXcdZ", "aXcdY"),
+      &[
+        Stable { base: 0..1, ours: 0..1, theirs: 0..1 },
+        BothSame { base: 1..2, ours: 1..2, theirs: 1..2 },
+        Stable { base: 2..4, ours: 2..4, theirs: 2..4 },
+        Conflict { base: 4..5, ours: 4..5, theirs: 4..5 },
+      ]
+    );
+  }
+
+  #[test]
+  fn adjacent_changes_conflict() {
+    // Edits to neighbouring lines leave no stable line between them.
+    assert_eq!(
+      diff3_str("abcd", "aXcd", "abYd"),
+      &[
+        Stable { base: 0..1, ours: 0..1, theirs: 0..1 },
+        Conflict { base: 1..3, ours: 1..3, theirs: 1..3 },
+        Stable { base: 3..4, ours: 3..4, theirs: 3..4 },
+      ]
+    );
+  }
+
+  #[test]
+  fn deletion_and_insertion() {
+    assert_eq!(
+      diff3_str("abc", "ac", "Zabc"),
+      &[
+        Theirs { base: 0..0, ours: 0..0, theirs: 0..1 },
+        Stable { base: 0..1, ours: 0..1, theirs: 1..2 },
+        Ours { base: 1..2, ours: 1..1, theirs: 2..3 },
+        Stable { base: 2..3, ours: 1..2, theirs: 3..4 },
+      ]
+    );
+  }
+
+  #[test]
+  fn lines() {
+    let base: [&[u8]; 2] = [b"fn a() {}", b"fn b() {}"];
+    let ours: [&[u8]; 2] = [b"fn a() {}", b"fn b() { x }"];
+    let theirs: [&[u8]; 2] = [b"fn a() {}", b"fn b() { y }"];
+    let items = diff3(&base, &ours, &theirs, Algorithm::Histogram);
+    assert_eq!(items.len(), 2);
+    assert_eq!(items[1], Conflict { base: 1..2, ours: 1..2, theirs: 1..2 });
+    assert_eq!(items[1].ours(), 1..2);
+  }
+}
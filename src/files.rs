@@ -7,6 +7,26 @@ use std::path::PathBuf;
 use walkdir::DirEntry;
 use walkdir::WalkDir;
 
+use crate::large::Content;
+use crate::large::Window;
+use crate::large::estimate_memory;
+use crate::large::trim;
+use crate::pool::OrderedPool;
+
+/// Controls how file contents are read while walking.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WalkOptions {
+  /// Memory map files and only split the lines that differ, so files far
+  /// larger than memory can be compared.
+  pub large_files: bool,
+  /// Report pairs that would need more than this many bytes to diff as
+  /// `TooLarge` instead of diffing them. Implies `large_files`.
+  pub max_memory: Option<u64>,
+  /// Read the file pairs of directory trees on this many threads. Events are
+  /// still yielded in walk order. 0 and 1 read them one at a time.
+  pub jobs: usize,
+}
+
 /// An event produced by walking a pair of paths.
 pub enum FilePairEvent {
   /// A pair of diffable (non-identical, non-binary) text files.
@@ -16,6 +36,23 @@ pub enum FilePairEvent {
     lhs_content: Vec<u8>,
     rhs_content: Vec<u8>,
   },
+  /// A pair of diffable text files read in large-file mode, trimmed to the
+  /// lines that differ.
+  LargeTextDiff {
+    lhs_path: Option<PathBuf>,
+    rhs_path: Option<PathBuf>,
+    lhs: Window,
+    rhs: Window,
+  },
+  /// Text files whose differences need more memory to diff than allowed.
+  TooLarge {
+    lhs_path: Option<PathBuf>,
+    rhs_path: Option<PathBuf>,
+    /// Estimated bytes needed.
+    needed: u64,
+    /// The configured limit.
+    limit: u64,
+  },
   /// Files that differ but at least one is non-UTF-8.
   Binary {
     lhs_path: Option<PathBuf>,
@@ -31,13 +68,69 @@ pub enum FilePairEvent {
   },
 }
 
+impl FilePairEvent {
+  /// The old and new paths of the pair, if they exist.
+  pub fn paths(&self) -> (Option<&Path>, Option<&Path>) {
+    match self {
+      FilePairEvent::TextDiff { lhs_path, rhs_path, .. }
+      | FilePairEvent::LargeTextDiff { lhs_path, rhs_path, .. }
+      | FilePairEvent::TooLarge { lhs_path, rhs_path, .. }
+      | FilePairEvent::Binary { lhs_path, rhs_path }
+      | FilePairEvent::IoError { lhs_path, rhs_path, .. } => {
+        (lhs_path.as_deref(), rhs_path.as_deref())
+      }
+      FilePairEvent::TypeMismatch { lhs_path, rhs_path } => {
+        (Some(lhs_path), Some(rhs_path))
+      }
+    }
+  }
+}
+
 enum IterState {
   /// Walking two directory trees in parallel.
   Dirs(DirWalkState),
+  /// Walking two directory trees, reading file pairs on worker threads.
+  Parallel(ParallelWalk),
   /// A single pre-computed event.
   Once(Option<FilePairEvent>),
 }
 
+struct ParallelWalk {
+  walk: DirWalkState,
+  pool: OrderedPool<PairJob, Option<FilePairEvent>>,
+}
+
+impl ParallelWalk {
+  fn advance(&mut self) -> Option<FilePairEvent> {
+    loop {
+      while !self.pool.is_full() {
+        let Some(job) = self.walk.next_job() else {
+          break;
+        };
+        self.pool.send(job);
+      }
+      if let Some(event) = self.pool.next()? {
+        return Some(event);
+      }
+    }
+  }
+}
+
+/// A pair of walk entries that still needs reading.
+enum PairJob {
+  Read(Option<PathBuf>, Option<PathBuf>),
+  Done(FilePairEvent),
+}
+
+impl PairJob {
+  fn run(self, options: &WalkOptions) -> Option<FilePairEvent> {
+    match self {
+      PairJob::Read(lhs, rhs) => process_file_pair(lhs, rhs, options),
+      PairJob::Done(event) => Some(event),
+    }
+  }
+}
+
 struct DirWalkState {
   lhs_root: PathBuf,
   rhs_root: PathBuf,
@@ -45,6 +138,7 @@ struct DirWalkState {
   rhs_iter: Box<dyn Iterator<Item = DirEntry>>,
   lhs_next: Option<DirEntry>,
   rhs_next: Option<DirEntry>,
+  options: WalkOptions,
 }
 
 fn make_walk_iter(root: &Path) -> Box<dyn Iterator<Item = DirEntry>> {
@@ -60,6 +154,15 @@ fn make_walk_iter(root: &Path) -> Box<dyn Iterator<Item = DirEntry>> {
 impl DirWalkState {
   /// Advance the walk, skipping identical things.
   fn advance(&mut self) -> Option<FilePairEvent> {
+    loop {
+      if let Some(event) = self.next_job()?.run(&self.options) {
+        return Some(event);
+      }
+    }
+  }
+
+  /// Advance the walk to the next pair of entries that may differ.
+  fn next_job(&mut self) -> Option<PairJob> {
     loop {
       let ord = compare_entries(
         &self.lhs_next,
@@ -67,7 +170,7 @@ impl DirWalkState {
         &self.lhs_root,
         &self.rhs_root,
       );
-      match ord {
+      let job = match ord {
         Ordering::Equal
           if self.lhs_next.is_none() && self.rhs_next.is_none() =>
         {
@@ -78,24 +181,21 @@ impl DirWalkState {
           let rhs = self.rhs_next.take();
           self.lhs_next = self.lhs_iter.next();
           self.rhs_next = self.rhs_iter.next();
-          if let Some(event) = process_entry_pair(lhs.as_ref(), rhs.as_ref()) {
-            return Some(event);
-          }
+          entry_pair_job(lhs.as_ref(), rhs.as_ref())
         }
         Ordering::Less => {
           let lhs = self.lhs_next.take();
           self.lhs_next = self.lhs_iter.next();
-          if let Some(event) = process_entry_pair(lhs.as_ref(), None) {
-            return Some(event);
-          }
+          entry_pair_job(lhs.as_ref(), None)
         }
         Ordering::Greater => {
           let rhs = self.rhs_next.take();
           self.rhs_next = self.rhs_iter.next();
-          if let Some(event) = process_entry_pair(None, rhs.as_ref()) {
-            return Some(event);
-          }
+          entry_pair_job(None, rhs.as_ref())
         }
+      };
+      if job.is_some() {
+        return job;
       }
     }
   }
@@ -112,6 +212,7 @@ impl Iterator for FilePairIter {
   fn next(&mut self) -> Option<FilePairEvent> {
     match &mut self.state {
       IterState::Dirs(walk) => walk.advance(),
+      IterState::Parallel(walk) => walk.advance(),
       IterState::Once(event) => event.take(),
     }
   }
@@ -120,12 +221,22 @@ impl Iterator for FilePairIter {
 /// Walk lhs and rhs (files or directory trees) and yield an event for each
 /// differing file pair encountered.
 pub fn walk_file_pairs(lhs: &Path, rhs: &Path) -> FilePairIter {
+  walk_file_pairs_with(lhs, rhs, WalkOptions::default())
+}
+
+/// Like `walk_file_pairs`, reading files as described by `options`.
+pub fn walk_file_pairs_with(
+  lhs: &Path,
+  rhs: &Path,
+  options: WalkOptions,
+) -> FilePairIter {
   let stdin = Path::new("-");
   if lhs == stdin || rhs == stdin {
     return FilePairIter {
       state: IterState::Once(process_file_pair(
         Some(lhs.to_path_buf()),
         Some(rhs.to_path_buf()),
+        &options,
       )),
     };
   }
@@ -158,7 +269,7 @@ pub fn walk_file_pairs(lhs: &Path, rhs: &Path) -> FilePairIter {
   };
   match (lhs_is_dir, rhs_is_dir) {
     (false, false) => FilePairIter {
-      state: IterState::Once(process_file_pair(Some(lhs), Some(rhs))),
+      state: IterState::Once(process_file_pair(Some(lhs), Some(rhs), &options)),
     },
     (true, true) => {
       let lhs_root = lhs;
@@ -169,15 +280,22 @@ pub fn walk_file_pairs(lhs: &Path, rhs: &Path) -> FilePairIter {
       let mut rhs_iter = make_walk_iter(&rhs_root);
       let rhs_next = rhs_iter.next();
 
+      let walk = DirWalkState {
+        lhs_root,
+        rhs_root,
+        lhs_iter,
+        rhs_iter,
+        lhs_next,
+        rhs_next,
+        options,
+      };
+      if options.jobs <= 1 {
+        return FilePairIter { state: IterState::Dirs(walk) };
+      }
+      let pool =
+        OrderedPool::new(options.jobs, move |job: PairJob| job.run(&options));
       FilePairIter {
-        state: IterState::Dirs(DirWalkState {
-          lhs_root,
-          rhs_root,
-          lhs_iter,
-          rhs_iter,
-          lhs_next,
-          rhs_next,
-        }),
+        state: IterState::Parallel(ParallelWalk { walk, pool }),
       }
     }
     _ => FilePairIter {
@@ -211,26 +329,26 @@ fn is_dir(entry: &DirEntry) -> bool {
   entry.metadata().map(|m| m.is_dir()).unwrap_or(false)
 }
 
-/// Process one matched directory entry pair. Returns `None` for pairs that
-/// should be skipped (directories, identical inodes, identical file contents).
-fn process_entry_pair(
+/// Decide what to do with one matched directory entry pair. Returns `None`
+/// for pairs that should be skipped (directories, identical inodes).
+fn entry_pair_job(
   lhs: Option<&DirEntry>,
   rhs: Option<&DirEntry>,
-) -> Option<FilePairEvent> {
+) -> Option<PairJob> {
   match (lhs, rhs) {
     (None, None) => None,
     (Some(lhs), None) => {
       if is_dir(lhs) {
         None
       } else {
-        process_file_pair(Some(lhs.path().to_path_buf()), None)
+        Some(PairJob::Read(Some(lhs.path().to_path_buf()), None))
       }
     }
     (None, Some(rhs)) => {
       if is_dir(rhs) {
         None
       } else {
-        process_file_pair(None, Some(rhs.path().to_path_buf()))
+        Some(PairJob::Read(None, Some(rhs.path().to_path_buf())))
       }
     }
     (Some(lhs), Some(rhs)) => {
@@ -244,14 +362,14 @@ fn process_entry_pair(
       }
       match (is_dir(lhs), is_dir(rhs)) {
         (true, true) => None,
-        (false, false) => process_file_pair(
+        (false, false) => Some(PairJob::Read(
           Some(lhs.path().to_path_buf()),
           Some(rhs.path().to_path_buf()),
-        ),
-        _ => Some(FilePairEvent::TypeMismatch {
+        )),
+        _ => Some(PairJob::Done(FilePairEvent::TypeMismatch {
           lhs_path: lhs.path().to_path_buf(),
           rhs_path: rhs.path().to_path_buf(),
-        }),
+        })),
       }
     }
   }
@@ -261,7 +379,11 @@ fn process_entry_pair(
 fn process_file_pair(
   lhs_path: Option<PathBuf>,
   rhs_path: Option<PathBuf>,
+  options: &WalkOptions,
 ) -> Option<FilePairEvent> {
+  if options.large_files || options.max_memory.is_some() {
+    return process_large_file_pair(lhs_path, rhs_path, options.max_memory);
+  }
   let lhs = read(lhs_path.as_deref());
   let rhs = read(rhs_path.as_deref());
   match (lhs, rhs) {
@@ -287,6 +409,44 @@ fn process_file_pair(
   }
 }
 
+/// Like `process_file_pair`, but maps the files instead of reading them and
+/// trims off their common lines.
+fn process_large_file_pair(
+  lhs_path: Option<PathBuf>,
+  rhs_path: Option<PathBuf>,
+  max_memory: Option<u64>,
+) -> Option<FilePairEvent> {
+  let open = |p: Option<&Path>| match p {
+    Some(p) => Content::open(p),
+    None => Ok(Content::Owned(vec![])),
+  };
+  let (lhs, rhs) = match (open(lhs_path.as_deref()), open(rhs_path.as_deref()))
+  {
+    (Err(e), _) | (_, Err(e)) => {
+      return Some(FilePairEvent::IoError {
+        lhs_path,
+        rhs_path,
+        err: e.to_string(),
+      });
+    }
+    (Ok(lhs), Ok(rhs)) => (lhs, rhs),
+  };
+  if *lhs == *rhs {
+    return None;
+  }
+  if std::str::from_utf8(&lhs).is_err() || std::str::from_utf8(&rhs).is_err() {
+    return Some(FilePairEvent::Binary { lhs_path, rhs_path });
+  }
+  let (lhs, rhs) = trim(lhs, rhs);
+  let needed = estimate_memory(&lhs, &rhs);
+  if let Some(limit) = max_memory
+    && needed > limit
+  {
+    return Some(FilePairEvent::TooLarge { lhs_path, rhs_path, needed, limit });
+  }
+  Some(FilePairEvent::LargeTextDiff { lhs_path, rhs_path, lhs, rhs })
+}
+
 fn open(path: &Path) -> Result<Box<dyn Read>, Box<dyn std::error::Error>> {
   if path == Path::new("-") {
     Ok(Box::new(std::io::stdin()))
@@ -372,4 +532,71 @@ mod tests {
     );
     Ok(())
   }
+
+  #[test]
+  fn large_files() -> Result<(), Box<dyn std::error::Error>> {
+    let dir = tempfile::tempdir()?;
+    let (old, new) = (dir.path().join("old"), dir.path().join("new"));
+    let body: String = (0..1000).map(|i| format!("line {i}\n")).collect();
+    std::fs::write(&old, format!("{body}old\n{body}"))?;
+    std::fs::write(&new, format!("{body}new\n{body}"))?;
+
+    let options = WalkOptions { large_files: true, ..Default::default() };
+    let events: Vec<_> = walk_file_pairs_with(&old, &new, options).collect();
+    let [FilePairEvent::LargeTextDiff { lhs, rhs, .. }] = &events[..] else {
+      panic!("expected a single large text diff");
+    };
+    assert_eq!(
+      lhs.lines(1),
+      (vec![b"line 999" as &[u8], b"old", b"line 0"], 999)
+    );
+    assert_eq!(rhs.lines(0), (vec![b"new" as &[u8]], 1000));
+
+    let options = WalkOptions {
+      max_memory: Some(100),
+      ..Default::default()
+    };
+    let events: Vec<_> = walk_file_pairs_with(&old, &new, options).collect();
+    assert!(matches!(
+      events[..],
+      [FilePairEvent::TooLarge { needed: 512, limit: 100, .. }]
+    ));
+
+    assert_eq!(walk_file_pairs_with(&old, &old, options).count(), 0);
+    Ok(())
+  }
+
+  #[test]
+  fn parallel_walk() -> Result<(), Box<dyn std::error::Error>> {
+    let old = tempfile::tempdir()?;
+    let new = tempfile::tempdir()?;
+    for i in 0..50 {
+      std::fs::write(old.path().join(format!("{i:02}")), format!("{i}\n"))?;
+      if i % 7 != 0 {
+        let content = if i % 3 == 0 { format!("{i}\n") } else { "x\n".into() };
+        std::fs::write(new.path().join(format!("{i:02}")), content)?;
+      }
+    }
+    std::fs::create_dir(new.path().join("07"))?;
+
+    let paths = |jobs| {
+      let options = WalkOptions { jobs, ..Default::default() };
+      walk_file_pairs_with(old.path(), new.path(), options)
+        .map(|e| match e {
+          FilePairEvent::TextDiff { lhs_path, rhs_path, .. } => {
+            (lhs_path, rhs_path)
+          }
+          FilePairEvent::TypeMismatch { lhs_path, rhs_path } => {
+            (Some(lhs_path), Some(rhs_path))
+          }
+          _ => panic!("unexpected event"),
+        })
+        .collect::<Vec<_>>()
+    };
+    let serial = paths(1);
+    // 14 pairs are identical, "07" is a directory on one side.
+    assert_eq!(serial.len(), 36);
+    assert_eq!(paths(4), serial);
+    Ok(())
+  }
 }
@@ -0,0 +1,289 @@
+use std::fmt::Write as _;
+use std::io::Result;
+use std::io::Write;
+use std::iter::zip;
+
+use crate::report::Hunk;
+use crate::report::Line;
+use crate::report::OpKind;
+
+/// Colors for the classes named after the `Styles` fields, matching
+/// `Styles::simple` on a dark terminal.
+const CSS: &str = "\
+body { background: #1e1e1e; color: #d4d4d4; font-family: sans-serif; margin: 2em; }
+a { color: #4aa5f0; }
+h1, summary { color: #ffffff; font-weight: bold; }
+summary { cursor: pointer; font-family: monospace; font-size: 1.1em; padding: 0.3em 0; }
+details.section { border-top: 1px solid #444; margin-top: 1em; }
+table.hunk { border-collapse: collapse; font-family: monospace; margin: 0.5em 0; width: 100%; }
+table.hunk td { padding: 0 0.5em; vertical-align: top; }
+td.number { color: #2aa1b3; text-align: right; user-select: none; width: 1%; }
+td.sign { user-select: none; width: 1%; }
+td.text { white-space: pre-wrap; word-break: break-all; }
+tr.deleted { background: #3a1d20; }
+tr.inserted { background: #1d3a20; }
+.separator { color: #2aa1b3; }
+.header { color: #ffffff; font-weight: bold; }
+.old { color: #e05561; }
+.old_dim { opacity: 0.6; }
+.new { color: #8cc265; }
+.new_dim { }
+.moved_old { color: #c162de; }
+.moved_new { color: #4aa5f0; }
+.normalized { color: #d18f52; }
+";
+
+/// Starts a report document titled `title`.
+pub(crate) fn start(out: &mut dyn Write, title: &str) -> Result<()> {
+  let title = escape(title);
+  writeln!(out, "<!DOCTYPE html>")?;
+  writeln!(out, "<html lang=\"en\">")?;
+  writeln!(out, "<head>")?;
+  writeln!(out, "<meta charset=\"utf-8\">")?;
+  writeln!(out, "<title>{title}</title>")?;
+  writeln!(out, "<style>\n{CSS}</style>")?;
+  writeln!(out, "</head>")?;
+  writeln!(out, "<body>")?;
+  writeln!(out, "<h1>{title}</h1>")
+}
+
+pub(crate) fn end(out: &mut dyn Write) -> Result<()> {
+  writeln!(out, "</body>")?;
+  writeln!(out, "</html>")
+}
+
+/// Writes a list of links to `sections`, given as their ids and already
+/// escaped names.
+pub(crate) fn index(
+  out: &mut dyn Write,
+  sections: &[(String, String)],
+) -> Result<()> {
+  writeln!(out, "<nav>\n<ul>")?;
+  for (id, name) in sections {
+    writeln!(out, "<li><a href=\"#{id}\">{name}</a></li>")?;
+  }
+  writeln!(out, "</ul>\n</nav>")
+}
+
+/// Writes a collapsible section with an already escaped `name` and `body`.
+pub(crate) fn section(
+  out: &mut dyn Write,
+  id: &str,
+  name: &str,
+  body: &[u8],
+) -> Result<()> {
+  writeln!(out, "<details class=\"section\" id=\"{id}\" open>")?;
+  writeln!(out, "<summary>{name}</summary>")?;
+  out.write_all(body)?;
+  writeln!(out, "</details>")
+}
+
+/// Formats `hunks` as tables of numbered lines, highlighting the parts of
+/// lines that changed. Lines that only match thanks to normalization are
+/// marked with `~` if `flag_normalized`.
+pub(crate) fn hunks(hunks: &[Hunk], flag_normalized: bool) -> String {
+  let mut out = String::new();
+  for h in hunks {
+    out.push_str("<table class=\"hunk\">\n");
+    writeln!(
+      out,
+      "<tr><td class=\"separator\" colspan=\"4\">@@ -{},{} +{},{} @@</td></tr>",
+      h.old_start, h.old_lines, h.new_start, h.new_lines,
+    )
+    .unwrap();
+    for op in &h.ops {
+      match op.kind {
+        OpKind::Equal => {
+          for (old, new) in zip(&op.old, &op.new) {
+            let normalized = flag_normalized && text(old) != text(new);
+            let (sign, class) =
+              if normalized { ("~", "normalized") } else { (" ", "both") };
+            let pieces = [(text(new), class)];
+            let numbers = (Some(old.number), Some(new.number));
+            row(&mut out, "context", numbers, (sign, class), &pieces);
+          }
+        }
+        OpKind::Delete => lines(&mut out, &op.old, true, "old", "old"),
+        OpKind::Insert => lines(&mut out, &op.new, false, "new", "new"),
+        OpKind::Replace => {
+          lines(&mut out, &op.old, true, "old", "old_dim");
+          lines(&mut out, &op.new, false, "new", "new_dim");
+        }
+        OpKind::MovedOut { .. } => {
+          lines(&mut out, &op.old, true, "old", "moved_old")
+        }
+        OpKind::MovedIn { .. } => {
+          lines(&mut out, &op.new, false, "new", "moved_new")
+        }
+      }
+    }
+    out.push_str("</table>\n");
+  }
+  out
+}
+
+/// Formats the rows of old or new `lines`, with the pieces that changed in
+/// the `changed` class and the rest in `unchanged`.
+fn lines(
+  out: &mut String,
+  lines: &[Line],
+  old: bool,
+  changed: &str,
+  unchanged: &str,
+) {
+  for line in lines {
+    let pieces: Vec<_> = line
+      .pieces
+      .iter()
+      .map(|(t, c)| (t.as_ref(), if *c { changed } else { unchanged }))
+      .collect();
+    if old {
+      let numbers = (Some(line.number), None);
+      row(out, "deleted", numbers, ("-", changed), &pieces);
+    } else {
+      let numbers = (None, Some(line.number));
+      row(out, "inserted", numbers, ("+", changed), &pieces);
+    }
+  }
+}
+
+/// Formats a row of the line numbered `numbers` in the old and new files,
+/// made of `pieces` of text and their classes.
+fn row(
+  out: &mut String,
+  class: &str,
+  numbers: (Option<usize>, Option<usize>),
+  (sign, sign_class): (&str, &str),
+  pieces: &[(impl AsRef<str>, &str)],
+) {
+  let number = |n: Option<usize>| n.map_or(String::new(), |n| n.to_string());
+  write!(
+    out,
+    "<tr class=\"{class}\"><td class=\"number\">{}</td>\
+     <td class=\"number\">{}</td><td class=\"sign {sign_class}\">{sign}</td>\
+     <td class=\"text\">",
+    number(numbers.0),
+    number(numbers.1),
+  )
+  .unwrap();
+  // Merge runs of pieces in the same class into one span.
+  let mut runs: Vec<(String, &str)> = vec![];
+  for (text, class) in pieces {
+    match runs.last_mut() {
+      Some((run, run_class)) if run_class == class => {
+        run.push_str(text.as_ref())
+      }
+      _ => runs.push((text.as_ref().to_owned(), class)),
+    }
+  }
+  for (text, class) in runs {
+    if !text.is_empty() {
+      write!(out, "<span class=\"{class}\">{}</span>", escape(&text)).unwrap();
+    }
+  }
+  out.push_str("</td></tr>\n");
+}
+
+fn text(line: &Line) -> String {
+  line.pieces.iter().map(|(t, _)| t.as_ref()).collect()
+}
+
+/// Escapes `s` for use in HTML text and attribute values. Control
+/// characters, which HTML doesn't allow, are replaced.
+pub(crate) fn escape(s: &str) -> String {
+  let mut out = String::with_capacity(s.len());
+  for c in s.chars() {
+    match c {
+      '&' => out.push_str("&amp;"),
+      '<' => out.push_str("&lt;"),
+      '>' => out.push_str("&gt;"),
+      '"' => out.push_str("&quot;"),
+      '\'' => out.push_str("&#39;"),
+      '\t' | '\n' => out.push(c),
+      c if c.is_control() => out.push('\u{fffd}'),
+      c => out.push(c),
+    }
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::report::Op;
+
+  fn line<'a>(number: usize, pieces: &[(&'a str, bool)]) -> Line<'a> {
+    Line {
+      number,
+      pieces: pieces.iter().map(|&(t, c)| (t.into(), c)).collect(),
+      paired_with: None,
+    }
+  }
+
+  #[test]
+  fn escaping() {
+    assert_eq!(
+      escape("a < b && c > \"d\" 'e'"),
+      "a &lt; b &amp;&amp; c &gt; &quot;d&quot; &#39;e&#39;"
+    );
+    assert_eq!(escape("\tx\0\x1b"), "\tx\u{fffd}\u{fffd}");
+  }
+
+  #[test]
+  fn highlights_changes() {
+    let h = Hunk {
+      old_start: 7,
+      old_lines: 2,
+      new_start: 7,
+      new_lines: 2,
+      ops: vec![
+        Op {
+          kind: OpKind::Equal,
+          old: vec![line(7, &[("<a>", false)])],
+          new: vec![line(7, &[("<a>", false)])],
+        },
+        Op {
+          kind: OpKind::Replace,
+          old: vec![line(8, &[("x = ", false), ("1", true)])],
+          new: vec![line(8, &[("x = ", false), ("2", true)])],
+        },
+      ],
+    };
+    assert_eq!(
+      hunks(&[h], false),
+      concat!(
+        "<table class=\"hunk\">\n",
+        "<tr><td class=\"separator\" colspan=\"4\">@@ -7,2 +7,2 @@</td></tr>\n",
+        "<tr class=\"context\"><td class=\"number\">7</td><td class=\"number\">7</td>",
+        "<td class=\"sign both\"> </td><td class=\"text\">",
+        "<span class=\"both\">&lt;a&gt;</span></td></tr>\n",
+        "<tr class=\"deleted\"><td class=\"number\">8</td><td class=\"number\"></td>",
+        "<td class=\"sign old\">-</td><td class=\"text\">",
+        "<span class=\"old_dim\">x = </span><span class=\"old\">1</span></td></tr>\n",
+        "<tr class=\"inserted\"><td class=\"number\"></td><td class=\"number\">8</td>",
+        "<td class=\"sign new\">+</td><td class=\"text\">",
+        "<span class=\"new_dim\">x = </span><span class=\"new\">2</span></td></tr>\n",
+        "</table>\n",
+      )
+    );
+  }
+
+  #[test]
+  fn flags_normalized_lines() {
+    let h = Hunk {
+      old_start: 1,
+      old_lines: 1,
+      new_start: 1,
+      new_lines: 1,
+      ops: vec![Op {
+        kind: OpKind::Equal,
+        old: vec![line(1, &[("a  b", false)])],
+        new: vec![line(1, &[("a b", false)])],
+      }],
+    };
+    assert!(hunks(&[h], true).contains(
+      "<td class=\"sign normalized\">~</td><td class=\"text\">\
+       <span class=\"normalized\">a b</span>"
+    ));
+  }
+}
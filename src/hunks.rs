@@ -5,7 +5,7 @@ use DiffItem::*;
 use crate::diff::DiffItem;
 use crate::diff::Side;
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
 pub struct Hunk {
   pub diffs: Vec<DiffItem>,
 }
@@ -33,7 +33,7 @@ impl Hunk {
         }
 
         if context == 0 {
-          hunk.diffs.retain(|d| matches!(d, Mutation { .. }));
+          hunk.diffs.retain(|d| !matches!(d, Match { .. }));
           return Some(hunk);
         }
 
@@ -54,6 +54,18 @@ impl Hunk {
       .collect()
   }
 
+  /// Whether `pred` holds for every line added, removed or moved in this hunk.
+  pub fn changes_all(&self, mut pred: impl FnMut(Side, usize) -> bool) -> bool {
+    self
+      .diffs
+      .iter()
+      .filter(|d| !matches!(d, Match { .. }))
+      .all(|d| {
+        d.lhs().all(|i| pred(Side::Lhs, i))
+          && d.rhs().all(|i| pred(Side::Rhs, i))
+      })
+  }
+
   pub fn side(&self, side: Side) -> Range<usize> {
     Range {
       start: self.diffs.first().map_or(0, |d| d.side(side).start),
@@ -73,13 +85,14 @@ impl Hunk {
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::diff::Algorithm;
   use crate::diff::diff;
   use crate::tokens::split_lines;
 
   fn diff_lines(lhs: &[u8], rhs: &[u8]) -> Vec<DiffItem> {
     let lhs_lines: Vec<_> = split_lines(lhs);
     let rhs_lines: Vec<_> = split_lines(rhs);
-    diff(&lhs_lines, &rhs_lines)
+    diff(&lhs_lines, &rhs_lines, Algorithm::Patience)
   }
 
   fn hunk_positions(hunks: &[Hunk]) -> Vec<((usize, usize), (usize, usize))> {
@@ -92,6 +105,23 @@ mod tests {
       .collect::<Vec<_>>()
   }
 
+  #[test]
+  fn changes_all() {
+    let lhs = split_lines(b"a\n\nb\nc\nd\ne\nf\ng\nh\n");
+    let rhs = split_lines(b"a\nb\nc\nd\ne\nf\ng\nH\n");
+    let hunks = Hunk::build(1, &diff(&lhs, &rhs, Algorithm::Patience));
+    let blank: Vec<_> = hunks
+      .iter()
+      .map(|h| {
+        h.changes_all(|side, i| match side {
+          Side::Lhs => lhs[i].is_empty(),
+          Side::Rhs => rhs[i].is_empty(),
+        })
+      })
+      .collect();
+    assert_eq!(blank, &[true, false]);
+  }
+
   #[test]
   fn build_hunks() {
     let diff = diff_lines(
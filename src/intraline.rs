@@ -0,0 +1,109 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+
+use crate::align::pair_lines;
+use crate::cleanup::cleanup_token_diffs;
+use crate::diff::Algorithm;
+use crate::diff::Budget;
+use crate::diff::DiffItem;
+use crate::diff::diff_seq_with_budget;
+use crate::normalize::Normalization;
+use crate::tokens::Intraline;
+use crate::tokens::is_whitespace_token;
+
+/// Finds the edits within changed lines, so that the printer and `DiffStats`
+/// agree on which lines were edited into which and what changed in them.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct TokenDiffer<'a> {
+  pub(crate) normalization: Normalization,
+  pub(crate) intraline: Intraline,
+  pub(crate) algorithm: Algorithm,
+  /// The budget of the line diff these lines came from, which token diffs
+  /// and pairing spend from too. Unlimited if `None`.
+  pub(crate) budget: Option<&'a RefCell<Budget>>,
+}
+
+impl TokenDiffer<'_> {
+  /// Splits `lines` into tokens, with a `"\n"` token between lines.
+  pub(crate) fn tokenize<'a>(&self, lines: &[&'a [u8]]) -> Vec<&'a [u8]> {
+    self.normalization.tokenize(lines, self.intraline)
+  }
+
+  /// Diffs two runs of tokens by their normalized keys, cleaned up to be
+  /// easier to read.
+  pub(crate) fn diff_tokens(
+    &self,
+    lhs_tokens: &[&[u8]],
+    rhs_tokens: &[&[u8]],
+  ) -> Vec<DiffItem> {
+    let mut unlimited = Budget::default();
+    let mut budget = self.budget.map(RefCell::borrow_mut);
+    let diffs = diff_seq_with_budget(
+      &self.normalization.token_keys(lhs_tokens),
+      &self.normalization.token_keys(rhs_tokens),
+      self.algorithm,
+      budget.as_deref_mut().unwrap_or(&mut unlimited),
+    );
+    cleanup_token_diffs(lhs_tokens, rhs_tokens, diffs)
+  }
+
+  /// Pairs each of `lhs_lines` with the line of `rhs_lines` it was edited
+  /// into, as `pair_lines` does, comparing their non-whitespace tokens.
+  /// Returns `None` if there are too many lines to pair or no budget left to
+  /// pair them with, in which case they are token diffed as one block
+  /// instead.
+  pub(crate) fn pair_lines(
+    &self,
+    lhs_lines: &[&[u8]],
+    rhs_lines: &[&[u8]],
+  ) -> Option<Vec<DiffItem>> {
+    let cost = lhs_lines.len().saturating_mul(rhs_lines.len());
+    if self.budget.is_some_and(|b| !b.borrow_mut().spend(cost)) {
+      return None;
+    }
+    pair_lines(&self.words(lhs_lines), &self.words(rhs_lines))
+  }
+
+  /// Returns the keys of the non-whitespace tokens of each line in `lines`.
+  fn words<'a>(&self, lines: &[&'a [u8]]) -> Vec<Vec<Cow<'a, [u8]>>> {
+    lines
+      .iter()
+      .map(|&l| {
+        self
+          .tokenize(&[l])
+          .into_iter()
+          .filter(|t| !is_whitespace_token(t))
+          .map(|t| self.normalization.token_key(t))
+          .collect()
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn token_diffs_spend_the_budget() {
+    // Two long lines with nothing but their first and last tokens in common.
+    let lhs: Vec<u8> = (0..5000)
+      .flat_map(|i| format!("{i} ").into_bytes())
+      .collect();
+    let rhs: Vec<u8> = (0..5000)
+      .rev()
+      .flat_map(|i| format!("{i} ").into_bytes())
+      .collect();
+    let budget = RefCell::new(Budget::new(Some(1000), None));
+    let differ = TokenDiffer {
+      budget: Some(&budget),
+      ..Default::default()
+    };
+    let lhs_tokens = differ.tokenize(&[&lhs]);
+    let rhs_tokens = differ.tokenize(&[&rhs]);
+    let diffs = differ.diff_tokens(&lhs_tokens, &rhs_tokens);
+    assert!(budget.borrow().exhausted());
+    assert_eq!(diffs.first().map(|d| d.lhs().start), Some(0));
+    assert!(differ.pair_lines(&[&lhs], &[&rhs]).is_none());
+  }
+}
@@ -0,0 +1,199 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::report::Hunk;
+use crate::report::Line;
+use crate::report::Op;
+use crate::report::OpKind;
+
+/// The version of the JSON output's schema, documented in `docs/json.md`.
+/// Bumped whenever a change could break existing readers.
+pub(crate) const VERSION: u32 = 1;
+
+/// Formats a record for one file pair as a single line of JSON. `fields`
+/// are already formatted as JSON. Records in JSON Lines carry the schema
+/// `version` themselves, since there's no document around them to.
+pub(crate) fn record(
+  kind: &str,
+  old_path: Option<&Path>,
+  new_path: Option<&Path>,
+  fields: &[(&str, String)],
+  version: bool,
+) -> String {
+  let mut out = String::from("{");
+  if version {
+    write!(out, "\"version\":{VERSION},").unwrap();
+  }
+  write!(
+    out,
+    "\"type\":{},\"old_path\":{},\"new_path\":{}",
+    quote(kind),
+    path(old_path),
+    path(new_path),
+  )
+  .unwrap();
+  for (name, value) in fields {
+    write!(out, ",{}:{value}", quote(name)).unwrap();
+  }
+  out.push('}');
+  out
+}
+
+pub(crate) fn hunks(hunks: &[Hunk]) -> String {
+  array(hunks.iter().map(|h| {
+    format!(
+      "{{\"old_start\":{},\"old_lines\":{},\"new_start\":{},\"new_lines\":{},\
+       \"ops\":{}}}",
+      h.old_start,
+      h.old_lines,
+      h.new_start,
+      h.new_lines,
+      array(h.ops.iter().map(op)),
+    )
+  }))
+}
+
+fn op(op: &Op) -> String {
+  let (kind, extra, highlighted) = match op.kind {
+    OpKind::Equal => ("equal", String::new(), false),
+    OpKind::Delete => ("delete", String::new(), false),
+    OpKind::Insert => ("insert", String::new(), false),
+    OpKind::Replace => ("replace", String::new(), true),
+    OpKind::MovedOut { to } => ("moved_out", format!(",\"to\":{to}"), true),
+    OpKind::MovedIn { from } => ("moved_in", format!(",\"from\":{from}"), true),
+  };
+  let lines =
+    |lines: &[Line]| array(lines.iter().map(|l| line(l, highlighted)));
+  format!(
+    "{{\"op\":{}{extra},\"old\":{},\"new\":{}}}",
+    quote(kind),
+    lines(&op.old),
+    lines(&op.new),
+  )
+}
+
+/// Formats `line`, with the byte ranges of its text that changed if it's
+/// `highlighted`.
+fn line(line: &Line, highlighted: bool) -> String {
+  let mut text = String::new();
+  let mut changed: Vec<(usize, usize)> = vec![];
+  for (piece, is_changed) in &line.pieces {
+    let start = text.len();
+    text.push_str(piece);
+    if !is_changed || piece.is_empty() {
+      continue;
+    }
+    match changed.last_mut() {
+      Some((_, end)) if *end == start => *end = text.len(),
+      _ => changed.push((start, text.len())),
+    }
+  }
+  let mut out = format!("{{\"line\":{},\"text\":{}", line.number, quote(&text));
+  if highlighted {
+    let ranges = array(changed.iter().map(|(s, e)| format!("[{s},{e}]")));
+    write!(out, ",\"changed\":{ranges}").unwrap();
+  }
+  if let Some(n) = line.paired_with {
+    write!(out, ",\"paired_with\":{n}").unwrap();
+  }
+  out.push('}');
+  out
+}
+
+fn array(items: impl Iterator<Item = String>) -> String {
+  format!("[{}]", items.collect::<Vec<_>>().join(","))
+}
+
+fn path(p: Option<&Path>) -> String {
+  p.map_or("null".into(), |p| quote(&p.to_string_lossy()))
+}
+
+/// Quotes `s` as a JSON string.
+pub(crate) fn quote(s: &str) -> String {
+  let mut out = String::with_capacity(s.len() + 2);
+  out.push('"');
+  for c in s.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      '\t' => out.push_str("\\t"),
+      c if c < ' ' => write!(out, "\\u{:04x}", c as u32).unwrap(),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn line<'a>(number: usize, pieces: &[(&'a str, bool)]) -> Line<'a> {
+    Line {
+      number,
+      pieces: pieces.iter().map(|&(t, c)| (t.into(), c)).collect(),
+      paired_with: None,
+    }
+  }
+
+  #[test]
+  fn quoting() {
+    assert_eq!(quote("plain"), r#""plain""#);
+    assert_eq!(quote("a\"b\\c"), r#""a\"b\\c""#);
+    assert_eq!(quote("\t\n\x1b"), r#""\t\n\u001b""#);
+    assert_eq!(quote("日本"), "\"日本\"");
+  }
+
+  #[test]
+  fn records() {
+    assert_eq!(
+      record("binary", Some(Path::new("a.bin")), None, &[], false),
+      r#"{"type":"binary","old_path":"a.bin","new_path":null}"#
+    );
+    assert_eq!(
+      record("error", None, None, &[("message", quote("oops"))], true),
+      r#"{"version":1,"type":"error","old_path":null,"new_path":null,"message":"oops"}"#
+    );
+  }
+
+  #[test]
+  fn changed_ranges() {
+    let mut old = line(3, &[("let ", false), ("x", true), (" = 1;", false)]);
+    old.paired_with = Some(4);
+    let h = Hunk {
+      old_start: 2,
+      old_lines: 2,
+      new_start: 2,
+      new_lines: 3,
+      ops: vec![
+        Op {
+          kind: OpKind::Equal,
+          old: vec![line(2, &[("{", false)])],
+          new: vec![line(2, &[("{", false)])],
+        },
+        Op {
+          kind: OpKind::Replace,
+          old: vec![old],
+          new: vec![
+            line(3, &[("new", true), ("", true), ("ish", true)]),
+            line(4, &[("let ", false), ("y", true), (" = 1;", false)]),
+          ],
+        },
+      ],
+    };
+    assert_eq!(
+      hunks(&[h]),
+      concat!(
+        r#"[{"old_start":2,"old_lines":2,"new_start":2,"new_lines":3,"ops":["#,
+        r#"{"op":"equal","old":[{"line":2,"text":"{"}],"new":[{"line":2,"text":"{"}]},"#,
+        r#"{"op":"replace","#,
+        r#""old":[{"line":3,"text":"let x = 1;","changed":[[4,5]],"paired_with":4}],"#,
+        r#""new":[{"line":3,"text":"newish","changed":[[0,6]]},"#,
+        r#"{"line":4,"text":"let y = 1;","changed":[[4,5]]}]}]}]"#,
+      )
+    );
+  }
+}
@@ -0,0 +1,255 @@
+use std::fs::File;
+use std::ops::Deref;
+use std::ops::Range;
+use std::path::Path;
+
+use memmap2::Mmap;
+
+use crate::tokens::split_lines;
+
+/// A rough upper bound on the memory needed per line while diffing, covering
+/// the line slices, their comparison keys and the diff algorithms' tables.
+const BYTES_PER_LINE: u64 = 128;
+
+/// The contents of a file, memory mapped where possible.
+pub enum Content {
+  Mapped(Mmap),
+  Owned(Vec<u8>),
+}
+
+impl Content {
+  /// Maps the file at `path`, reading it into memory instead if it can not be
+  /// mapped (e.g. stdin or a pipe).
+  pub fn open(path: &Path) -> std::io::Result<Content> {
+    if path == Path::new("-") {
+      let mut buffer = vec![];
+      std::io::Read::read_to_end(&mut std::io::stdin(), &mut buffer)?;
+      return Ok(Content::Owned(buffer));
+    }
+    let file = File::open(path)?;
+    if !file.metadata()?.is_file() {
+      let mut buffer = vec![];
+      std::io::Read::read_to_end(&mut &file, &mut buffer)?;
+      return Ok(Content::Owned(buffer));
+    }
+    // SAFETY: The mapping is only ever read. If another process truncates the
+    // file while it is mapped, reads may fault, which is the usual caveat of
+    // diffing files that are being written to.
+    Ok(Content::Mapped(unsafe { Mmap::map(&file)? }))
+  }
+
+  /// Bytes of this content held on the heap rather than in a mapping.
+  fn heap_size(&self) -> u64 {
+    match self {
+      Content::Mapped(_) => 0,
+      Content::Owned(v) => v.len() as u64,
+    }
+  }
+}
+
+impl Deref for Content {
+  type Target = [u8];
+
+  fn deref(&self) -> &[u8] {
+    match self {
+      Content::Mapped(m) => m,
+      Content::Owned(v) => v,
+    }
+  }
+}
+
+/// A file trimmed down to the lines that differ from its counterpart.
+pub struct Window {
+  content: Content,
+  /// The differing lines, as a byte range of `content`.
+  middle: Range<usize>,
+  /// How many lines come before `middle`.
+  first_line: usize,
+}
+
+impl Window {
+  /// Returns the differing lines with up to `context` unchanged lines on
+  /// either side, along with the index of the first returned line.
+  pub fn lines(&self, context: usize) -> (Vec<&[u8]>, usize) {
+    let (text, first_line) = self.text(context);
+    (split_lines(text), first_line)
+  }
+
+  /// Like `lines`, but returns the unsplit text of those lines, ending with
+  /// a line ending unless it is the end of a file without one.
+  pub fn text(&self, context: usize) -> (&[u8], usize) {
+    let (start, before) = back_lines(&self.content, self.middle.start, context);
+    let end = forward_lines(&self.content, self.middle.end, context);
+    (&self.content[start..end], self.first_line - before)
+  }
+
+  /// Whether the file's last line is missing its newline.
+  pub fn no_newline(&self) -> bool {
+    !self.content.is_empty() && !self.content.ends_with(b"\n")
+  }
+
+  /// How many common lines were trimmed off the start and end.
+  pub fn trimmed_lines(&self) -> usize {
+    self.first_line + count_lines(&self.content[self.middle.end..])
+  }
+
+  fn line_count(&self) -> u64 {
+    count_lines(&self.content[self.middle.clone()]) as u64 + 1
+  }
+}
+
+/// Trims the common leading and trailing lines off `lhs` and `rhs` without
+/// splitting them into lines.
+pub fn trim(lhs: Content, rhs: Content) -> (Window, Window) {
+  let max = lhs.len().min(rhs.len());
+  let mut start = std::iter::zip(lhs.iter(), rhs.iter())
+    .take_while(|(l, r)| l == r)
+    .count();
+  while !(is_line_start(&lhs, start) && is_line_start(&rhs, start)) {
+    start -= 1;
+  }
+
+  let mut suffix = std::iter::zip(lhs.iter().rev(), rhs.iter().rev())
+    .take(max - start)
+    .take_while(|(l, r)| l == r)
+    .count();
+  while suffix > 0
+    && !(is_line_start(&lhs, lhs.len() - suffix)
+      && is_line_start(&rhs, rhs.len() - suffix))
+  {
+    suffix -= 1;
+  }
+
+  let first_line = count_lines(&lhs[..start]);
+  let window = |content: Content| {
+    let middle = start..content.len() - suffix;
+    Window { content, middle, first_line }
+  };
+  (window(lhs), window(rhs))
+}
+
+/// Roughly how many bytes diffing `lhs` against `rhs` will take.
+pub fn estimate_memory(lhs: &Window, rhs: &Window) -> u64 {
+  lhs.content.heap_size()
+    + rhs.content.heap_size()
+    + (lhs.line_count() + rhs.line_count()) * BYTES_PER_LINE
+}
+
+/// Whether a line starts at `i`, following the line endings `split_lines`
+/// understands.
+fn is_line_start(content: &[u8], i: usize) -> bool {
+  match i.checked_sub(1).map(|p| content[p]) {
+    None | Some(b'\n') => true,
+    Some(b'\r') => content.get(i) != Some(&b'\n'),
+    _ => false,
+  }
+}
+
+/// Counts the line endings in `content`.
+fn count_lines(content: &[u8]) -> usize {
+  (0..content.len())
+    .filter(|&i| match content[i] {
+      b'\n' => true,
+      b'\r' => content.get(i + 1) != Some(&b'\n'),
+      _ => false,
+    })
+    .count()
+}
+
+/// Steps back up to `n` lines from the line starting at `i`, returning the new
+/// start and the number of lines stepped over.
+fn back_lines(content: &[u8], mut i: usize, n: usize) -> (usize, usize) {
+  let mut stepped = 0;
+  while stepped < n && i > 0 {
+    i -= 1;
+    while !is_line_start(content, i) {
+      i -= 1;
+    }
+    stepped += 1;
+  }
+  (i, stepped)
+}
+
+/// Steps forward up to `n` lines from the line starting at `i`.
+fn forward_lines(content: &[u8], mut i: usize, n: usize) -> usize {
+  for _ in 0..n {
+    if i >= content.len() {
+      break;
+    }
+    i += 1;
+    while i < content.len() && !is_line_start(content, i) {
+      i += 1;
+    }
+  }
+  i
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn windows(lhs: &[u8], rhs: &[u8]) -> (Window, Window) {
+    trim(Content::Owned(lhs.to_vec()), Content::Owned(rhs.to_vec()))
+  }
+
+  fn lines(w: &Window, context: usize) -> (Vec<&str>, usize) {
+    let (lines, first) = w.lines(context);
+    let lines = lines
+      .into_iter()
+      .map(|l| std::str::from_utf8(l).unwrap())
+      .collect();
+    (lines, first)
+  }
+
+  #[test]
+  fn trim_common_lines() {
+    let (l, r) = windows(b"a\nb\nc\nd\ne\n", b"a\nb\nX\nd\ne\n");
+    assert_eq!(lines(&l, 0), (vec!["c"], 2));
+    assert_eq!(lines(&r, 0), (vec!["X"], 2));
+    assert_eq!(lines(&l, 1), (vec!["b", "c", "d"], 1));
+    assert_eq!(l.trimmed_lines(), 4);
+    assert_eq!(lines(&r, 5), (vec!["a", "b", "X", "d", "e"], 0));
+  }
+
+  #[test]
+  fn trim_to_line_boundaries() {
+    // The common bytes "ab" and "\n" end and start mid-line.
+    let (l, r) = windows(b"x\nabc\ny\n", b"x\nabd\ny\n");
+    assert_eq!(lines(&l, 0), (vec!["abc"], 1));
+    assert_eq!(lines(&r, 0), (vec!["abd"], 1));
+  }
+
+  #[test]
+  fn trim_insertion() {
+    let (l, r) = windows(b"a\nb\n", b"a\nb\nb\n");
+    assert_eq!(lines(&l, 0), (vec![], 2));
+    assert_eq!(lines(&r, 0), (vec!["b"], 2));
+    assert_eq!(lines(&l, 1), (vec!["b"], 1));
+    assert_eq!(lines(&r, 1), (vec!["b", "b"], 1));
+  }
+
+  #[test]
+  fn trim_line_endings() {
+    let (l, r) = windows(b"a\r\nb\r\nc\r\n", b"a\r\nB\r\nc\r\n");
+    assert_eq!(lines(&l, 0), (vec!["b"], 1));
+    assert_eq!(lines(&r, 1), (vec!["a", "B", "c"], 0));
+
+    // "\r" alone ends a line, but not when a "\n" follows on one side.
+    let (l, r) = windows(b"a\rb\rc", b"a\r\nb\rc");
+    assert_eq!(lines(&l, 0), (vec!["a"], 0));
+    assert_eq!(lines(&r, 0), (vec!["a"], 0));
+  }
+
+  #[test]
+  fn trim_missing_newline() {
+    let (l, r) = windows(b"a\nb", b"a\nb\n");
+    assert_eq!(lines(&l, 0), (vec!["b"], 1));
+    assert_eq!(lines(&r, 0), (vec!["b"], 1));
+  }
+
+  #[test]
+  fn memory_estimate() {
+    let (l, r) = windows(b"a\nb\nc\n", b"a\nX\nY\nc\n");
+    assert_eq!(estimate_memory(&l, &r), 6 + 8 + (2 + 3) * BYTES_PER_LINE);
+  }
+}
@@ -1,35 +1,105 @@
+mod align;
+mod anchor;
+mod apply;
+mod cleanup;
 mod cluster;
+mod columns;
 mod diff;
+mod diff3;
 mod files;
+mod html;
 mod hunks;
+mod intraline;
+mod json;
+mod large;
+mod merge;
+mod moves;
+mod normalize;
+mod patch;
+mod pool;
 mod printer;
+mod report;
+mod similarity;
+mod slider;
+mod stats;
 mod styles;
 mod tokens;
+mod unified;
 
+pub use anchor::Anchor;
+pub use apply::ApplyOptions;
+pub use apply::FileReport;
+pub use apply::HunkStatus;
+pub use apply::apply;
 pub use cluster::ClusterEntry;
 pub use cluster::DiffCluster;
 pub use cluster::DiffSignature;
+pub use diff::Algorithm;
+pub use diff::Budget;
 pub use diff::DiffItem;
 pub use diff::diff;
+pub use diff::diff_anchored;
+pub use diff::diff_seq;
+pub use diff::diff_seq_pinned;
+pub use diff::diff_seq_with_budget;
+pub use diff3::Diff3Item;
+pub use diff3::diff3;
+pub use diff3::diff3_seq;
 pub use files::FilePairEvent;
+pub use files::WalkOptions;
 pub use files::walk_file_pairs;
+pub use files::walk_file_pairs_with;
+pub use hunks::Hunk;
+pub use large::Content;
+pub use large::Window;
+pub use merge::ConflictStyle;
+pub use merge::MergeOptions;
+pub use merge::Merged;
+pub use merge::merge;
+pub use moves::detect_moves;
+pub use moves::detect_moves_with_budget;
+pub use normalize::Normalization;
+pub use patch::FilePatch;
+pub use patch::Patch;
+pub use patch::PatchError;
+pub use patch::PatchHunk;
+pub use printer::Format;
 pub use printer::Printer;
+pub use slider::slide_boundaries;
+pub use stats::DiffStats;
+pub use stats::FileStats;
 pub use styles::Styles;
+pub use tokens::Intraline;
 pub use tokens::tokenize_lines;
+pub use tokens::tokenize_lines_canonical;
 
 use std::path::Path;
 
-pub fn cluster_files(lhs: &Path, rhs: &Path) -> Vec<DiffCluster> {
-  DiffCluster::cluster(walk_file_pairs(lhs, rhs))
+pub fn cluster_files(
+  lhs: &Path,
+  rhs: &Path,
+  algorithm: Algorithm,
+  intraline: Intraline,
+) -> Vec<DiffCluster> {
+  DiffCluster::cluster(walk_file_pairs(lhs, rhs), algorithm, intraline)
+}
+
+pub fn stats_files(
+  lhs: &Path,
+  rhs: &Path,
+  algorithm: Algorithm,
+  options: WalkOptions,
+) -> Vec<FileStats> {
+  DiffStats::for_events(walk_file_pairs_with(lhs, rhs, options), algorithm)
 }
 
 pub fn diff_files(
   p: &mut Printer,
   lhs: &Path,
   rhs: &Path,
+  options: WalkOptions,
 ) -> Result<(), Box<dyn std::error::Error>> {
-  for event in walk_file_pairs(lhs, rhs) {
-    p.print_file_pair_event(event)?;
-  }
+  let events = walk_file_pairs_with(lhs, rhs, options);
+  p.print_file_pair_events(events, options.jobs)?;
   Ok(())
 }
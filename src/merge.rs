@@ -0,0 +1,292 @@
+use crate::diff::Algorithm;
+use crate::diff3::Diff3Item;
+use crate::diff3::diff3_seq;
+use crate::tokens::Intraline;
+
+/// How conflicts are written out.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum ConflictStyle {
+  /// Both sides of the conflict.
+  #[default]
+  Merge,
+  /// Both sides and the base they started from.
+  Diff3,
+  /// Like diff3, moving lines both sides agree on out of the conflict.
+  Zdiff3,
+}
+
+/// Controls how `merge` combines and marks up changes.
+#[derive(Clone, Debug)]
+pub struct MergeOptions {
+  pub algorithm: Algorithm,
+  pub style: ConflictStyle,
+  /// How many `<`, `|`, `=` and `>` characters conflict markers have.
+  pub marker_size: usize,
+  /// Text after the ours, base and theirs conflict markers.
+  pub labels: [String; 3],
+}
+
+impl Default for MergeOptions {
+  fn default() -> MergeOptions {
+    MergeOptions {
+      algorithm: Algorithm::default(),
+      style: ConflictStyle::default(),
+      marker_size: 7,
+      labels: ["ours".into(), "base".into(), "theirs".into()],
+    }
+  }
+}
+
+/// The outcome of a merge.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Merged {
+  /// The merged content, with conflict markers around any conflicts.
+  pub content: Vec<u8>,
+  /// How many conflicts were marked.
+  pub conflicts: usize,
+}
+
+/// Merges the changes made from `base` to `ours` and from `base` to `theirs`.
+///
+/// Conflicting regions whose lines were each edited in place on both sides
+/// are merged token by token, and only marked as conflicts if both sides
+/// changed the same part of a line.
+pub fn merge(
+  base: &[u8],
+  ours: &[u8],
+  theirs: &[u8],
+  options: &MergeOptions,
+) -> Merged {
+  let base = split_lines_inclusive(base);
+  let ours = split_lines_inclusive(ours);
+  let theirs = split_lines_inclusive(theirs);
+  let mut m = Merger { options, out: vec![], conflicts: 0 };
+  for item in diff3_seq(&base, &ours, &theirs, options.algorithm) {
+    let (b, o, t) =
+      (&base[item.base()], &ours[item.ours()], &theirs[item.theirs()]);
+    match item {
+      Diff3Item::Stable { .. }
+      | Diff3Item::Ours { .. }
+      | Diff3Item::BothSame { .. } => m.push_lines(o),
+      Diff3Item::Theirs { .. } => m.push_lines(t),
+      Diff3Item::Conflict { .. } => match merge_tokens(b, o, t, options) {
+        Some(merged) => m.out.extend(merged),
+        None => m.push_conflict(b, o, t),
+      },
+    }
+  }
+  Merged { content: m.out, conflicts: m.conflicts }
+}
+
+struct Merger<'a> {
+  options: &'a MergeOptions,
+  out: Vec<u8>,
+  conflicts: usize,
+}
+
+impl Merger<'_> {
+  fn push_lines(&mut self, lines: &[&[u8]]) {
+    for l in lines {
+      self.out.extend_from_slice(l);
+    }
+  }
+
+  fn push_marker(&mut self, c: u8, label: &str) {
+    if self.out.last().is_some_and(|&b| b != b'\n' && b != b'\r') {
+      self.out.push(b'\n');
+    }
+    self
+      .out
+      .extend(std::iter::repeat_n(c, self.options.marker_size));
+    if !label.is_empty() {
+      self.out.push(b' ');
+      self.out.extend_from_slice(label.as_bytes());
+    }
+    self.out.push(b'\n');
+  }
+
+  fn push_conflict(
+    &mut self,
+    base: &[&[u8]],
+    ours: &[&[u8]],
+    theirs: &[&[u8]],
+  ) {
+    let (mut ours, mut theirs) = (ours, theirs);
+    let mut suffix: &[&[u8]] = &[];
+    if self.options.style == ConflictStyle::Zdiff3 {
+      let prefix = common_len(ours.iter(), theirs.iter());
+      self.push_lines(&ours[..prefix]);
+      (ours, theirs) = (&ours[prefix..], &theirs[prefix..]);
+      let n = common_len(ours.iter().rev(), theirs.iter().rev());
+      suffix = &ours[ours.len() - n..];
+      (ours, theirs) = (&ours[..ours.len() - n], &theirs[..theirs.len() - n]);
+    }
+
+    let [ours_label, base_label, theirs_label] = &self.options.labels;
+    self.push_marker(b'<', ours_label);
+    self.push_lines(ours);
+    if self.options.style != ConflictStyle::Merge {
+      self.push_marker(b'|', base_label);
+      self.push_lines(base);
+    }
+    self.push_marker(b'=', "");
+    self.push_lines(theirs);
+    self.push_marker(b'>', theirs_label);
+    self.push_lines(suffix);
+    self.conflicts += 1;
+  }
+}
+
+/// Merges a conflict line by line at the token level. Only works when both
+/// sides edited the same number of lines in place and no line has
+/// overlapping edits.
+fn merge_tokens(
+  base: &[&[u8]],
+  ours: &[&[u8]],
+  theirs: &[&[u8]],
+  options: &MergeOptions,
+) -> Option<Vec<u8>> {
+  if base.len() != ours.len() || base.len() != theirs.len() {
+    return None;
+  }
+  let mut out = vec![];
+  for ((&b, &o), &t) in base.iter().zip(ours).zip(theirs) {
+    let b = Intraline::Token.tokenize(&[b], false);
+    let o = Intraline::Token.tokenize(&[o], false);
+    let t = Intraline::Token.tokenize(&[t], false);
+    for item in diff3_seq(&b, &o, &t, options.algorithm) {
+      let tokens = match item {
+        Diff3Item::Conflict { .. } => return None,
+        Diff3Item::Theirs { theirs, .. } => &t[theirs],
+        _ => &o[item.ours()],
+      };
+      out.extend(tokens.concat());
+    }
+  }
+  Some(out)
+}
+
+fn common_len<'a>(
+  lhs: impl Iterator<Item = &'a &'a [u8]>,
+  rhs: impl Iterator<Item = &'a &'a [u8]>,
+) -> usize {
+  lhs.zip(rhs).take_while(|(l, r)| l == r).count()
+}
+
+/// Splits `content` into lines, keeping their line endings so the merged
+/// result can be pieced back together exactly.
+fn split_lines_inclusive(content: &[u8]) -> Vec<&[u8]> {
+  let mut lines = vec![];
+  let mut start = 0;
+  let mut i = 0;
+  while i < content.len() {
+    match content[i] {
+      b'\r' if content.get(i + 1) == Some(&b'\n') => i += 1,
+      b'\r' | b'\n' => {}
+      _ => {
+        i += 1;
+        continue;
+      }
+    }
+    i += 1;
+    lines.push(&content[start..i]);
+    start = i;
+  }
+  if start < content.len() {
+    lines.push(&content[start..]);
+  }
+  lines
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn merge_with(
+    style: ConflictStyle,
+    base: &str,
+    ours: &str,
+    theirs: &str,
+  ) -> (String, usize) {
+    let options = MergeOptions { style, ..Default::default() };
+    let m =
+      merge(base.as_bytes(), ours.as_bytes(), theirs.as_bytes(), &options);
+    (String::from_utf8(m.content).unwrap(), m.conflicts)
+  }
+
+  #[test]
+  fn split_inclusive() {
+    assert_eq!(
+      split_lines_inclusive(b"a\nb\r\nc\rd"),
+      &[b"a\n" as &[u8], b"b\r\n", b"c\r", b"d"]
+    );
+    assert!(split_lines_inclusive(b"").is_empty());
+  }
+
+  #[test]
+  fn clean_merge() {
+    assert_eq!(
+      merge_with(
+        ConflictStyle::Merge,
+        "a\nb\nc\nd\n",
+        "A\nb\nc\nd\n",
+        "a\nb\nc\nD\ne\n",
+      ),
+      ("A\nb\nc\nD\ne\n".into(), 0)
+    );
+  }
+
+  #[test]
+  fn conflict_styles() {
+    let (base, ours, theirs) = ("a\nb\nc\n", "a\nx\ny\nc\n", "a\nx\nz\nc\n");
+    assert_eq!(
+      merge_with(ConflictStyle::Merge, base, ours, theirs),
+      ("a\n<<<<<<< ours\nx\ny\n=======\nx\nz\n>>>>>>> theirs\nc\n".into(), 1)
+    );
+    assert_eq!(
+      merge_with(ConflictStyle::Diff3, base, ours, theirs),
+      (
+        "a\n<<<<<<< ours\nx\ny\n||||||| base\nb\n=======\nx\nz\n>>>>>>> \
+         theirs\nc\n"
+          .into(),
+        1
+      )
+    );
+    assert_eq!(
+      merge_with(ConflictStyle::Zdiff3, base, ours, theirs),
+      (
+        "a\nx\n<<<<<<< ours\ny\n||||||| base\nb\n=======\nz\n>>>>>>> \
+         theirs\nc\n"
+          .into(),
+        1
+      )
+    );
+  }
+
+  #[test]
+  fn disjoint_token_edits_merge() {
+    assert_eq!(
+      merge_with(
+        ConflictStyle::Merge,
+        "f(a, b);\n",
+        "f(x, b);\n",
+        "f(a, y);\n"
+      ),
+      ("f(x, y);\n".into(), 0)
+    );
+    // Both sides changing the same token still conflicts.
+    assert_eq!(
+      merge_with(ConflictStyle::Merge, "f(a);\n", "f(x);\n", "f(y);\n").1,
+      1
+    );
+  }
+
+  #[test]
+  fn missing_newline_before_marker() {
+    assert_eq!(
+      merge_with(ConflictStyle::Merge, "a", "b", "c"),
+      ("<<<<<<< ours\nb\n=======\nc\n>>>>>>> theirs\n".into(), 1)
+    );
+  }
+}
@@ -0,0 +1,309 @@
+use std::hash::Hash;
+use std::iter::zip;
+use std::ops::Range;
+
+use crate::diff::Algorithm;
+use crate::diff::Budget;
+use crate::diff::DiffItem;
+use crate::diff::DiffItem::*;
+use crate::diff::Diffs;
+use crate::diff::diff_seq_with_budget;
+
+/// Blocks with fewer matching lines than this are not reported as moved.
+const MIN_MOVED_LINES: usize = 3;
+
+/// Up to this many consecutive edited lines are tolerated inside a moved block.
+const MAX_MOVED_GAP: usize = 2;
+
+/// Each pass of move detection re-diffs the lines left over, so stop after
+/// this many even if moves crossing yet more moves remain.
+const MAX_PASSES: usize = 8;
+
+/// A moved block, as a range of deleted lines on the lhs and the range of
+/// inserted lines on the rhs that it reappears as.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Block {
+  lhs: Range<usize>,
+  rhs: Range<usize>,
+}
+
+/// Finds runs of deleted lines that reappear as inserted lines in a different
+/// mutation and splits them out of `diffs` into `Moved` items.
+///
+/// Blocks may contain small edits, so the two halves of a move do not need to
+/// be the same length.
+pub fn detect_moves<T: Hash + Eq>(
+  lhs: &[T],
+  rhs: &[T],
+  diffs: &[DiffItem],
+  algorithm: Algorithm,
+) -> Vec<DiffItem> {
+  detect_moves_with_budget(lhs, rhs, diffs, algorithm, &mut Budget::default())
+}
+
+/// Like `detect_moves`, but spends the diffs it runs from `budget`, and stops
+/// looking for more moves once it is spent.
+pub fn detect_moves_with_budget<T: Hash + Eq>(
+  lhs: &[T],
+  rhs: &[T],
+  diffs: &[DiffItem],
+  algorithm: Algorithm,
+  budget: &mut Budget,
+) -> Vec<DiffItem> {
+  let mut blocks: Vec<Block> = vec![];
+  let mut claimed_lhs = vec![false; lhs.len()];
+  let mut claimed_rhs = vec![false; rhs.len()];
+  // Each pass can only find blocks that appear in the same order on both
+  // sides, so keep going until crossing moves are exhausted too.
+  for _ in 0..MAX_PASSES {
+    if budget.exhausted() {
+      break;
+    }
+    let claimed = (&claimed_lhs[..], &claimed_rhs[..]);
+    let found = find_blocks(lhs, rhs, diffs, claimed, algorithm, budget);
+    if found.is_empty() {
+      break;
+    }
+    for b in &found {
+      claimed_lhs[b.lhs.clone()].fill(true);
+      claimed_rhs[b.rhs.clone()].fill(true);
+    }
+    blocks.extend(found);
+  }
+  if blocks.is_empty() {
+    return diffs.to_vec();
+  }
+
+  let mut sources: Vec<_> = blocks
+    .iter()
+    .map(|b| (b.lhs.clone(), b.rhs.clone()))
+    .collect();
+  let mut destinations: Vec<_> = blocks
+    .iter()
+    .map(|b| (b.rhs.clone(), b.lhs.clone()))
+    .collect();
+  sources.sort_by_key(|(r, _)| r.start);
+  destinations.sort_by_key(|(r, _)| r.start);
+
+  let mut out = Diffs::default();
+  for d in diffs {
+    match d {
+      Match { lhs, .. } => out.add_match(lhs.len()),
+      Moved { lhs, rhs, counterpart } => {
+        out.add_moved(lhs.len(), rhs.len(), counterpart.clone())
+      }
+      Mutation { lhs, rhs } => {
+        for (range, counterpart) in segments(lhs, &sources) {
+          match counterpart {
+            Some(c) => out.add_moved(range.len(), 0, c),
+            None => out.add_mutation(range.len(), 0),
+          }
+        }
+        for (range, counterpart) in segments(rhs, &destinations) {
+          match counterpart {
+            Some(c) => out.add_moved(0, range.len(), c),
+            None => out.add_mutation(0, range.len()),
+          }
+        }
+      }
+    }
+  }
+  out.vec
+}
+
+/// Runs one round of move detection over the lhs and rhs lines that are not
+/// yet `claimed` by a block.
+fn find_blocks<T: Hash + Eq>(
+  lhs: &[T],
+  rhs: &[T],
+  diffs: &[DiffItem],
+  (claimed_lhs, claimed_rhs): (&[bool], &[bool]),
+  algorithm: Algorithm,
+  budget: &mut Budget,
+) -> Vec<Block> {
+  // Every still unclaimed deleted or inserted line, along with the index of
+  // the mutation it belongs to.
+  let mut deleted: Vec<(usize, usize)> = vec![];
+  let mut inserted: Vec<(usize, usize)> = vec![];
+  for (n, d) in diffs.iter().enumerate() {
+    if let Mutation { lhs, rhs } = d {
+      deleted.extend(lhs.clone().filter(|&i| !claimed_lhs[i]).map(|i| (i, n)));
+      inserted.extend(rhs.clone().filter(|&i| !claimed_rhs[i]).map(|i| (i, n)));
+    }
+  }
+  if deleted.len() < MIN_MOVED_LINES || inserted.len() < MIN_MOVED_LINES {
+    return vec![];
+  }
+  let lhs_lines: Vec<&T> = deleted.iter().map(|&(i, _)| &lhs[i]).collect();
+  let rhs_lines: Vec<&T> = inserted.iter().map(|&(i, _)| &rhs[i]).collect();
+
+  // Pairings between lines of different mutations, as indices into `deleted`
+  // and `inserted`. Lines paired within a single mutation are plain edits.
+  let pairs = diff_seq_with_budget(&lhs_lines, &rhs_lines, algorithm, budget)
+    .into_iter()
+    .filter_map(|d| match d {
+      Match { lhs, rhs } => Some(zip(lhs, rhs)),
+      _ => None,
+    })
+    .flatten()
+    .filter(|&(l, r)| deleted[l].1 != inserted[r].1);
+
+  let mut blocks = vec![];
+  let mut current: Option<(Block, usize, (usize, usize))> = None;
+  for (l, r) in pairs {
+    if let Some((block, matched, (last_l, last_r))) = &mut current
+      && deleted[l].1 == deleted[*last_l].1
+      && inserted[r].1 == inserted[*last_r].1
+      && l - *last_l <= MAX_MOVED_GAP + 1
+      && r - *last_r <= MAX_MOVED_GAP + 1
+      && deleted[l].0 - deleted[*last_l].0 == l - *last_l
+      && inserted[r].0 - inserted[*last_r].0 == r - *last_r
+    {
+      block.lhs.end = deleted[l].0 + 1;
+      block.rhs.end = inserted[r].0 + 1;
+      *matched += 1;
+      (*last_l, *last_r) = (l, r);
+      continue;
+    }
+    if let Some((block, matched, _)) = current.take()
+      && matched >= MIN_MOVED_LINES
+    {
+      blocks.push(block);
+    }
+    let (i, j) = (deleted[l].0, inserted[r].0);
+    current = Some((Block { lhs: i..i + 1, rhs: j..j + 1 }, 1, (l, r)));
+  }
+  if let Some((block, matched, _)) = current
+    && matched >= MIN_MOVED_LINES
+  {
+    blocks.push(block);
+  }
+  blocks
+}
+
+/// Splits `range` into consecutive pieces, pairing the ones covered by a block
+/// in `moved` with that block's counterpart.
+fn segments(
+  range: &Range<usize>,
+  moved: &[(Range<usize>, Range<usize>)],
+) -> Vec<(Range<usize>, Option<Range<usize>>)> {
+  let mut out = vec![];
+  let mut pos = range.start;
+  for (r, counterpart) in moved {
+    if r.start < range.start || r.end > range.end {
+      continue;
+    }
+    if pos < r.start {
+      out.push((pos..r.start, None));
+    }
+    out.push((r.clone(), Some(counterpart.clone())));
+    pos = r.end;
+  }
+  if pos < range.end {
+    out.push((pos..range.end, None));
+  }
+  out
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::diff::diff;
+  use crate::tokens::split_lines;
+
+  fn moves(lhs: &[u8], rhs: &[u8]) -> Vec<DiffItem> {
+    let lhs = split_lines(lhs);
+    let rhs = split_lines(rhs);
+    let diffs = diff(&lhs, &rhs, Algorithm::Patience);
+    detect_moves(&lhs, &rhs, &diffs, Algorithm::Patience)
+  }
+
+  #[test]
+  fn moved_block() {
+    assert_eq!(
+      moves(
+        include_bytes!("testdata/old/move.txt"),
+        include_bytes!("testdata/new/move.txt"),
+      ),
+      &[
+        Moved { lhs: 0..7, rhs: 0..0, counterpart: 9..16 },
+        Mutation { lhs: 7..8, rhs: 0..0 },
+        Match { lhs: 8..16, rhs: 0..8 },
+        Mutation { lhs: 16..16, rhs: 8..9 },
+        Moved {
+          lhs: 16..16,
+          rhs: 9..16,
+          counterpart: 0..7
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn moved_block_with_edit() {
+    assert_eq!(
+      moves(
+        b"a\nb\nc\nd\nk1\nk2\nk3\nk4\nk5\n",
+        b"k1\nk2\nk3\nk4\nk5\na\nB\nc\nd\n",
+      ),
+      &[
+        Moved { lhs: 0..4, rhs: 0..0, counterpart: 5..9 },
+        Match { lhs: 4..9, rhs: 0..5 },
+        Moved { lhs: 9..9, rhs: 5..9, counterpart: 0..4 },
+      ]
+    );
+  }
+
+  #[test]
+  fn short_blocks_are_not_moves() {
+    assert_eq!(
+      moves(b"a\nb\nx\ny\nz\n", b"x\ny\nz\na\nb\n"),
+      &[
+        Mutation { lhs: 0..2, rhs: 0..0 },
+        Match { lhs: 2..5, rhs: 0..3 },
+        Mutation { lhs: 5..5, rhs: 3..5 },
+      ]
+    );
+  }
+
+  #[test]
+  fn edits_in_place_are_not_moves() {
+    assert_eq!(
+      moves(b"a\nb\nc\nd\nk\n", b"a\nB\nc\nD\nk\n"),
+      diff(
+        &split_lines(b"a\nb\nc\nd\nk\n"),
+        &split_lines(b"a\nB\nc\nD\nk\n"),
+        Algorithm::Patience
+      ),
+    );
+  }
+
+  #[test]
+  fn crossing_moves() {
+    let diffs = moves(
+      b"a1\na2\na3\nb1\nb2\nb3\nk\nc1\nc2\nc3\n",
+      b"c1\nc2\nc3\nk\nb1\nb2\nb3\na1\na2\na3\n",
+    );
+    let moved: Vec<_> =
+      diffs.iter().filter(|d| matches!(d, Moved { .. })).collect();
+    assert_eq!(moved.len(), 4, "{diffs:?}");
+  }
+
+  #[test]
+  fn stops_when_out_of_budget() {
+    let lhs = b"a1\na2\na3\nb1\nb2\nb3\nk\nc1\nc2\nc3\n";
+    let rhs = b"c1\nc2\nc3\nk\nb1\nb2\nb3\na1\na2\na3\n";
+    let (lhs, rhs) = (split_lines(lhs), split_lines(rhs));
+    let diffs = diff(&lhs, &rhs, Algorithm::Patience);
+    let mut budget = Budget::new(Some(0), None);
+    let moves = detect_moves_with_budget(
+      &lhs,
+      &rhs,
+      &diffs,
+      Algorithm::Patience,
+      &mut budget,
+    );
+    assert!(budget.exhausted());
+    assert!(!moves.iter().any(|d| matches!(d, Moved { .. })));
+  }
+}
@@ -0,0 +1,241 @@
+use std::borrow::Cow;
+
+use unicode_normalization::UnicodeNormalization;
+
+use crate::tokens::Intraline;
+use crate::tokens::is_whitespace_token;
+
+/// Controls which differences are ignored when comparing lines.
+///
+/// Lines are diffed by a normalized comparison key while the original bytes
+/// are still used for display. Defaults to comparing lines exactly.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct Normalization {
+  /// Ignore all whitespace.
+  pub ignore_all_space: bool,
+  /// Treat every run of whitespace as a single space and ignore whitespace at
+  /// the end of lines.
+  pub ignore_space_change: bool,
+  /// Ignore whitespace at the end of lines.
+  pub ignore_space_at_eol: bool,
+  /// Suppress hunks whose changes only add or remove blank lines.
+  pub ignore_blank_lines: bool,
+  /// Ignore differences in letter case.
+  pub ignore_case: bool,
+  /// Treat canonically equivalent Unicode text, such as NFC and NFD encodings
+  /// of the same characters, as equal.
+  pub unicode_normalize: bool,
+}
+
+impl Normalization {
+  /// Returns the key `line` is compared by.
+  pub fn key<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+    self.fold(self.strip_space(line))
+  }
+
+  fn strip_space<'a>(&self, line: &'a [u8]) -> Cow<'a, [u8]> {
+    if !self.ignore_all_space
+      && !self.ignore_space_change
+      && !self.ignore_space_at_eol
+    {
+      return Cow::Borrowed(line);
+    }
+    if self.ignore_all_space {
+      return Cow::Owned(
+        line
+          .iter()
+          .copied()
+          .filter(|b| !b.is_ascii_whitespace())
+          .collect(),
+      );
+    }
+    let line = line.trim_ascii_end();
+    if !self.ignore_space_change {
+      return Cow::Borrowed(line);
+    }
+    let mut key = Vec::with_capacity(line.len());
+    for &b in line {
+      if !b.is_ascii_whitespace() {
+        key.push(b);
+      } else if key.last().is_none_or(|&l| l != b' ') {
+        key.push(b' ');
+      }
+    }
+    Cow::Owned(key)
+  }
+
+  /// Applies case folding and Unicode normalization. Content that is not
+  /// valid UTF-8 only has its ASCII letters folded.
+  fn fold<'a>(&self, s: Cow<'a, [u8]>) -> Cow<'a, [u8]> {
+    if !self.ignore_case && !self.unicode_normalize {
+      return s;
+    }
+    let Ok(text) = std::str::from_utf8(&s) else {
+      return if self.ignore_case {
+        Cow::Owned(s.to_ascii_lowercase())
+      } else {
+        s
+      };
+    };
+    if text.is_ascii() {
+      return if self.ignore_case {
+        Cow::Owned(s.to_ascii_lowercase())
+      } else {
+        s
+      };
+    }
+    let mut text: String = if self.unicode_normalize {
+      text.nfc().collect()
+    } else {
+      text.to_owned()
+    };
+    if self.ignore_case {
+      text = text.to_lowercase();
+    }
+    Cow::Owned(text.into_bytes())
+  }
+
+  /// Returns the keys for every line in `lines`.
+  pub fn keys<'a>(&self, lines: &[&'a [u8]]) -> Vec<Cow<'a, [u8]>> {
+    lines.iter().map(|l| self.key(l)).collect()
+  }
+
+  /// Returns the key `token` is compared by when diffing within lines.
+  pub fn token_key<'a>(&self, token: &'a [u8]) -> Cow<'a, [u8]> {
+    let ignore_space = self.ignore_all_space || self.ignore_space_change;
+    if ignore_space && token != b"\n" && is_whitespace_token(token) {
+      return Cow::Borrowed(b" ");
+    }
+    self.fold(Cow::Borrowed(token))
+  }
+
+  /// Splits `lines` into tokens at the `intraline` granularity. With
+  /// `unicode_normalize` set, precomposed and decomposed forms of the same
+  /// text split into the same tokens.
+  pub fn tokenize<'a>(
+    &self,
+    lines: &[&'a [u8]],
+    intraline: Intraline,
+  ) -> Vec<&'a [u8]> {
+    intraline.tokenize(lines, self.unicode_normalize)
+  }
+
+  /// Returns the keys for every token in `tokens`.
+  pub fn token_keys<'a>(&self, tokens: &[&'a [u8]]) -> Vec<Cow<'a, [u8]>> {
+    tokens.iter().map(|t| self.token_key(t)).collect()
+  }
+}
+
+/// Whether `line` is empty or contains only whitespace.
+pub fn is_blank(line: &[u8]) -> bool {
+  line.iter().all(|b| b.is_ascii_whitespace())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::diff::Algorithm;
+  use crate::diff::DiffItem;
+  use crate::diff::diff_seq;
+
+  #[test]
+  fn exact() {
+    let n = Normalization::default();
+    assert_eq!(n.key(b" a  b "), b" a  b " as &[u8]);
+  }
+
+  #[test]
+  fn ignore_all_space() {
+    let n = Normalization {
+      ignore_all_space: true,
+      ..Default::default()
+    };
+    assert_eq!(n.key(b" a \t b "), b"ab" as &[u8]);
+    assert_eq!(n.key(b"ab"), n.key(b"a b"));
+  }
+
+  #[test]
+  fn ignore_space_change() {
+    let n = Normalization {
+      ignore_space_change: true,
+      ..Default::default()
+    };
+    assert_eq!(n.key(b"  a \t b  "), b" a b" as &[u8]);
+    assert_eq!(n.key(b"a  b"), n.key(b"a\tb "));
+    assert_ne!(n.key(b"ab"), n.key(b"a b"));
+  }
+
+  #[test]
+  fn ignore_space_at_eol() {
+    let n = Normalization {
+      ignore_space_at_eol: true,
+      ..Default::default()
+    };
+    assert_eq!(n.key(b" a  b \t"), b" a  b" as &[u8]);
+    assert_ne!(n.key(b"a b"), n.key(b"a  b"));
+  }
+
+  #[test]
+  fn ignore_case() {
+    let n = Normalization { ignore_case: true, ..Default::default() };
+    assert_eq!(n.key(b"Hello World"), n.key(b"hello world"));
+    assert_eq!(n.key("STRASSE Ä".as_bytes()), n.key("strasse ä".as_bytes()));
+    assert_eq!(n.key(b"ABC\xff"), b"abc\xff" as &[u8]);
+    assert_ne!(n.key("é".as_bytes()), n.key("e\u{301}".as_bytes()));
+  }
+
+  #[test]
+  fn unicode_normalize() {
+    let n = Normalization {
+      unicode_normalize: true,
+      ..Default::default()
+    };
+    assert_eq!(n.key("café".as_bytes()), n.key("cafe\u{301}".as_bytes()));
+    assert_ne!(n.key(b"Cafe"), n.key(b"cafe"));
+  }
+
+  #[test]
+  fn unicode_normalize_tokens() {
+    let n = Normalization {
+      ignore_case: true,
+      unicode_normalize: true,
+      ..Default::default()
+    };
+    let lhs = n.tokenize(&["Café au lait".as_bytes()], Intraline::Token);
+    let rhs = n.tokenize(&["cafe\u{301} AU lait".as_bytes()], Intraline::Token);
+    assert_eq!(n.token_keys(&lhs), n.token_keys(&rhs));
+  }
+
+  #[test]
+  fn token_keys() {
+    let n = Normalization {
+      ignore_space_change: true,
+      ..Default::default()
+    };
+    assert_eq!(
+      n.token_keys(&[b"x", b"  ", b"\n", b"\t"]),
+      &[b"x" as &[u8], b" ", b"\n", b" "]
+    );
+  }
+
+  #[test]
+  fn diff_keys() {
+    let n = Normalization {
+      ignore_space_change: true,
+      ..Default::default()
+    };
+    let lhs = [b"fn f() {" as &[u8], b"  x  += 1;", b"}"];
+    let rhs = [b"fn f() {" as &[u8], b"\tx += 1; ", b"}"];
+    assert_eq!(
+      diff_seq(&n.keys(&lhs), &n.keys(&rhs), Algorithm::Patience),
+      &[DiffItem::Match { lhs: 0..3, rhs: 0..3 }]
+    );
+  }
+
+  #[test]
+  fn blank() {
+    assert!(is_blank(b""));
+    assert!(is_blank(b" \t"));
+    assert!(!is_blank(b" x"));
+  }
+}
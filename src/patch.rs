@@ -0,0 +1,934 @@
+use std::fmt;
+use std::io::Write;
+use std::iter::zip;
+use std::ops::Range;
+use std::path::PathBuf;
+
+use crate::diff::DiffItem;
+use crate::diff::DiffItem::*;
+use crate::diff::Diffs;
+use crate::hunks::Hunk;
+
+/// A parsed unified diff, changing one or more files.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Patch {
+  pub files: Vec<FilePatch>,
+}
+
+/// The changes to a single file.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct FilePatch {
+  /// The path before the change as written in the patch, or `None` if the
+  /// file is created.
+  pub old_path: Option<PathBuf>,
+  /// The path after the change as written in the patch, or `None` if the
+  /// file is deleted.
+  pub new_path: Option<PathBuf>,
+  pub old_mode: Option<u32>,
+  pub new_mode: Option<u32>,
+  /// Whether this came from a `diff --git` header, whose paths start with an
+  /// `a/` or `b/` prefix.
+  pub git: bool,
+  pub rename: bool,
+  pub copy: bool,
+  /// Whether the patch only says that the (binary) files differ, or holds a
+  /// git binary patch, which is not parsed.
+  pub binary: bool,
+  pub hunks: Vec<PatchHunk>,
+}
+
+/// One hunk of a file patch.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PatchHunk {
+  /// The hunk's changes, with line ranges into the whole old and new files.
+  pub hunk: Hunk,
+  /// The old lines the hunk covers, starting at line `hunk.lhs().start`.
+  pub old_lines: Vec<Vec<u8>>,
+  /// The new lines the hunk covers, starting at line `hunk.rhs().start`.
+  pub new_lines: Vec<Vec<u8>>,
+  /// Whether the last old line is missing its newline.
+  pub old_no_newline: bool,
+  /// Whether the last new line is missing its newline.
+  pub new_no_newline: bool,
+  /// The text after the closing `@@`, usually the enclosing function.
+  pub heading: Vec<u8>,
+}
+
+/// Where and why a patch could not be parsed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PatchError {
+  /// 1-based line of the patch.
+  pub line: usize,
+  /// 1-based byte column within the line.
+  pub column: usize,
+  pub message: String,
+}
+
+impl fmt::Display for PatchError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "line {}, column {}: {}", self.line, self.column, self.message)
+  }
+}
+
+impl std::error::Error for PatchError {}
+
+type Result<T> = std::result::Result<T, PatchError>;
+
+/// An error message with the 0-based column of the line it is about.
+type ColumnResult<T> = std::result::Result<T, (usize, String)>;
+
+impl Patch {
+  /// Parses `input`, skipping any text outside file patches such as commit
+  /// messages or `Only in` lines.
+  pub fn parse(input: &[u8]) -> Result<Patch> {
+    let mut lines: Vec<&[u8]> = input.split(|&b| b == b'\n').collect();
+    if lines.last().is_some_and(|l| l.is_empty()) {
+      lines.pop();
+    }
+    let mut parser = Parser { lines, pos: 0 };
+    let mut files = vec![];
+    while let Some(line) = parser.peek() {
+      if line.starts_with(b"diff --git ") {
+        files.push(parser.git_file()?);
+      } else if line.starts_with(b"--- ") {
+        files.push(parser.file(FilePatch::default())?);
+      } else if line.starts_with(b"+++ ") {
+        return Err(parser.error(0, "`+++` line without a `---` line"));
+      } else if line.starts_with(b"@@ ") {
+        return Err(parser.error(0, "hunk without a file header"));
+      } else {
+        parser.pos += 1;
+      }
+    }
+    Ok(Patch { files })
+  }
+
+  /// The patch that undoes this one.
+  pub fn reverse(&self) -> Patch {
+    Patch {
+      files: self.files.iter().map(FilePatch::reverse).collect(),
+    }
+  }
+}
+
+impl FilePatch {
+  pub fn reverse(&self) -> FilePatch {
+    FilePatch {
+      old_path: self.new_path.clone(),
+      new_path: self.old_path.clone(),
+      old_mode: self.new_mode,
+      new_mode: self.old_mode,
+      hunks: self.hunks.iter().map(PatchHunk::reverse).collect(),
+      ..self.clone()
+    }
+  }
+}
+
+impl PatchHunk {
+  pub fn reverse(&self) -> PatchHunk {
+    let diffs = self.hunk.diffs.iter().map(|d| match d.clone() {
+      Match { lhs, rhs } => Match { lhs: rhs, rhs: lhs },
+      Mutation { lhs, rhs } => Mutation { lhs: rhs, rhs: lhs },
+      Moved { lhs, rhs, counterpart } => {
+        Moved { lhs: rhs, rhs: lhs, counterpart }
+      }
+    });
+    PatchHunk {
+      hunk: Hunk { diffs: diffs.collect() },
+      old_lines: self.new_lines.clone(),
+      new_lines: self.old_lines.clone(),
+      old_no_newline: self.new_no_newline,
+      new_no_newline: self.old_no_newline,
+      heading: self.heading.clone(),
+    }
+  }
+
+  /// Writes the hunk in unified format, starting with its `@@` line.
+  pub fn write_unified(&self, out: &mut Vec<u8>) {
+    let (old, new) = (self.hunk.lhs(), self.hunk.rhs());
+    // An empty side names the line before it, and a count of 1 is implied.
+    let range = |r: &Range<usize>| match r.len() {
+      0 => format!("{},0", r.start),
+      1 => format!("{}", r.start + 1),
+      n => format!("{},{n}", r.start + 1),
+    };
+    write!(out, "@@ -{} +{} @@", range(&old), range(&new)).unwrap();
+    if !self.heading.is_empty() {
+      out.push(b' ');
+      out.extend_from_slice(&self.heading);
+    }
+    out.push(b'\n');
+
+    let mut line = |prefix: u8, text: &[u8], no_newline: bool| {
+      out.push(prefix);
+      out.extend_from_slice(text);
+      out.push(b'\n');
+      if no_newline {
+        out.extend_from_slice(b"\\ No newline at end of file\n");
+      }
+    };
+    let old_line = |i: usize| &self.old_lines[i - old.start];
+    let new_line = |i: usize| &self.new_lines[i - new.start];
+    for d in &self.hunk.diffs {
+      match d {
+        Match { lhs, rhs } => {
+          for (i, j) in zip(lhs.clone(), rhs.clone()) {
+            let old_no_newline = i + 1 == old.end && self.old_no_newline;
+            let new_no_newline = j + 1 == new.end && self.new_no_newline;
+            if old_no_newline == new_no_newline {
+              line(b' ', old_line(i), old_no_newline);
+            } else {
+              // Lines that only differ in their newline aren't context, so
+              // write them as a change like `diff -u` does.
+              line(b'-', old_line(i), old_no_newline);
+              line(b'+', new_line(j), new_no_newline);
+            }
+          }
+        }
+        // A patch can't say where lines moved from, so moves are written as
+        // the deletions and insertions they are made of.
+        Mutation { lhs, rhs } | Moved { lhs, rhs, .. } => {
+          for i in lhs.clone() {
+            let no_newline = i + 1 == old.end && self.old_no_newline;
+            line(b'-', old_line(i), no_newline);
+          }
+          for i in rhs.clone() {
+            let no_newline = i + 1 == new.end && self.new_no_newline;
+            line(b'+', new_line(i), no_newline);
+          }
+        }
+      }
+    }
+  }
+}
+
+struct Parser<'a> {
+  lines: Vec<&'a [u8]>,
+  pos: usize,
+}
+
+impl<'a> Parser<'a> {
+  fn peek(&self) -> Option<&'a [u8]> {
+    self.lines.get(self.pos).copied()
+  }
+
+  /// An error at byte `column` (0-based) of the current line.
+  fn error(&self, column: usize, message: impl Into<String>) -> PatchError {
+    PatchError {
+      line: self.pos + 1,
+      column: column + 1,
+      message: message.into(),
+    }
+  }
+
+  /// Parses a `diff --git` header with its extended header lines, followed
+  /// by the usual `---`/`+++` lines and hunks if the contents changed.
+  fn git_file(&mut self) -> Result<FilePatch> {
+    let line = self.peek().unwrap();
+    let paths = &line[b"diff --git ".len()..];
+    let (old, new) = split_git_paths(paths).ok_or_else(|| {
+      self.error(b"diff --git ".len(), "expected `a/PATH b/PATH`")
+    })?;
+    let mut file = FilePatch {
+      old_path: Some(old),
+      new_path: Some(new),
+      git: true,
+      ..Default::default()
+    };
+    self.pos += 1;
+
+    while let Some(line) = self.peek() {
+      let mode = |at: usize| -> Result<u32> {
+        parse_mode(&line[at..])
+          .ok_or_else(|| self.error(at, "expected an octal file mode"))
+      };
+      let path = |prefix: &[u8], side: &str| {
+        let mut p = PathBuf::from(side);
+        p.push(unquote(&line[prefix.len()..]));
+        Some(p)
+      };
+      if line.starts_with(b"old mode ") {
+        file.old_mode = Some(mode(b"old mode ".len())?);
+      } else if line.starts_with(b"new mode ") {
+        file.new_mode = Some(mode(b"new mode ".len())?);
+      } else if line.starts_with(b"deleted file mode ") {
+        file.old_mode = Some(mode(b"deleted file mode ".len())?);
+        file.new_path = None;
+      } else if line.starts_with(b"new file mode ") {
+        file.new_mode = Some(mode(b"new file mode ".len())?);
+        file.old_path = None;
+      } else if line.starts_with(b"rename from ") {
+        file.rename = true;
+        file.old_path = path(b"rename from ", "a");
+      } else if line.starts_with(b"rename to ") {
+        file.rename = true;
+        file.new_path = path(b"rename to ", "b");
+      } else if line.starts_with(b"copy from ") {
+        file.copy = true;
+        file.old_path = path(b"copy from ", "a");
+      } else if line.starts_with(b"copy to ") {
+        file.copy = true;
+        file.new_path = path(b"copy to ", "b");
+      } else if let Some(rest) = line.strip_prefix(b"index ") {
+        // `index <old>..<new> <mode>` gives the mode when it is unchanged.
+        if let Some(i) = rest.iter().position(|&b| b == b' ') {
+          let m = mode(b"index ".len() + i + 1)?;
+          file.old_mode.get_or_insert(m);
+          file.new_mode.get_or_insert(m);
+        }
+      } else if line.starts_with(b"similarity index ")
+        || line.starts_with(b"dissimilarity index ")
+      {
+        // Only informative.
+      } else if line.starts_with(b"Binary files ") {
+        file.binary = true;
+      } else if line.starts_with(b"GIT binary patch") {
+        file.binary = true;
+        self.pos += 1;
+        while self.peek().is_some_and(|l| !l.starts_with(b"diff --git ")) {
+          self.pos += 1;
+        }
+        return Ok(file);
+      } else if line.starts_with(b"--- ") {
+        return self.file(file);
+      } else {
+        break;
+      }
+      self.pos += 1;
+    }
+    Ok(file)
+  }
+
+  /// Parses `---` and `+++` lines and the hunks after them into `file`.
+  fn file(&mut self, mut file: FilePatch) -> Result<FilePatch> {
+    file.old_path = self.file_path(b"--- ")?;
+    self.pos += 1;
+    file.new_path = self.file_path(b"+++ ")?;
+    self.pos += 1;
+    while self.peek().is_some_and(|l| l.starts_with(b"@@ ")) {
+      file.hunks.push(self.hunk()?);
+    }
+    Ok(file)
+  }
+
+  fn file_path(&self, prefix: &[u8]) -> Result<Option<PathBuf>> {
+    let line =
+      self
+        .peek()
+        .filter(|l| l.starts_with(prefix))
+        .ok_or_else(|| {
+          let prefix = String::from_utf8_lossy(prefix);
+          self.error(0, format!("expected a `{}` line", prefix.trim_end()))
+        })?;
+    let path = &line[prefix.len()..];
+    // GNU diff follows the path with a tab and a timestamp.
+    let path = match path.iter().position(|&b| b == b'\t') {
+      Some(tab) => &path[..tab],
+      None => path.strip_suffix(b"\r").unwrap_or(path),
+    };
+    if path.is_empty() {
+      return Err(self.error(prefix.len(), "expected a path"));
+    }
+    if path == b"/dev/null" {
+      return Ok(None);
+    }
+    Ok(Some(PathBuf::from(unquote(path))))
+  }
+
+  fn hunk(&mut self) -> Result<PatchHunk> {
+    let line = self.peek().unwrap();
+    let (old, new, heading) = parse_hunk_header(line)
+      .map_err(|(column, message)| self.error(column, message))?;
+    self.pos += 1;
+
+    let mut hunk = PatchHunk {
+      heading: heading.to_vec(),
+      ..Default::default()
+    };
+    let mut diffs = Diffs::default();
+    let (mut old_left, mut new_left) = (old.len(), new.len());
+    let mut last = b' ';
+    while old_left > 0 || new_left > 0 || self.peek_marker() {
+      let Some(line) = self.peek() else {
+        return Err(self.error(
+          0,
+          format!(
+            "patch ends inside a hunk, expected {old_left} more old and \
+             {new_left} more new lines"
+          ),
+        ));
+      };
+      let (kind, text) = match line.split_first() {
+        Some((&kind, text)) => (kind, text),
+        // Some tools strip the space off blank context lines.
+        None => (b' ', line),
+      };
+      let fits = match kind {
+        b' ' => old_left > 0 && new_left > 0,
+        b'-' => old_left > 0,
+        b'+' => new_left > 0,
+        b'\\' => true,
+        _ => {
+          return Err(self.error(
+            0,
+            "expected a hunk line starting with ' ', '-', '+' or '\\'",
+          ));
+        }
+      };
+      if !fits {
+        return Err(self.error(
+          0,
+          format!(
+            "hunk has more lines than its header's -{},{} +{},{}",
+            old.start + 1,
+            old.len(),
+            new.start + 1,
+            new.len()
+          ),
+        ));
+      }
+      match kind {
+        b' ' => {
+          diffs.add_match(1);
+          hunk.old_lines.push(text.to_vec());
+          hunk.new_lines.push(text.to_vec());
+          (old_left, new_left) = (old_left - 1, new_left - 1);
+        }
+        b'-' => {
+          diffs.add_mutation(1, 0);
+          hunk.old_lines.push(text.to_vec());
+          old_left -= 1;
+        }
+        b'+' => {
+          diffs.add_mutation(0, 1);
+          hunk.new_lines.push(text.to_vec());
+          new_left -= 1;
+        }
+        _ => {
+          hunk.old_no_newline |= last != b'+';
+          hunk.new_no_newline |= last != b'-';
+        }
+      }
+      last = kind;
+      self.pos += 1;
+    }
+    hunk.hunk = Hunk {
+      diffs: diffs
+        .vec
+        .into_iter()
+        .map(|d| offset(d, old.start, new.start))
+        .collect(),
+    };
+    Ok(hunk)
+  }
+
+  /// Whether the next line is a `\ No newline at end of file` marker.
+  fn peek_marker(&self) -> bool {
+    self.peek().is_some_and(|l| l.starts_with(b"\\"))
+  }
+}
+
+/// Parses a `@@ -old +new @@ heading` line into its line ranges and heading,
+/// reporting errors as 0-based columns.
+fn parse_hunk_header(
+  line: &[u8],
+) -> ColumnResult<(Range<usize>, Range<usize>, &[u8])> {
+  let mut cursor = Cursor { line, pos: 0 };
+  for &c in b"@@ -" {
+    cursor.expect(c)?;
+  }
+  let old = cursor.range()?;
+  for &c in b" +" {
+    cursor.expect(c)?;
+  }
+  let new = cursor.range()?;
+  for &c in b" @@" {
+    cursor.expect(c)?;
+  }
+  let heading = line[cursor.pos..].strip_prefix(b" ").unwrap_or_default();
+  Ok((old, new, heading))
+}
+
+/// What a line of a unified diff is, for reading one a line at a time.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum PatchLine<'a> {
+  HunkHeader,
+  Context(&'a [u8]),
+  Removed(&'a [u8]),
+  Added(&'a [u8]),
+  /// A `\ No newline at end of file` marker.
+  NoNewline,
+  /// Anything outside a hunk, like file headers or commit messages.
+  Other,
+}
+
+/// Tells hunk lines from the text around them by counting down the lines
+/// each hunk header promises.
+#[derive(Default)]
+pub(crate) struct PatchLines {
+  old_left: usize,
+  new_left: usize,
+  /// Whether the last line was a line of a hunk, which is the only place a
+  /// `\ No newline at end of file` marker can go.
+  in_hunk: bool,
+}
+
+impl PatchLines {
+  /// Classifies the next line, without its newline.
+  pub(crate) fn next<'a>(&mut self, line: &'a [u8]) -> PatchLine<'a> {
+    let kind = self.classify(line);
+    self.in_hunk = matches!(
+      kind,
+      PatchLine::Context(_) | PatchLine::Removed(_) | PatchLine::Added(_)
+    );
+    kind
+  }
+
+  fn classify<'a>(&mut self, line: &'a [u8]) -> PatchLine<'a> {
+    if self.in_hunk && line.starts_with(b"\\") {
+      return PatchLine::NoNewline;
+    }
+    if self.old_left == 0 && self.new_left == 0 {
+      if let Ok((old, new, _)) = parse_hunk_header(line) {
+        (self.old_left, self.new_left) = (old.len(), new.len());
+        return PatchLine::HunkHeader;
+      }
+      return PatchLine::Other;
+    }
+    match line.split_first() {
+      Some((b' ', text)) if self.old_left > 0 && self.new_left > 0 => {
+        self.old_left -= 1;
+        self.new_left -= 1;
+        PatchLine::Context(text)
+      }
+      // Some tools strip the space off blank context lines.
+      None if self.old_left > 0 && self.new_left > 0 => {
+        self.old_left -= 1;
+        self.new_left -= 1;
+        PatchLine::Context(line)
+      }
+      Some((b'-', text)) if self.old_left > 0 => {
+        self.old_left -= 1;
+        PatchLine::Removed(text)
+      }
+      Some((b'+', text)) if self.new_left > 0 => {
+        self.new_left -= 1;
+        PatchLine::Added(text)
+      }
+      // A truncated hunk; start looking for the next one.
+      _ => {
+        *self = PatchLines::default();
+        PatchLine::Other
+      }
+    }
+  }
+}
+
+/// Reads the numbers of a hunk header, reporting errors as 0-based columns.
+struct Cursor<'a> {
+  line: &'a [u8],
+  pos: usize,
+}
+
+impl Cursor<'_> {
+  fn expect(&mut self, c: u8) -> ColumnResult<()> {
+    if self.line.get(self.pos) != Some(&c) {
+      return Err((self.pos, format!("expected '{}'", c as char)));
+    }
+    self.pos += 1;
+    Ok(())
+  }
+
+  fn number(&mut self) -> ColumnResult<usize> {
+    let start = self.pos;
+    while self.line.get(self.pos).is_some_and(u8::is_ascii_digit) {
+      self.pos += 1;
+    }
+    std::str::from_utf8(&self.line[start..self.pos])
+      .unwrap()
+      .parse()
+      .map_err(|_| (start, "expected a line number".into()))
+  }
+
+  /// Parses `start[,count]` into 0-based line indices. A hunk side without
+  /// lines names the line it comes after.
+  fn range(&mut self) -> ColumnResult<Range<usize>> {
+    let start_col = self.pos;
+    let start = self.number()?;
+    let count = if self.line.get(self.pos) == Some(&b',') {
+      self.pos += 1;
+      self.number()?
+    } else {
+      1
+    };
+    if count == 0 {
+      return Ok(start..start);
+    }
+    if start == 0 {
+      return Err((start_col, "line numbers start at 1".into()));
+    }
+    Ok(start - 1..start - 1 + count)
+  }
+}
+
+/// Moves `d` down by `lhs` and `rhs` lines.
+pub(crate) fn offset(d: DiffItem, lhs: usize, rhs: usize) -> DiffItem {
+  let shift = |r: Range<usize>, by: usize| r.start + by..r.end + by;
+  match d {
+    Match { lhs: l, rhs: r } => {
+      Match { lhs: shift(l, lhs), rhs: shift(r, rhs) }
+    }
+    Mutation { lhs: l, rhs: r } => {
+      Mutation { lhs: shift(l, lhs), rhs: shift(r, rhs) }
+    }
+    Moved { lhs: l, rhs: r, counterpart } => {
+      // The counterpart is on the side the moved lines are missing from.
+      let counterpart = if l.is_empty() {
+        shift(counterpart, lhs)
+      } else {
+        shift(counterpart, rhs)
+      };
+      Moved {
+        lhs: shift(l, lhs),
+        rhs: shift(r, rhs),
+        counterpart,
+      }
+    }
+  }
+}
+
+fn parse_mode(s: &[u8]) -> Option<u32> {
+  let s = std::str::from_utf8(s).ok()?.trim_end();
+  u32::from_str_radix(s, 8).ok()
+}
+
+/// Splits the paths of a `diff --git` line. Unquoted paths may contain
+/// spaces, so the split is where the two paths name the same file, falling
+/// back to the first ` b/`.
+fn split_git_paths(s: &[u8]) -> Option<(PathBuf, PathBuf)> {
+  let s = s.strip_suffix(b"\r").unwrap_or(s);
+  if s.starts_with(b"\"") {
+    let end = quoted_end(s)?;
+    let rest = s[end..].strip_prefix(b" ")?;
+    return Some((unquote(&s[..end]).into(), unquote(rest).into()));
+  }
+  let splits: Vec<_> = (0..s.len())
+    .filter(|&i| s[i..].starts_with(b" b/") || s[i..].starts_with(b" \""))
+    .collect();
+  let at = splits
+    .iter()
+    .copied()
+    .find(|&i| s.get(2..i) == s.get(i + 3..))
+    .or(splits.first().copied())?;
+  Some((unquote(&s[..at]).into(), unquote(&s[at + 1..]).into()))
+}
+
+/// The end of the C-style quoted string `s` starts with.
+fn quoted_end(s: &[u8]) -> Option<usize> {
+  let mut i = 1;
+  while i < s.len() {
+    match s[i] {
+      b'\\' => i += 2,
+      b'"' => return Some(i + 1),
+      _ => i += 1,
+    }
+  }
+  None
+}
+
+/// Decodes a path git quoted because of special characters, or returns it as
+/// is.
+fn unquote(s: &[u8]) -> String {
+  let Some(inner) = s.strip_prefix(b"\"").and_then(|s| s.strip_suffix(b"\""))
+  else {
+    return String::from_utf8_lossy(s).into_owned();
+  };
+  let mut out = vec![];
+  let mut i = 0;
+  while i < inner.len() {
+    if inner[i] != b'\\' || i + 1 == inner.len() {
+      out.push(inner[i]);
+      i += 1;
+      continue;
+    }
+    let c = inner[i + 1];
+    i += 2;
+    out.push(match c {
+      b'a' => 7,
+      b'b' => 8,
+      b't' => b'\t',
+      b'n' => b'\n',
+      b'v' => 11,
+      b'f' => 12,
+      b'r' => b'\r',
+      b'0'..=b'7' => {
+        let digits = inner[i - 1..]
+          .iter()
+          .take(3)
+          .take_while(|d| d.is_ascii_digit());
+        let n = digits.clone().count();
+        let v = digits.fold(0u32, |v, d| v * 8 + (d - b'0') as u32);
+        i += n - 1;
+        v as u8
+      }
+      c => c,
+    });
+  }
+  String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn path(p: &str) -> Option<PathBuf> {
+    Some(PathBuf::from(p))
+  }
+
+  fn lines(ls: &[&str]) -> Vec<Vec<u8>> {
+    ls.iter().map(|l| l.as_bytes().to_vec()).collect()
+  }
+
+  #[test]
+  fn gnu() {
+    let patch = Patch::parse(
+      b"diff -ru old/a.txt new/a.txt
+--- old/a.txt\t2024-01-01 00:00:00.000000000 +0000
++++ new/a.txt\t2024-01-02 00:00:00.000000000 +0000
+@@ -1,4 +1,4 @@ fn main
+ one
+-two
++TWO
+ three
+
+@@ -10,0 +11,2 @@
++x
++y
+Only in new: b.txt
+",
+    )
+    .unwrap();
+    let [file] = &patch.files[..] else { panic!() };
+    assert_eq!(file.old_path, path("old/a.txt"));
+    assert_eq!(file.new_path, path("new/a.txt"));
+    assert!(!file.git);
+    assert_eq!(file.hunks.len(), 2);
+
+    let h = &file.hunks[0];
+    assert_eq!(h.heading, b"fn main");
+    assert_eq!(
+      h.hunk.diffs,
+      &[
+        Match { lhs: 0..1, rhs: 0..1 },
+        Mutation { lhs: 1..2, rhs: 1..2 },
+        Match { lhs: 2..4, rhs: 2..4 },
+      ]
+    );
+    assert_eq!(h.old_lines, lines(&["one", "two", "three", ""]));
+    assert_eq!(h.new_lines, lines(&["one", "TWO", "three", ""]));
+
+    let h = &file.hunks[1];
+    assert_eq!(h.hunk.diffs, &[Mutation { lhs: 10..10, rhs: 10..12 }]);
+    assert_eq!((h.hunk.lhs(), h.hunk.rhs()), (10..10, 10..12));
+  }
+
+  #[test]
+  fn git() {
+    let patch = Patch::parse(
+      b"commit message
+diff --git a/run.sh b/run.sh
+old mode 100644
+new mode 100755
+diff --git a/old name.txt b/new name.txt
+similarity index 90%
+rename from old name.txt
+rename to new name.txt
+index 1234567..89abcde 100644
+--- a/old name.txt
++++ b/new name.txt
+@@ -1 +1 @@
+-a
+\\ No newline at end of file
++b
+diff --git a/gone b/gone
+deleted file mode 100644
+index 1234567..0000000
+--- a/gone
++++ /dev/null
+@@ -1 +0,0 @@
+-x
+diff --git \"a/tab\\there\" \"b/tab\\there\"
+new file mode 100644
+index 0000000..e69de29
+diff --git a/img.png b/img.png
+GIT binary patch
+literal 0
+HcmV?d00001
+
+diff --git a/c b/c
+index 1234567..89abcde 100644
+--- a/c
++++ b/c
+@@ -1 +1 @@
+-x
++y
+\\ No newline at end of file
+",
+    )
+    .unwrap();
+    let files = &patch.files;
+    assert_eq!(files.len(), 6);
+
+    assert_eq!(files[0].old_path, path("a/run.sh"));
+    assert_eq!(
+      (files[0].old_mode, files[0].new_mode),
+      (Some(0o100644), Some(0o100755))
+    );
+    assert!(files[0].hunks.is_empty());
+
+    assert!(files[1].rename && files[1].git);
+    assert_eq!(files[1].old_path, path("a/old name.txt"));
+    assert_eq!(files[1].new_path, path("b/new name.txt"));
+    assert_eq!(files[1].new_mode, Some(0o100644));
+    let h = &files[1].hunks[0];
+    assert!(h.old_no_newline && !h.new_no_newline);
+
+    assert_eq!(
+      (files[2].new_path.clone(), files[2].old_mode),
+      (None, Some(0o100644))
+    );
+    assert_eq!(
+      files[2].hunks[0].hunk.diffs,
+      &[Mutation { lhs: 0..1, rhs: 0..0 }]
+    );
+
+    assert_eq!(files[3].old_path, None);
+    assert_eq!(files[3].new_path, path("b/tab\there"));
+
+    assert!(files[4].binary);
+
+    let h = &files[5].hunks[0];
+    assert!(!h.old_no_newline && h.new_no_newline);
+  }
+
+  #[test]
+  fn moves_are_written_as_changes() {
+    let hunk = PatchHunk {
+      hunk: Hunk {
+        diffs: vec![
+          Moved { lhs: 0..1, rhs: 0..0, counterpart: 1..2 },
+          Match { lhs: 1..2, rhs: 0..1 },
+          Moved { lhs: 2..2, rhs: 1..2, counterpart: 0..1 },
+        ],
+      },
+      old_lines: lines(&["a", "b"]),
+      new_lines: lines(&["b", "a"]),
+      ..Default::default()
+    };
+    let mut out = vec![];
+    hunk.write_unified(&mut out);
+    assert_eq!(out, b"@@ -1,2 +1,2 @@\n-a\n b\n+a\n");
+
+    let reversed = hunk.reverse();
+    assert_eq!(
+      reversed.hunk.diffs[0],
+      Moved { lhs: 0..0, rhs: 0..1, counterpart: 1..2 }
+    );
+    let mut out = vec![];
+    reversed.write_unified(&mut out);
+    assert_eq!(out, b"@@ -1,2 +1,2 @@\n+a\n b\n-a\n");
+  }
+
+  #[test]
+  fn split_paths() {
+    let split = |s: &str| {
+      let (a, b) = split_git_paths(s.as_bytes()).unwrap();
+      (a.display().to_string(), b.display().to_string())
+    };
+    assert_eq!(split("a/x b/y"), ("a/x".into(), "b/y".into()));
+    assert_eq!(split("a/x b/y b/x b/y"), ("a/x b/y".into(), "b/x b/y".into()));
+    assert_eq!(
+      split("\"a/\\303\\251\" b/e"),
+      ("a/\u{e9}".into(), "b/e".into())
+    );
+  }
+
+  #[test]
+  fn errors() {
+    let error = |s: &str| {
+      let e = Patch::parse(s.as_bytes()).unwrap_err();
+      (e.line, e.column, e.message)
+    };
+    assert_eq!(
+      error("--- a\n+++ b\n@@ -1,x +1 @@\n"),
+      (3, 7, "expected a line number".into())
+    );
+    assert_eq!(
+      error("--- a\n+++ b\n@@ -1 +1 @\n"),
+      (3, 11, "expected '@'".into())
+    );
+    assert_eq!(
+      error("--- a\n+++ b\n@@ -1 +1 @@\n-a\n*b\n"),
+      (5, 1, "expected a hunk line starting with ' ', '-', '+' or '\\'".into())
+    );
+    assert_eq!(
+      error("--- a\n+++ b\n@@ -1 +1 @@\n-a\n-b\n"),
+      (5, 1, "hunk has more lines than its header's -1,1 +1,1".into())
+    );
+    assert_eq!(
+      error("--- a\n+++ b\n@@ -1,2 +1 @@\n-a\n"),
+      (
+        5,
+        1,
+        "patch ends inside a hunk, expected 1 more old and 1 more new \
+              lines"
+          .into()
+      )
+    );
+    assert_eq!(error("--- a\nx\n"), (2, 1, "expected a `+++` line".into()));
+    assert_eq!(
+      error("diff --git a/x b/x\nold mode 64z\n"),
+      (2, 10, "expected an octal file mode".into())
+    );
+    assert_eq!(
+      error("@@ -1 +1 @@\n"),
+      (1, 1, "hunk without a file header".into())
+    );
+  }
+
+  #[test]
+  fn patch_lines() {
+    let mut lines = PatchLines::default();
+    let text = concat!(
+      "commit 123\n",
+      "\\ not a marker\n",
+      "\n",
+      "    - a list\n",
+      "@@ -1,2 +1,2 @@ f\n",
+      " a\n",
+      "-b\n",
+      "+c\n",
+      "\\ No newline at end of file\n",
+      "\\ nor this\n",
+      "-not in a hunk\n",
+    );
+    let kinds: Vec<_> =
+      text.lines().map(|l| lines.next(l.as_bytes())).collect();
+    assert_eq!(
+      kinds,
+      &[
+        PatchLine::Other,
+        PatchLine::Other,
+        PatchLine::Other,
+        PatchLine::Other,
+        PatchLine::HunkHeader,
+        PatchLine::Context(b"a"),
+        PatchLine::Removed(b"b"),
+        PatchLine::Added(b"c"),
+        PatchLine::NoNewline,
+        PatchLine::Other,
+        PatchLine::Other,
+      ]
+    );
+  }
+}
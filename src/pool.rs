@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+use std::panic::AssertUnwindSafe;
+use std::panic::catch_unwind;
+use std::panic::resume_unwind;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::mpsc::Receiver;
+use std::sync::mpsc::Sender;
+use std::sync::mpsc::channel;
+
+/// How many items per worker may be waiting or in progress before `is_full`.
+const QUEUE_PER_JOB: usize = 4;
+
+/// Runs a function over items on a pool of threads, handing the results back
+/// in the order the items were sent.
+pub(crate) struct OrderedPool<T, U> {
+  items: Sender<(usize, T)>,
+  /// Results, or the panics of the items `f` panicked on.
+  results: Receiver<(usize, std::thread::Result<U>)>,
+  /// Results that arrived before those of earlier items.
+  pending: BTreeMap<usize, U>,
+  jobs: usize,
+  sent: usize,
+  returned: usize,
+}
+
+impl<T: Send + 'static, U: Send + 'static> OrderedPool<T, U> {
+  /// Starts `jobs` threads running `f`. They exit once the pool is dropped.
+  /// If `f` panics, `next` panics with the same payload.
+  pub(crate) fn new(
+    jobs: usize,
+    f: impl Fn(T) -> U + Send + Sync + 'static,
+  ) -> OrderedPool<T, U> {
+    let jobs = jobs.max(1);
+    let (items, items_rx) = channel::<(usize, T)>();
+    let (results_tx, results) = channel();
+    let items_rx = Arc::new(Mutex::new(items_rx));
+    let f = Arc::new(f);
+    for _ in 0..jobs {
+      let items_rx = Arc::clone(&items_rx);
+      let results_tx = results_tx.clone();
+      let f = Arc::clone(&f);
+      std::thread::spawn(move || {
+        loop {
+          let next = items_rx.lock().unwrap().recv();
+          let Ok((i, item)) = next else {
+            break;
+          };
+          // Hand panics back to `next`, since waiting for a result that
+          // never comes would hang.
+          let result = catch_unwind(AssertUnwindSafe(|| f(item)));
+          if results_tx.send((i, result)).is_err() {
+            break;
+          }
+        }
+      });
+    }
+    OrderedPool {
+      items,
+      results,
+      pending: BTreeMap::new(),
+      jobs,
+      sent: 0,
+      returned: 0,
+    }
+  }
+
+  /// Whether enough items are queued to keep every worker busy.
+  pub(crate) fn is_full(&self) -> bool {
+    self.sent - self.returned >= self.jobs * QUEUE_PER_JOB
+  }
+
+  pub(crate) fn send(&mut self, item: T) {
+    // If every worker has died, `next` reports it.
+    let _ = self.items.send((self.sent, item));
+    self.sent += 1;
+  }
+
+  /// Waits for the result of the oldest item not yet returned. Returns `None`
+  /// once every item sent has been returned.
+  pub(crate) fn next(&mut self) -> Option<U> {
+    if self.returned == self.sent {
+      return None;
+    }
+    loop {
+      if let Some(result) = self.pending.remove(&self.returned) {
+        self.returned += 1;
+        return Some(result);
+      }
+      let (i, result) = self.results.recv().expect("worker threads exited");
+      match result {
+        Ok(result) => self.pending.insert(i, result),
+        Err(panic) => resume_unwind(panic),
+      };
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn results_keep_order() {
+    let mut pool = OrderedPool::new(4, |i: u64| {
+      // Make later items finish first.
+      std::thread::sleep(std::time::Duration::from_millis(20 - i));
+      i * i
+    });
+    let mut out = vec![];
+    for i in 0..20 {
+      if pool.is_full() {
+        out.push(pool.next().unwrap());
+      }
+      pool.send(i);
+    }
+    while let Some(r) = pool.next() {
+      out.push(r);
+    }
+    assert_eq!(out, (0..20).map(|i| i * i).collect::<Vec<_>>());
+  }
+
+  #[test]
+  #[should_panic(expected = "bad item")]
+  fn panics_reach_the_caller() {
+    let mut pool = OrderedPool::new(4, |i: u64| {
+      if i == 3 {
+        panic!("bad item");
+      }
+      i
+    });
+    for i in 0..8 {
+      pool.send(i);
+    }
+    while pool.next().is_some() {}
+  }
+}
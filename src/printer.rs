@@ -1,28 +1,115 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::VecDeque;
 use std::error::Error;
+use std::io::BufRead;
 use std::io::Result;
 use std::io::Write;
+use std::iter::zip;
 use std::path::Path;
 use std::path::PathBuf;
+use std::time::Duration;
 
+use diff::Algorithm;
+use diff::Budget;
 use diff::DiffItem;
 use diff::DiffItem::*;
 use diff::Side;
 use owo_colors::OwoColorize;
 use owo_colors::Style;
 
+use crate::anchor::Anchor;
+use crate::anchor::anchored_pairs;
 use crate::cluster::DiffCluster;
+use crate::columns::Spans;
+use crate::columns::layout;
 use crate::diff;
+use crate::diff::diff_seq_pinned;
 use crate::files::FilePairEvent;
+use crate::html;
 use crate::hunks::Hunk;
+use crate::intraline::TokenDiffer;
+use crate::json;
+use crate::moves::detect_moves_with_budget;
+use crate::normalize::Normalization;
+use crate::normalize::is_blank;
+use crate::patch::PatchLine;
+use crate::patch::PatchLines;
+use crate::pool::OrderedPool;
+use crate::report;
+use crate::report::OpKind;
+use crate::slider::slide_boundaries;
 use crate::styles::Styles;
-use crate::tokenize_lines;
+use crate::tokens::Intraline;
 use crate::tokens::split_lines;
+use crate::unified;
+use crate::unified::Headers;
+use crate::unified::patch_lines;
+
+/// What a `Printer` writes.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Format {
+  /// Colored diffs that highlight the edits within lines.
+  #[default]
+  Color,
+  /// Plain unified diffs with git style headers, for `git apply` or
+  /// `patch -p1`.
+  Unified,
+  /// Plain unified diffs with modification times, like `diff -u` writes.
+  GnuUnified,
+  /// A JSON document describing every file pair, following the schema in
+  /// `docs/json.md`.
+  ///
+  /// Only `Printer::print_file_pair_events` writes the whole document.
+  Json,
+  /// The same records as a JSON document, one per line as each file pair is
+  /// diffed.
+  JsonLines,
+  /// A self-contained HTML report with an index of the files and collapsible
+  /// colored diffs of each.
+  ///
+  /// Only `Printer::print_file_pair_events` and `Printer::print_clusters`
+  /// write the whole report.
+  Html,
+}
+
+impl Format {
+  fn is_json(self) -> bool {
+    matches!(self, Format::Json | Format::JsonLines)
+  }
+}
 
 pub struct Printer<'a> {
-  styles: Styles,
   writer: &'a mut dyn Write,
+  config: Config,
+  /// What is left of the budget of the file being printed, which the token
+  /// diffs and move detection within it spend from too.
+  budget: RefCell<Budget>,
+}
+
+/// How a `Printer` diffs and prints, everything but where it prints to.
+#[derive(Clone)]
+struct Config {
+  styles: Styles,
   context: usize,
   common_prefix: PathBuf,
+  algorithm: Algorithm,
+  detect_moves: bool,
+  anchors: Vec<Anchor>,
+  indent_heuristic: bool,
+  max_cost: Option<u64>,
+  timeout: Option<Duration>,
+  intraline: Intraline,
+  normalization: Normalization,
+  flag_normalized: bool,
+  format: Format,
+  /// The trees being compared, which unified diffs name files relative to.
+  roots: (PathBuf, PathBuf),
+  /// How many terminal columns side-by-side output fills, if it's on.
+  side_by_side: Option<usize>,
+  wrap: bool,
+  line_numbers: bool,
 }
 
 impl<'a> Printer<'a> {
@@ -32,23 +119,210 @@ impl<'a> Printer<'a> {
     common_prefix: PathBuf,
   ) -> Printer<'a> {
     Printer {
-      styles: Styles::simple(),
       writer,
-      context,
-      common_prefix,
+      config: Config {
+        styles: Styles::simple(),
+        context,
+        common_prefix,
+        algorithm: Algorithm::default(),
+        detect_moves: false,
+        anchors: vec![],
+        indent_heuristic: true,
+        max_cost: None,
+        timeout: None,
+        intraline: Intraline::default(),
+        normalization: Normalization::default(),
+        flag_normalized: false,
+        format: Format::default(),
+        roots: Default::default(),
+        side_by_side: None,
+        wrap: false,
+        line_numbers: false,
+      },
+      budget: RefCell::default(),
     }
   }
 
+  /// Use `algorithm` for both line and token level diffs.
+  pub fn with_algorithm(mut self, algorithm: Algorithm) -> Printer<'a> {
+    self.config.algorithm = algorithm;
+    self
+  }
+
+  /// Highlight blocks of lines that moved instead of showing them as unrelated
+  /// deletions and insertions.
+  pub fn with_moves(mut self, detect_moves: bool) -> Printer<'a> {
+    self.config.detect_moves = detect_moves;
+    self
+  }
+
+  /// Always match up the lines `anchors` pick with their counterparts.
+  pub fn with_anchors(mut self, anchors: Vec<Anchor>) -> Printer<'a> {
+    self.config.anchors = anchors;
+    self
+  }
+
+  /// Slide inserted and deleted blocks so they start and end at natural
+  /// breaks, such as blank lines and changes in indentation. On by default.
+  pub fn with_indent_heuristic(
+    mut self,
+    indent_heuristic: bool,
+  ) -> Printer<'a> {
+    self.config.indent_heuristic = indent_heuristic;
+    self
+  }
+
+  /// Stop looking for a minimal diff of a file after `max_cost` units of work
+  /// or `timeout`, whichever comes first. Files that run out say so.
+  pub fn with_budget(
+    mut self,
+    max_cost: Option<u64>,
+    timeout: Option<Duration>,
+  ) -> Printer<'a> {
+    self.config.max_cost = max_cost;
+    self.config.timeout = timeout;
+    self
+  }
+
+  /// Highlight edits within changed lines at the `intraline` granularity.
+  pub fn with_intraline(mut self, intraline: Intraline) -> Printer<'a> {
+    self.config.intraline = intraline;
+    self
+  }
+
+  /// Compare lines using `normalization` while still printing them as is.
+  pub fn with_normalization(
+    mut self,
+    normalization: Normalization,
+  ) -> Printer<'a> {
+    self.config.normalization = normalization;
+    self
+  }
+
+  /// Mark context lines that only match thanks to normalization with `~`.
+  pub fn with_flag_normalized(mut self, flag_normalized: bool) -> Printer<'a> {
+    self.config.flag_normalized = flag_normalized;
+    self
+  }
+
+  /// Write `format` instead of colored diffs. Only `Format::Color` uses
+  /// colors.
+  pub fn with_format(mut self, format: Format) -> Printer<'a> {
+    self.config.format = format;
+    if format != Format::Color {
+      self.config.styles = Styles::default();
+    }
+    self
+  }
+
+  /// Name files in unified diffs relative to the `lhs` and `rhs` trees being
+  /// compared, rather than by their full paths.
+  pub fn with_roots(mut self, lhs: PathBuf, rhs: PathBuf) -> Printer<'a> {
+    self.config.roots = (lhs, rhs);
+    self
+  }
+
+  /// Print old and new lines next to each other in two columns filling
+  /// `width` terminal columns. Lines too long for their column are wrapped
+  /// if `wrap` is set and cut short otherwise.
+  pub fn with_side_by_side(mut self, width: usize, wrap: bool) -> Printer<'a> {
+    self.config.side_by_side = Some(width);
+    self.config.wrap = wrap;
+    self
+  }
+
+  /// Print the old and new line numbers of each line in a gutter before it.
+  pub fn with_line_numbers(mut self, line_numbers: bool) -> Printer<'a> {
+    self.config.line_numbers = line_numbers;
+    self
+  }
+
+  /// Prints the diff of one file pair.
+  ///
+  /// `Json` and `Html` output wraps every file pair in one document, which
+  /// only `print_file_pair_events` and `print_clusters` write. With those
+  /// formats this prints just the record or section body of `event`.
   pub fn print_file_pair_event(&mut self, event: FilePairEvent) -> Result<()> {
+    if self.config.format.is_json() {
+      return self.print_json_event(event);
+    }
+    if self.config.format == Format::Html {
+      return self.print_html_event(event);
+    }
     match event {
+      FilePairEvent::TextDiff {
+        lhs_path,
+        rhs_path,
+        lhs_content,
+        rhs_content,
+      } if self.config.format != Format::Color => {
+        self.print_patch(
+          lhs_path.as_deref(),
+          rhs_path.as_deref(),
+          &lhs_content,
+          &rhs_content,
+          (0, 0),
+        )?;
+      }
       FilePairEvent::TextDiff {
         lhs_path,
         rhs_path,
         lhs_content,
         rhs_content,
       } => {
-        self.print_file_header(lhs_path.as_deref(), rhs_path.as_deref())?;
-        self.print_diff(true, &lhs_content, &rhs_content)?;
+        self.print_text_diff(
+          lhs_path.as_deref(),
+          rhs_path.as_deref(),
+          &split_lines(&lhs_content),
+          &split_lines(&rhs_content),
+          (0, 0),
+        )?;
+      }
+      FilePairEvent::LargeTextDiff { lhs_path, rhs_path, lhs, rhs }
+        if self.config.format != Format::Color =>
+      {
+        let (lhs_text, lhs_first) = lhs.text(self.config.context);
+        let (rhs_text, rhs_first) = rhs.text(self.config.context);
+        self.print_patch(
+          lhs_path.as_deref(),
+          rhs_path.as_deref(),
+          lhs_text,
+          rhs_text,
+          (lhs_first, rhs_first),
+        )?;
+      }
+      FilePairEvent::LargeTextDiff { lhs_path, rhs_path, lhs, rhs } => {
+        let (lhs_lines, lhs_first) = lhs.lines(self.config.context);
+        let (rhs_lines, rhs_first) = rhs.lines(self.config.context);
+        self.print_text_diff(
+          lhs_path.as_deref(),
+          rhs_path.as_deref(),
+          &lhs_lines,
+          &rhs_lines,
+          (lhs_first, rhs_first),
+        )?;
+      }
+      FilePairEvent::TooLarge { lhs_path, rhs_path, needed, limit } => {
+        writeln!(
+          self.writer,
+          "Files {} and {} need about {} to diff, over the {} limit",
+          self
+            .display_name(lhs_path.as_deref())
+            .style(self.config.styles.old),
+          self
+            .display_name(rhs_path.as_deref())
+            .style(self.config.styles.new),
+          format_size(needed),
+          format_size(limit),
+        )?;
+      }
+      FilePairEvent::Binary { lhs_path, rhs_path }
+        if self.config.format != Format::Color =>
+      {
+        let old = self.patch_side(lhs_path.as_deref(), Side::Lhs, &[], 0);
+        let new = self.patch_side(rhs_path.as_deref(), Side::Rhs, &[], 0);
+        let headers = self.headers();
+        unified::write_binary(self.writer, headers, &old, &new)?;
       }
       FilePairEvent::Binary { lhs_path, rhs_path } => {
         self.print_binary_files_differ(
@@ -65,10 +339,10 @@ impl<'a> Printer<'a> {
           "Error diffing {} and {}:\n{}",
           self
             .display_name(lhs_path.as_deref())
-            .style(self.styles.old),
+            .style(self.config.styles.old),
           self
             .display_name(rhs_path.as_deref())
-            .style(self.styles.new),
+            .style(self.config.styles.new),
           err,
         )?;
       }
@@ -76,14 +350,549 @@ impl<'a> Printer<'a> {
     Ok(())
   }
 
+  /// Prints `events` in order, rendering up to `jobs` of them at a time on
+  /// worker threads.
+  pub fn print_file_pair_events(
+    &mut self,
+    events: impl Iterator<Item = FilePairEvent>,
+    jobs: usize,
+  ) -> Result<()> {
+    match self.config.format {
+      Format::Json => {
+        // A JSON document lists the file pairs in an array, so each record
+        // but the first needs a comma before it.
+        write!(self.writer, "{{\"version\":{},\"files\":[", json::VERSION)?;
+        let mut records = 0;
+        self.render_events(
+          events,
+          jobs,
+          |_, _| (),
+          |writer, (), out| {
+            if !out.is_empty() {
+              writer.write_all(if records == 0 { b"\n" } else { b",\n" })?;
+              records += 1;
+            }
+            writer.write_all(&out)
+          },
+        )?;
+        writeln!(self.writer, "\n]}}")
+      }
+      Format::Html => self.print_html_report(events, jobs),
+      _ if jobs <= 1 => {
+        for event in events {
+          self.print_file_pair_event(event)?;
+        }
+        Ok(())
+      }
+      _ => self.render_events(
+        events,
+        jobs,
+        |_, _| (),
+        |writer, (), out| writer.write_all(&out),
+      ),
+    }
+  }
+
+  /// Prints `events` each with a `Printer` of their own, up to `jobs` of them
+  /// at a time on worker threads. What each printed is handed to `f` in
+  /// order, along with what `meta` made of the event beforehand.
+  fn render_events<M>(
+    &mut self,
+    events: impl Iterator<Item = FilePairEvent>,
+    jobs: usize,
+    meta: impl Fn(&Self, &FilePairEvent) -> M,
+    mut f: impl FnMut(&mut dyn Write, M, Vec<u8>) -> Result<()>,
+  ) -> Result<()> {
+    if jobs <= 1 {
+      for event in events {
+        let m = meta(self, &event);
+        f(self.writer, m, render(&self.config, event)?)?;
+      }
+      return Ok(());
+    }
+    let config = self.config.clone();
+    let mut pool = OrderedPool::new(jobs, move |event| render(&config, event));
+    let mut pending = VecDeque::new();
+    for event in events {
+      while pool.is_full() {
+        let m = pending.pop_front().unwrap();
+        f(self.writer, m, pool.next().unwrap()?)?;
+      }
+      pending.push_back(meta(self, &event));
+      pool.send(event);
+    }
+    while let Some(out) = pool.next() {
+      f(self.writer, pending.pop_front().unwrap(), out?)?;
+    }
+    Ok(())
+  }
+
+  /// Prints an HTML report of `events`: an index, a section for each text
+  /// file that differs, and a list of the binary files, mismatches and
+  /// errors.
+  fn print_html_report(
+    &mut self,
+    events: impl Iterator<Item = FilePairEvent>,
+    jobs: usize,
+  ) -> Result<()> {
+    let (mut files, mut others) = (vec![], vec![]);
+    let meta = |p: &Self, event: &FilePairEvent| {
+      let text = matches!(
+        event,
+        FilePairEvent::TextDiff { .. } | FilePairEvent::LargeTextDiff { .. }
+      );
+      let (lhs, rhs) = event.paths();
+      (text, p.html_pair_name(lhs, rhs))
+    };
+    self.render_events(events, jobs, meta, |_, (text, name), out| {
+      // Text files whose differences are all ignored print nothing.
+      if out.is_empty() {
+        return Ok(());
+      }
+      if text {
+        files.push((name, out));
+      } else {
+        others.push(out);
+      }
+      Ok(())
+    })?;
+
+    let mut sections: Vec<_> = (1..=files.len())
+      .map(|i| format!("file-{i}"))
+      .zip(files.iter().map(|(name, _)| name.clone()))
+      .collect();
+    if !others.is_empty() {
+      sections
+        .push(("others".into(), "Binary files, mismatches and errors".into()));
+    }
+    let title = self.html_title();
+    html::start(self.writer, &title)?;
+    html::index(self.writer, &sections)?;
+    for ((id, name), (_, body)) in zip(&sections, &files) {
+      html::section(self.writer, id, name, body)?;
+    }
+    if !others.is_empty() {
+      let (id, name) = sections.last().unwrap();
+      html::section(self.writer, id, name, &others.concat())?;
+    }
+    html::end(self.writer)
+  }
+
+  fn html_title(&self) -> String {
+    let (lhs, rhs) = &self.config.roots;
+    format!("pratdiff {} {}", lhs.display(), rhs.display())
+  }
+
+  /// Names a pair of files in an HTML report, escaped.
+  fn html_pair_name(&self, lhs: Option<&Path>, rhs: Option<&Path>) -> String {
+    let lhs = lhs.map(|p| self.relative_name(p, Side::Lhs));
+    let rhs = rhs.map(|p| self.relative_name(p, Side::Rhs));
+    let name = match (lhs, rhs) {
+      (Some(l), Some(r)) if l == r => l.display().to_string(),
+      (Some(l), Some(r)) => format!("{} → {}", l.display(), r.display()),
+      (Some(l), None) => format!("{} (deleted)", l.display()),
+      (None, Some(r)) => format!("{} (added)", r.display()),
+      (None, None) => String::new(),
+    };
+    html::escape(&name)
+  }
+
+  /// Prints the HTML for `event`: the hunks of text files, or a list item
+  /// for the others.
+  fn print_html_event(&mut self, event: FilePairEvent) -> Result<()> {
+    let name = |p: Option<&Path>, side| {
+      let name = p.map(|p| self.relative_name(p, side));
+      let name = name.as_deref().unwrap_or(Path::new("/dev/null"));
+      html::escape(&name.display().to_string())
+    };
+    let (old, new) = event.paths();
+    let (old, new) = (name(old, Side::Lhs), name(new, Side::Rhs));
+    let item = match event {
+      FilePairEvent::TextDiff { lhs_content, rhs_content, .. } => {
+        let lhs = split_lines(&lhs_content);
+        let rhs = split_lines(&rhs_content);
+        return self.print_html_hunks(&lhs, &rhs, (0, 0));
+      }
+      FilePairEvent::LargeTextDiff { lhs, rhs, .. } => {
+        let (lhs_lines, lhs_first) = lhs.lines(self.config.context);
+        let (rhs_lines, rhs_first) = rhs.lines(self.config.context);
+        return self.print_html_hunks(
+          &lhs_lines,
+          &rhs_lines,
+          (lhs_first, rhs_first),
+        );
+      }
+      FilePairEvent::TooLarge { needed, limit, .. } => format!(
+        "Files {old} and {new} need about {} to diff, over the {} limit",
+        format_size(needed),
+        format_size(limit),
+      ),
+      FilePairEvent::Binary { .. } => {
+        format!("Binary files {old} and {new} differ")
+      }
+      FilePairEvent::TypeMismatch { lhs_path, rhs_path } => {
+        let kind = |p: &Path| if p.is_dir() { "directory" } else { "file" };
+        format!(
+          "{old} is a {} and {new} is a {}",
+          kind(&lhs_path),
+          kind(&rhs_path),
+        )
+      }
+      FilePairEvent::IoError { err, .. } => {
+        format!("Error diffing {old} and {new}: {}", html::escape(&err))
+      }
+    };
+    writeln!(self.writer, "<p>{item}</p>")
+  }
+
+  /// Prints the HTML tables of the hunks between `lhs` and `rhs`, if any.
+  fn print_html_hunks(
+    &mut self,
+    lhs: &[&[u8]],
+    rhs: &[&[u8]],
+    first_lines: (usize, usize),
+  ) -> Result<()> {
+    let Some((hunks, exhausted)) = self.report_hunks(lhs, rhs, first_lines)
+    else {
+      return Ok(());
+    };
+    if exhausted {
+      writeln!(
+        self.writer,
+        "<p class=\"separator\">Diff budget exceeded, changes may not be \
+         minimal</p>"
+      )?;
+    }
+    let tables = html::hunks(&hunks, self.config.flag_normalized);
+    self.writer.write_all(tables.as_bytes())
+  }
+
+  /// Prints the JSON record of `event`, if it has one. Text files whose
+  /// differences are all ignored have none.
+  fn print_json_event(&mut self, event: FilePairEvent) -> Result<()> {
+    let version = self.config.format == Format::JsonLines;
+    let names = |lhs: Option<&Path>, rhs: Option<&Path>| {
+      (
+        lhs.map(|p| self.relative_name(p, Side::Lhs)),
+        rhs.map(|p| self.relative_name(p, Side::Rhs)),
+      )
+    };
+    let record = |kind: &str,
+                  (lhs, rhs): (Option<PathBuf>, Option<PathBuf>),
+                  fields: &[(&str, String)]| {
+      json::record(kind, lhs.as_deref(), rhs.as_deref(), fields, version)
+    };
+    let record = match event {
+      FilePairEvent::TextDiff {
+        lhs_path,
+        rhs_path,
+        lhs_content,
+        rhs_content,
+      } => {
+        let no_newline = (no_newline(&lhs_content), no_newline(&rhs_content));
+        self
+          .json_text(&lhs_content, &rhs_content, (0, 0), no_newline)
+          .map(|fields| {
+            record(
+              "text",
+              names(lhs_path.as_deref(), rhs_path.as_deref()),
+              &fields,
+            )
+          })
+      }
+      FilePairEvent::LargeTextDiff { lhs_path, rhs_path, lhs, rhs } => {
+        let (lhs_text, lhs_first) = lhs.text(self.config.context);
+        let (rhs_text, rhs_first) = rhs.text(self.config.context);
+        let no_newline = (lhs.no_newline(), rhs.no_newline());
+        self
+          .json_text(lhs_text, rhs_text, (lhs_first, rhs_first), no_newline)
+          .map(|fields| {
+            record(
+              "text",
+              names(lhs_path.as_deref(), rhs_path.as_deref()),
+              &fields,
+            )
+          })
+      }
+      FilePairEvent::TooLarge { lhs_path, rhs_path, needed, limit } => {
+        Some(record(
+          "too_large",
+          names(lhs_path.as_deref(), rhs_path.as_deref()),
+          &[("needed", needed.to_string()), ("limit", limit.to_string())],
+        ))
+      }
+      FilePairEvent::Binary { lhs_path, rhs_path } => Some(record(
+        "binary",
+        names(lhs_path.as_deref(), rhs_path.as_deref()),
+        &[],
+      )),
+      FilePairEvent::TypeMismatch { lhs_path, rhs_path } => {
+        let kind =
+          |p: &Path| json::quote(if p.is_dir() { "directory" } else { "file" });
+        Some(record(
+          "type_mismatch",
+          names(Some(&lhs_path), Some(&rhs_path)),
+          &[("old_kind", kind(&lhs_path)), ("new_kind", kind(&rhs_path))],
+        ))
+      }
+      FilePairEvent::IoError { lhs_path, rhs_path, err } => Some(record(
+        "error",
+        names(lhs_path.as_deref(), rhs_path.as_deref()),
+        &[("message", json::quote(&err))],
+      )),
+    };
+    if let Some(record) = record {
+      write!(self.writer, "{record}")?;
+      if version {
+        writeln!(self.writer)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Diffs the lines of `lhs_text` against `rhs_text` into the JSON fields
+  /// of a text record, or `None` if there are no differences left to show.
+  /// `no_newline` is whether each file's last line is missing its newline.
+  fn json_text(
+    &self,
+    lhs_text: &[u8],
+    rhs_text: &[u8],
+    first_lines: (usize, usize),
+    (old_no_newline, new_no_newline): (bool, bool),
+  ) -> Option<Vec<(&'static str, String)>> {
+    let n = &self.config.normalization;
+    let ignore_newline =
+      n.ignore_all_space || n.ignore_space_change || n.ignore_space_at_eol;
+    let (lhs, rhs) = (split_lines(lhs_text), split_lines(rhs_text));
+    let (hunks, exhausted) = match self.report_hunks(&lhs, &rhs, first_lines) {
+      Some(report) => report,
+      // Lines are compared without their line endings, so a file gaining or
+      // losing its final newline has no hunks of its own.
+      None if old_no_newline != new_no_newline && !ignore_newline => {
+        (vec![], false)
+      }
+      None => return None,
+    };
+    Some(vec![
+      ("budget_exhausted", exhausted.to_string()),
+      ("old_no_newline", old_no_newline.to_string()),
+      ("new_no_newline", new_no_newline.to_string()),
+      ("hunks", json::hunks(&hunks)),
+    ])
+  }
+
+  /// Diffs `lhs` against `rhs` and describes the hunks line by line, also
+  /// returning whether the diff ran out of budget. `None` if there are no
+  /// differences left to show.
+  fn report_hunks<'b>(
+    &self,
+    lhs: &[&'b [u8]],
+    rhs: &[&'b [u8]],
+    first_lines: (usize, usize),
+  ) -> Option<(Vec<report::Hunk<'b>>, bool)> {
+    let (hunks, exhausted) = self.build_hunks(lhs, rhs);
+    if hunks.is_empty() {
+      return None;
+    }
+    let hunks = hunks
+      .iter()
+      .map(|h| report::Hunk {
+        old_start: first_lines.0 + h.lhs().start + 1,
+        old_lines: h.lhs().len(),
+        new_start: first_lines.1 + h.rhs().start + 1,
+        new_lines: h.rhs().len(),
+        ops: h
+          .diffs
+          .iter()
+          .map(|d| self.report_op(lhs, rhs, d, first_lines))
+          .collect(),
+      })
+      .collect();
+    Some((hunks, exhausted))
+  }
+
+  fn report_op<'b>(
+    &self,
+    lhs_lines: &[&'b [u8]],
+    rhs_lines: &[&'b [u8]],
+    d: &DiffItem,
+    first_lines: (usize, usize),
+  ) -> report::Op<'b> {
+    let (lhs, rhs) = (d.lhs(), d.rhs());
+    let old_first = first_lines.0 + lhs.start + 1;
+    let new_first = first_lines.1 + rhs.start + 1;
+    // Numbers lines from `first`, and the lines they pair with from
+    // `pair_first`.
+    let numbered = |lines: Vec<ChangedLine<'b>>, first, pair_first| {
+      lines
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| report::Line {
+          number: first + i,
+          pieces: line.pieces,
+          paired_with: line.pair.map(|p| pair_first + p),
+        })
+        .collect()
+    };
+    let old = |lines| numbered(lines, old_first, new_first);
+    let new = |lines| numbered(lines, new_first, old_first);
+    let plain = |lines: &[&'b [u8]]| {
+      lines
+        .iter()
+        .map(|&l| ChangedLine::whole(l, false))
+        .collect()
+    };
+    match d {
+      Match { .. } => report::Op {
+        kind: OpKind::Equal,
+        old: old(plain(&lhs_lines[lhs.clone()])),
+        new: new(plain(&rhs_lines[rhs.clone()])),
+      },
+      Mutation { .. } if rhs.is_empty() => report::Op {
+        kind: OpKind::Delete,
+        old: old(plain(&lhs_lines[lhs.clone()])),
+        new: vec![],
+      },
+      Mutation { .. } if lhs.is_empty() => report::Op {
+        kind: OpKind::Insert,
+        old: vec![],
+        new: new(plain(&rhs_lines[rhs.clone()])),
+      },
+      Mutation { .. } => {
+        let (old_lines, new_lines) =
+          self.changed_lines(&lhs_lines[lhs.clone()], &rhs_lines[rhs.clone()]);
+        report::Op {
+          kind: OpKind::Replace,
+          old: old(old_lines),
+          new: new(new_lines),
+        }
+      }
+      Moved { counterpart, .. } => {
+        let moved_out = rhs.is_empty();
+        let (lhs_moved, rhs_moved) = if moved_out {
+          (&lhs_lines[lhs.clone()], &rhs_lines[counterpart.clone()])
+        } else {
+          (&lhs_lines[counterpart.clone()], &rhs_lines[rhs.clone()])
+        };
+        let differ = self.token_differ();
+        let lhs_tokens = differ.tokenize(lhs_moved);
+        let rhs_tokens = differ.tokenize(rhs_moved);
+        let diffs = differ.diff_tokens(&lhs_tokens, &rhs_tokens);
+        let side = if moved_out { Side::Lhs } else { Side::Rhs };
+        let tokens = if moved_out { &lhs_tokens } else { &rhs_tokens };
+        let lines = changed_pieces(tokens, &diffs, side)
+          .into_iter()
+          .map(|pieces| ChangedLine { pieces, pair: None })
+          .collect();
+        if moved_out {
+          report::Op {
+            kind: OpKind::MovedOut {
+              to: first_lines.1 + counterpart.start + 1,
+            },
+            old: old(lines),
+            new: vec![],
+          }
+        } else {
+          report::Op {
+            kind: OpKind::MovedIn {
+              from: first_lines.0 + counterpart.start + 1,
+            },
+            old: vec![],
+            new: new(lines),
+          }
+        }
+      }
+    }
+  }
+
+  /// Prints the diff of two files' lines. `first_lines` are the line numbers
+  /// `lhs` and `rhs` start at, for callers that only pass part of a file.
+  fn print_text_diff(
+    &mut self,
+    lhs_path: Option<&Path>,
+    rhs_path: Option<&Path>,
+    lhs: &[&[u8]],
+    rhs: &[&[u8]],
+    first_lines: (usize, usize),
+  ) -> Result<()> {
+    let (hunks, exhausted) = self.build_hunks(lhs, rhs);
+    // Files can differ only in ways normalization ignores.
+    if !hunks.is_empty() {
+      self.print_file_header(lhs_path, rhs_path)?;
+      if exhausted {
+        self.print_budget_exhausted()?;
+      }
+      self.print_hunks(true, lhs, rhs, &hunks, first_lines)?;
+    }
+    Ok(())
+  }
+
+  /// Prints the diff of two files' text as a patch. `first_lines` are the
+  /// line numbers the texts start at.
+  fn print_patch(
+    &mut self,
+    lhs_path: Option<&Path>,
+    rhs_path: Option<&Path>,
+    lhs_text: &[u8],
+    rhs_text: &[u8],
+    first_lines: (usize, usize),
+  ) -> Result<()> {
+    let lhs = patch_lines(lhs_text);
+    let rhs = patch_lines(rhs_text);
+    let (hunks, _) = self.build_hunks(&lhs, &rhs);
+    let old = self.patch_side(lhs_path, Side::Lhs, &lhs, first_lines.0);
+    let new = self.patch_side(rhs_path, Side::Rhs, &rhs, first_lines.1);
+    let headers = self.headers();
+    unified::write_patch(self.writer, headers, &old, &new, &hunks)
+  }
+
+  fn patch_side<'b>(
+    &self,
+    path: Option<&'b Path>,
+    side: Side,
+    lines: &'b [&'b [u8]],
+    first_line: usize,
+  ) -> unified::Side<'b> {
+    let name = path.map(|p| match self.config.format {
+      // diff -u names files as they were given.
+      Format::GnuUnified => p.to_path_buf(),
+      _ => self.relative_name(p, side),
+    });
+    unified::Side { name, path, lines, first_line }
+  }
+
+  /// Names `p` relative to the tree on `side` being compared, or failing
+  /// that, without the common prefix.
+  fn relative_name(&self, p: &Path, side: Side) -> PathBuf {
+    let root = match side {
+      Side::Lhs => &self.config.roots.0,
+      Side::Rhs => &self.config.roots.1,
+    };
+    match p.strip_prefix(root) {
+      Ok(rel) if !rel.as_os_str().is_empty() => rel.to_path_buf(),
+      _ => p
+        .strip_prefix(&self.config.common_prefix)
+        .unwrap_or(p)
+        .to_path_buf(),
+    }
+  }
+
+  fn headers(&self) -> Headers {
+    match self.config.format {
+      Format::GnuUnified => Headers::Gnu,
+      _ => Headers::Git,
+    }
+  }
+
   fn display_name(&self, p: Option<&Path>) -> String {
     let Some(p) = p else {
       return "/dev/null".into();
     };
-    let stripped = p.strip_prefix(&self.common_prefix).unwrap_or(p);
+    let stripped = p.strip_prefix(&self.config.common_prefix).unwrap_or(p);
     if let Ok(link) = std::fs::read_link(p) {
-      let stripped_link =
-        link.strip_prefix(&self.common_prefix).unwrap_or(&link);
+      let stripped_link = link
+        .strip_prefix(&self.config.common_prefix)
+        .unwrap_or(&link);
       return format!("{} -> {}", stripped.display(), stripped_link.display());
     }
     stripped.display().to_string()
@@ -98,8 +907,8 @@ impl<'a> Printer<'a> {
     writeln!(
       self.writer,
       "Error diffing {} and {}:\n{}",
-      self.display_name(lhs).style(self.styles.old),
-      self.display_name(rhs).style(self.styles.new),
+      self.display_name(lhs).style(self.config.styles.old),
+      self.display_name(rhs).style(self.config.styles.new),
       err
     )
   }
@@ -115,9 +924,9 @@ impl<'a> Printer<'a> {
     writeln!(
       self.writer,
       "File/directory mistmatch:\n  {} is a {}\n  {} is a {}",
-      self.display_name(Some(lhs)).style(self.styles.old),
+      self.display_name(Some(lhs)).style(self.config.styles.old),
       ft(lhs),
-      self.display_name(Some(rhs)).style(self.styles.new),
+      self.display_name(Some(rhs)).style(self.config.styles.new),
       ft(rhs),
     )
   }
@@ -130,8 +939,8 @@ impl<'a> Printer<'a> {
     writeln!(
       self.writer,
       "Binary files {} and {} differ",
-      self.display_name(lhs).style(self.styles.old),
-      self.display_name(rhs).style(self.styles.new),
+      self.display_name(lhs).style(self.config.styles.old),
+      self.display_name(rhs).style(self.config.styles.new),
     )?;
     Ok(())
   }
@@ -144,14 +953,14 @@ impl<'a> Printer<'a> {
     writeln!(
       self.writer,
       "{} {}",
-      "---".style(self.styles.old),
-      self.display_name(lhs).style(self.styles.header),
+      "---".style(self.config.styles.old),
+      self.display_name(lhs).style(self.config.styles.header),
     )?;
     writeln!(
       self.writer,
       "{} {}",
-      "+++".style(self.styles.new),
-      self.display_name(rhs).style(self.styles.header)
+      "+++".style(self.config.styles.new),
+      self.display_name(rhs).style(self.config.styles.header)
     )?;
     Ok(())
   }
@@ -164,57 +973,289 @@ impl<'a> Printer<'a> {
   ) -> Result<()> {
     let lhs = split_lines(lhs_all);
     let rhs = split_lines(rhs_all);
-    let diffs = diff(&lhs, &rhs);
-    let hunks = Hunk::build(self.context, &diffs);
+    let (hunks, exhausted) = self.build_hunks(&lhs, &rhs);
+    if exhausted {
+      self.print_budget_exhausted()?;
+    }
+    self.print_hunks(include_headers, &lhs, &rhs, &hunks, (0, 0))
+  }
+
+  /// Recolors a unified diff read from `input`, such as the output of `git
+  /// diff` or `git log -p`, highlighting edits within changed lines. Text
+  /// outside of hunks, like commit messages, is passed through untouched
+  /// apart from file headers.
+  pub fn print_unified_diff(&mut self, input: &mut dyn BufRead) -> Result<()> {
+    *self.budget.get_mut() =
+      Budget::new(self.config.max_cost, self.config.timeout);
+    let mut lines = PatchLines::default();
+    let (mut removed, mut added) = (vec![], vec![]);
+    let mut raw = vec![];
+    loop {
+      raw.clear();
+      if input.read_until(b'\n', &mut raw)? == 0 {
+        break;
+      }
+      // The diff may already have been colored, by git for one.
+      let line = strip_ansi(raw.strip_suffix(b"\n").unwrap_or(&raw));
+      match lines.next(&line) {
+        PatchLine::Removed(text) => {
+          if !added.is_empty() {
+            self.print_changed_block(&mut removed, &mut added)?;
+          }
+          removed.push(text.to_vec());
+        }
+        PatchLine::Added(text) => added.push(text.to_vec()),
+        kind => {
+          self.print_changed_block(&mut removed, &mut added)?;
+          let s = String::from_utf8_lossy(&line);
+          match kind {
+            PatchLine::Context(text) => {
+              self.print_lines(
+                &[text],
+                " ",
+                self.config.styles.both,
+                Gutter::NONE,
+              )?;
+            }
+            PatchLine::HunkHeader | PatchLine::NoNewline => {
+              writeln!(
+                self.writer,
+                "{}",
+                s.style(self.config.styles.separator)
+              )?;
+            }
+            _ if s.starts_with("--- ") || s.starts_with("+++ ") => {
+              let (marker, name) = s.split_at(3);
+              let marker_style = if marker == "---" {
+                self.config.styles.old
+              } else {
+                self.config.styles.new
+              };
+              writeln!(
+                self.writer,
+                "{}{}",
+                marker.style(marker_style),
+                name.style(self.config.styles.header),
+              )?;
+            }
+            _ if s.starts_with("diff ") => {
+              writeln!(self.writer, "{}", s.style(self.config.styles.header))?;
+            }
+            _ => self.writer.write_all(&raw)?,
+          }
+        }
+      }
+    }
+    self.print_changed_block(&mut removed, &mut added)
+  }
+
+  /// Prints and clears a block of removed and added lines read from a
+  /// unified diff.
+  fn print_changed_block(
+    &mut self,
+    removed: &mut Vec<Vec<u8>>,
+    added: &mut Vec<Vec<u8>>,
+  ) -> Result<()> {
+    let lhs: Vec<&[u8]> = removed.iter().map(Vec::as_slice).collect();
+    let rhs: Vec<&[u8]> = added.iter().map(Vec::as_slice).collect();
+    if rhs.is_empty() {
+      self.print_lines(&lhs, "-", self.config.styles.old, Gutter::NONE)?;
+    } else if lhs.is_empty() {
+      self.print_lines(&rhs, "+", self.config.styles.new, Gutter::NONE)?;
+    } else {
+      self.print_mutation(&lhs, &rhs, Gutter::NONE)?;
+    }
+    removed.clear();
+    added.clear();
+    Ok(())
+  }
+
+  /// Diffs `lhs` against `rhs`, also returning whether the diff ran out of
+  /// budget.
+  fn build_hunks(&self, lhs: &[&[u8]], rhs: &[&[u8]]) -> (Vec<Hunk>, bool) {
+    let lhs_keys = self.config.normalization.keys(lhs);
+    let rhs_keys = self.config.normalization.keys(rhs);
+    let mut budget = self.budget.borrow_mut();
+    *budget = Budget::new(self.config.max_cost, self.config.timeout);
+    let pins = anchored_pairs(lhs, rhs, &self.config.anchors);
+    let mut diffs = diff_seq_pinned(
+      &lhs_keys,
+      &rhs_keys,
+      self.config.algorithm,
+      &pins,
+      &mut budget,
+    );
+    if self.config.indent_heuristic {
+      diffs = slide_boundaries(lhs, rhs, &diffs);
+    }
+    // Patches have no way to show moves.
+    let patch =
+      matches!(self.config.format, Format::Unified | Format::GnuUnified);
+    if self.config.detect_moves && !patch {
+      diffs = detect_moves_with_budget(
+        &lhs_keys,
+        &rhs_keys,
+        &diffs,
+        self.config.algorithm,
+        &mut budget,
+      );
+    }
+    let mut hunks = Hunk::build(self.config.context, &diffs);
+    if self.config.normalization.ignore_blank_lines {
+      hunks.retain(|h| {
+        !h.changes_all(|side, i| match side {
+          Side::Lhs => is_blank(lhs[i]),
+          Side::Rhs => is_blank(rhs[i]),
+        })
+      });
+    }
+    (hunks, budget.exhausted())
+  }
+
+  fn print_budget_exhausted(&mut self) -> Result<()> {
+    writeln!(
+      self.writer,
+      "{}",
+      "Diff budget exceeded, changes may not be minimal"
+        .style(self.config.styles.separator)
+    )
+  }
 
+  fn print_hunks(
+    &mut self,
+    include_headers: bool,
+    lhs: &[&[u8]],
+    rhs: &[&[u8]],
+    hunks: &[Hunk],
+    first_lines: (usize, usize),
+  ) -> Result<()> {
+    // Size line numbers for the whole file so columns line up across hunks.
+    let last_line = (first_lines.0 + lhs.len()).max(first_lines.1 + rhs.len());
+    let number_width = last_line.to_string().len();
     for h in hunks {
       if include_headers {
-        self.print_hunk_header(&h)?;
+        self.print_hunk_header(h, first_lines)?;
+      }
+      match self.config.side_by_side {
+        Some(width) => {
+          let rows = self.side_by_side_rows(lhs, rhs, &h.diffs, first_lines);
+          self.print_side_by_side(&rows, width, number_width)?;
+        }
+        None => {
+          let width = if self.config.line_numbers { number_width } else { 0 };
+          self.print_hunk_body(lhs, rhs, &h.diffs, first_lines, width)?;
+        }
       }
-      self.print_hunk_body(&lhs, &rhs, &h.diffs)?;
     }
     Ok(())
   }
 
-  fn print_hunk_header(&mut self, h: &Hunk) -> Result<()> {
+  fn print_hunk_header(
+    &mut self,
+    h: &Hunk,
+    first_lines: (usize, usize),
+  ) -> Result<()> {
     let (l, r) = (h.lhs(), h.rhs());
     writeln!(
       self.writer,
       "{}",
       format!(
         "@@ -{},{} +{},{} @@",
-        l.start + 1,
+        first_lines.0 + l.start + 1,
         l.len(),
-        r.start + 1,
+        first_lines.1 + r.start + 1,
         r.len()
       )
-      .style(self.styles.separator)
+      .style(self.config.styles.separator)
     )?;
     Ok(())
   }
 
+  /// Prints the lines of a hunk. `first_lines` are the line numbers `lhs`
+  /// and `rhs` start at, shown in a gutter `number_width` digits wide unless
+  /// that's 0.
   fn print_hunk_body(
     &mut self,
     lhs_lines: &[&[u8]],
     rhs_lines: &[&[u8]],
     diffs: &[DiffItem],
+    first_lines: (usize, usize),
+    number_width: usize,
   ) -> Result<()> {
     for d in diffs {
+      let (lhs, rhs) = (d.lhs(), d.rhs());
+      let gutter = Gutter {
+        width: number_width,
+        lhs: Some(first_lines.0 + lhs.start + 1),
+        rhs: Some(first_lines.1 + rhs.start + 1),
+      };
       match &d {
         Mutation { lhs, rhs } => {
           if rhs.is_empty() {
-            self.print_lines(&lhs_lines[lhs.clone()], "-", self.styles.old)?;
+            self.print_lines(
+              &lhs_lines[lhs.clone()],
+              "-",
+              self.config.styles.old,
+              gutter.side(Side::Lhs),
+            )?;
           } else if lhs.is_empty() {
-            self.print_lines(&rhs_lines[rhs.clone()], "+", self.styles.new)?;
+            self.print_lines(
+              &rhs_lines[rhs.clone()],
+              "+",
+              self.config.styles.new,
+              gutter.side(Side::Rhs),
+            )?;
           } else {
             self.print_mutation(
               &lhs_lines[lhs.clone()],
               &rhs_lines[rhs.clone()],
+              gutter,
             )?;
           }
         }
+        Moved { lhs, rhs, counterpart } => {
+          if rhs.is_empty() {
+            self.print_moved(
+              &lhs_lines[lhs.clone()],
+              &rhs_lines[counterpart.clone()],
+              Side::Lhs,
+              gutter.side(Side::Lhs),
+            )?;
+          } else {
+            self.print_moved(
+              &lhs_lines[counterpart.clone()],
+              &rhs_lines[rhs.clone()],
+              Side::Rhs,
+              gutter.side(Side::Rhs),
+            )?;
+          }
+        }
+        Match { lhs, rhs } if self.config.flag_normalized => {
+          for (i, (l, r)) in zip(lhs.clone(), rhs.clone()).enumerate() {
+            if lhs_lines[l] == rhs_lines[r] {
+              self.print_lines(
+                &lhs_lines[l..=l],
+                " ",
+                self.config.styles.both,
+                gutter.nth(i),
+              )?;
+            } else {
+              self.print_lines(
+                &rhs_lines[r..=r],
+                "~",
+                self.config.styles.normalized,
+                gutter.nth(i),
+              )?;
+            }
+          }
+        }
         Match { lhs, .. } => {
-          self.print_lines(&lhs_lines[lhs.clone()], " ", self.styles.both)?;
+          self.print_lines(
+            &lhs_lines[lhs.clone()],
+            " ",
+            self.config.styles.both,
+            gutter,
+          )?;
         }
       }
     }
@@ -226,66 +1267,456 @@ impl<'a> Printer<'a> {
     lines: &[&[u8]],
     prefix: &str,
     style: Style,
+    gutter: Gutter,
   ) -> Result<()> {
-    for line in lines {
+    for (i, line) in lines.iter().enumerate() {
+      self.print_gutter(gutter.nth(i))?;
       let s = String::from_utf8_lossy(line);
       writeln!(self.writer, "{}{}", prefix.style(style), s.style(style))?;
     }
     Ok(())
   }
 
+  fn print_gutter(&mut self, gutter: Gutter) -> Result<()> {
+    if gutter.width == 0 {
+      return Ok(());
+    }
+    let width = gutter.width;
+    let number = |n: Option<usize>| match n {
+      Some(n) => format!("{n:>width$}"),
+      None => " ".repeat(width),
+    };
+    write!(
+      self.writer,
+      "{}",
+      format!("{} {} ", number(gutter.lhs), number(gutter.rhs))
+        .style(self.config.styles.separator)
+    )
+  }
+
+  /// Prints a block of lines replaced by other lines. Edited lines are paired
+  /// with what they became and highlighted within the line, while lines
+  /// without a counterpart are printed whole.
   fn print_mutation(
     &mut self,
     lhs_lines: &[&[u8]],
     rhs_lines: &[&[u8]],
+    gutter: Gutter,
+  ) -> Result<()> {
+    let Some(pairs) = self.token_differ().pair_lines(lhs_lines, rhs_lines)
+    else {
+      return self.print_token_mutation(lhs_lines, rhs_lines, gutter);
+    };
+    for d in pairs {
+      match d {
+        Match { lhs, rhs } => {
+          let differ = self.token_differ();
+          let pairs: Vec<_> = zip(lhs, rhs)
+            .map(|(l, r)| {
+              let lhs_tokens = differ.tokenize(&lhs_lines[l..=l]);
+              let rhs_tokens = differ.tokenize(&rhs_lines[r..=r]);
+              let diffs = differ.diff_tokens(&lhs_tokens, &rhs_tokens);
+              (gutter.at(l, r), lhs_tokens, rhs_tokens, diffs)
+            })
+            .collect();
+          for (gutter, lhs_tokens, _, diffs) in &pairs {
+            self.print_mutation_side(
+              lhs_tokens,
+              diffs,
+              Side::Lhs,
+              self.config.styles.old,
+              self.config.styles.old_dim,
+              gutter.side(Side::Lhs),
+            )?;
+          }
+          for (gutter, _, rhs_tokens, diffs) in &pairs {
+            self.print_mutation_side(
+              rhs_tokens,
+              diffs,
+              Side::Rhs,
+              self.config.styles.new,
+              self.config.styles.new_dim,
+              gutter.side(Side::Rhs),
+            )?;
+          }
+        }
+        Mutation { lhs, rhs } => {
+          let at = gutter.at(lhs.start, rhs.start);
+          self.print_lines(
+            &lhs_lines[lhs],
+            "-",
+            self.config.styles.old,
+            at.side(Side::Lhs),
+          )?;
+          self.print_lines(
+            &rhs_lines[rhs],
+            "+",
+            self.config.styles.new,
+            at.side(Side::Rhs),
+          )?;
+        }
+        Moved { .. } => unreachable!(),
+      }
+    }
+    Ok(())
+  }
+
+  /// Prints a block of replaced lines as a single token level diff.
+  fn print_token_mutation(
+    &mut self,
+    lhs_lines: &[&[u8]],
+    rhs_lines: &[&[u8]],
+    gutter: Gutter,
   ) -> Result<()> {
-    let lhs_tokens = tokenize_lines(lhs_lines);
-    let rhs_tokens = tokenize_lines(rhs_lines);
-    let diffs = diff(&lhs_tokens, &rhs_tokens);
+    let differ = self.token_differ();
+    let lhs_tokens = differ.tokenize(lhs_lines);
+    let rhs_tokens = differ.tokenize(rhs_lines);
+    let diffs = differ.diff_tokens(&lhs_tokens, &rhs_tokens);
     self.print_mutation_side(
       &lhs_tokens,
       &diffs,
-      "-",
       Side::Lhs,
-      self.styles.old,
-      self.styles.old_dim,
+      self.config.styles.old,
+      self.config.styles.old_dim,
+      gutter.side(Side::Lhs),
     )?;
     self.print_mutation_side(
       &rhs_tokens,
       &diffs,
-      "+",
       Side::Rhs,
-      self.styles.new,
-      self.styles.new_dim,
+      self.config.styles.new,
+      self.config.styles.new_dim,
+      gutter.side(Side::Rhs),
     )?;
     Ok(())
   }
 
+  /// Prints one half of a moved block, highlighting any edits made to it
+  /// along the way.
+  fn print_moved(
+    &mut self,
+    lhs_lines: &[&[u8]],
+    rhs_lines: &[&[u8]],
+    side: Side,
+    gutter: Gutter,
+  ) -> Result<()> {
+    let differ = self.token_differ();
+    let lhs_tokens = differ.tokenize(lhs_lines);
+    let rhs_tokens = differ.tokenize(rhs_lines);
+    let diffs = differ.diff_tokens(&lhs_tokens, &rhs_tokens);
+    match side {
+      Side::Lhs => self.print_mutation_side(
+        &lhs_tokens,
+        &diffs,
+        side,
+        self.config.styles.old,
+        self.config.styles.moved_old,
+        gutter,
+      ),
+      Side::Rhs => self.print_mutation_side(
+        &rhs_tokens,
+        &diffs,
+        side,
+        self.config.styles.new,
+        self.config.styles.moved_new,
+        gutter,
+      ),
+    }
+  }
+
+  /// How changed lines are paired up and split into tokens to highlight
+  /// edits within them.
+  fn token_differ(&self) -> TokenDiffer<'_> {
+    TokenDiffer {
+      normalization: self.config.normalization,
+      intraline: self.config.intraline,
+      algorithm: self.config.algorithm,
+      budget: Some(&self.budget),
+    }
+  }
+
   fn print_mutation_side(
     &mut self,
     tokens: &[&[u8]],
     diffs: &[DiffItem],
-    prefix: &str,
     side: Side,
     mutation: Style,
     matching: Style,
+    gutter: Gutter,
   ) -> Result<()> {
-    write!(self.writer, "{}", prefix.style(mutation))?;
-    for d in diffs {
-      let style = if matches!(d, Match { .. }) { matching } else { mutation };
-      for &t in &tokens[d.side(side)] {
-        let s = String::from_utf8_lossy(t);
+    let prefix = match side {
+      Side::Lhs => "-",
+      Side::Rhs => "+",
+    };
+    let lines = mutation_spans(tokens, diffs, side, mutation, matching);
+    for (i, line) in lines.into_iter().enumerate() {
+      self.print_gutter(gutter.nth(i))?;
+      write!(self.writer, "{}", prefix.style(mutation))?;
+      for (s, style) in line {
         write!(self.writer, "{}", s.style(style))?;
-        if t == b"\n" {
-          write!(self.writer, "{}", prefix.style(mutation))?;
+      }
+      writeln!(self.writer)?;
+    }
+    Ok(())
+  }
+
+  /// Lines up the lines of a hunk in rows of old and new lines, leaving gaps
+  /// across from lines that were only deleted or inserted.
+  fn side_by_side_rows<'b>(
+    &self,
+    lhs_lines: &[&'b [u8]],
+    rhs_lines: &[&'b [u8]],
+    diffs: &[DiffItem],
+    first_lines: (usize, usize),
+  ) -> Vec<Row<'b>> {
+    let styles = &self.config.styles;
+    let old = |i, text| Cell {
+      number: first_lines.0 + i + 1,
+      sign: "-",
+      style: styles.old,
+      text,
+    };
+    let new = |i, text| Cell {
+      number: first_lines.1 + i + 1,
+      sign: "+",
+      style: styles.new,
+      text,
+    };
+    let mut rows = vec![];
+    for d in diffs {
+      match d {
+        Match { lhs, rhs } => {
+          for (l, r) in zip(lhs.clone(), rhs.clone()) {
+            let (sign, style) =
+              if self.config.flag_normalized && lhs_lines[l] != rhs_lines[r] {
+                ("~", styles.normalized)
+              } else {
+                (" ", styles.both)
+              };
+            let cell = |number, line| Cell {
+              number,
+              sign,
+              style,
+              text: whole_line(line, style),
+            };
+            rows.push((
+              Some(cell(first_lines.0 + l + 1, lhs_lines[l])),
+              Some(cell(first_lines.1 + r + 1, rhs_lines[r])),
+            ));
+          }
+        }
+        Mutation { lhs, rhs } => {
+          let pairs = self
+            .mutation_rows(&lhs_lines[lhs.clone()], &rhs_lines[rhs.clone()]);
+          let (mut l, mut r) = (lhs.start, rhs.start);
+          for (lhs_text, rhs_text) in pairs {
+            rows.push((
+              lhs_text.map(|text| {
+                l += 1;
+                old(l - 1, text)
+              }),
+              rhs_text.map(|text| {
+                r += 1;
+                new(r - 1, text)
+              }),
+            ));
+          }
+        }
+        Moved { lhs, rhs, counterpart } => {
+          let (lhs_moved, rhs_moved) = if rhs.is_empty() {
+            (&lhs_lines[lhs.clone()], &rhs_lines[counterpart.clone()])
+          } else {
+            (&lhs_lines[counterpart.clone()], &rhs_lines[rhs.clone()])
+          };
+          let differ = self.token_differ();
+          let lhs_tokens = differ.tokenize(lhs_moved);
+          let rhs_tokens = differ.tokenize(rhs_moved);
+          let diffs = differ.diff_tokens(&lhs_tokens, &rhs_tokens);
+          if rhs.is_empty() {
+            let lines = mutation_spans(
+              &lhs_tokens,
+              &diffs,
+              Side::Lhs,
+              styles.old,
+              styles.moved_old,
+            );
+            rows.extend(
+              zip(lhs.clone(), lines)
+                .map(|(i, text)| (Some(old(i, text)), None)),
+            );
+          } else {
+            let lines = mutation_spans(
+              &rhs_tokens,
+              &diffs,
+              Side::Rhs,
+              styles.new,
+              styles.moved_new,
+            );
+            rows.extend(
+              zip(rhs.clone(), lines)
+                .map(|(i, text)| (None, Some(new(i, text)))),
+            );
+          }
+        }
+      }
+    }
+    rows
+  }
+
+  /// Lines up a block of lines replaced by other lines, putting edited lines
+  /// across from what they became, highlighted like `print_mutation` does.
+  fn mutation_rows<'b>(
+    &self,
+    lhs_lines: &[&'b [u8]],
+    rhs_lines: &[&'b [u8]],
+  ) -> Vec<(Option<Spans<'b>>, Option<Spans<'b>>)> {
+    let styles = &self.config.styles;
+    let (lhs, rhs) = self.changed_lines(lhs_lines, rhs_lines);
+    let mut lhs = lhs
+      .into_iter()
+      .map(|l| (l.pair.is_some(), l.styled(styles.old, styles.old_dim)))
+      .peekable();
+    let mut rhs = rhs
+      .into_iter()
+      .map(|r| (r.pair.is_some(), r.styled(styles.new, styles.new_dim)))
+      .peekable();
+    // Pairs are in order, so lines that pair up arrive together. Lines
+    // without a counterpart share rows where they can.
+    let mut rows = vec![];
+    loop {
+      let row = match (lhs.peek(), rhs.peek()) {
+        (None, None) => break,
+        (Some((l, _)), Some((r, _))) if l == r => (lhs.next(), rhs.next()),
+        (Some((false, _)), _) | (Some(_), None) => (lhs.next(), None),
+        _ => (None, rhs.next()),
+      };
+      rows.push((row.0.map(|(_, l)| l), row.1.map(|(_, r)| r)));
+    }
+    rows
+  }
+
+  /// Pairs up the lines of a block of lines replaced by other lines, and
+  /// splits each into the pieces that changed and didn't, the way
+  /// `print_mutation` highlights them.
+  fn changed_lines<'b>(
+    &self,
+    lhs_lines: &[&'b [u8]],
+    rhs_lines: &[&'b [u8]],
+  ) -> (Vec<ChangedLine<'b>>, Vec<ChangedLine<'b>>) {
+    let whole = |lines: &[&'b [u8]]| {
+      lines
+        .iter()
+        .map(|&l| ChangedLine::whole(l, true))
+        .collect::<Vec<_>>()
+    };
+    let differ = self.token_differ();
+    let token_diff = |lhs: &[&'b [u8]], rhs: &[&'b [u8]]| {
+      let lhs_tokens = differ.tokenize(lhs);
+      let rhs_tokens = differ.tokenize(rhs);
+      let diffs = differ.diff_tokens(&lhs_tokens, &rhs_tokens);
+      (
+        changed_pieces(&lhs_tokens, &diffs, Side::Lhs),
+        changed_pieces(&rhs_tokens, &diffs, Side::Rhs),
+      )
+    };
+    if lhs_lines.is_empty() || rhs_lines.is_empty() {
+      return (whole(lhs_lines), whole(rhs_lines));
+    }
+    let Some(pairs) = differ.pair_lines(lhs_lines, rhs_lines) else {
+      let (lhs, rhs) = token_diff(lhs_lines, rhs_lines);
+      let unpaired = |lines: Vec<Pieces<'b>>| {
+        lines
+          .into_iter()
+          .map(|pieces| ChangedLine { pieces, pair: None })
+          .collect()
+      };
+      return (unpaired(lhs), unpaired(rhs));
+    };
+    let (mut lhs, mut rhs) = (vec![], vec![]);
+    for d in pairs {
+      match d {
+        Match { lhs: l, rhs: r } => {
+          for (l, r) in zip(l, r) {
+            let (mut lp, mut rp) =
+              token_diff(&lhs_lines[l..=l], &rhs_lines[r..=r]);
+            lhs.push(ChangedLine { pieces: lp.remove(0), pair: Some(r) });
+            rhs.push(ChangedLine { pieces: rp.remove(0), pair: Some(l) });
+          }
+        }
+        Mutation { lhs: l, rhs: r } => {
+          lhs.extend(whole(&lhs_lines[l]));
+          rhs.extend(whole(&rhs_lines[r]));
         }
+        Moved { .. } => unreachable!(),
+      }
+    }
+    (lhs, rhs)
+  }
+
+  /// Prints `rows` in two columns, each with line numbers, that together
+  /// fill `width` terminal columns.
+  fn print_side_by_side(
+    &mut self,
+    rows: &[Row],
+    width: usize,
+    number_width: usize,
+  ) -> Result<()> {
+    // Each column has its line number, a space and the sign before the text.
+    let column_width = width.saturating_sub(2) / 2;
+    let text_width = column_width.saturating_sub(number_width + 2);
+    let gap = " ".repeat(column_width);
+    for (lhs, rhs) in rows {
+      let lhs = self.cell_rows(lhs.as_ref(), number_width, text_width);
+      let rhs = self.cell_rows(rhs.as_ref(), number_width, text_width);
+      for i in 0..lhs.len().max(rhs.len()) {
+        writeln!(
+          self.writer,
+          "{}{}{}",
+          lhs.get(i).unwrap_or(&gap),
+          "│ ".style(self.config.styles.separator),
+          rhs.get(i).unwrap_or(&gap),
+        )?;
       }
     }
-    writeln!(self.writer)?;
     Ok(())
   }
 
+  /// Renders `cell` as the rows of its column, only numbering the first.
+  fn cell_rows(
+    &self,
+    cell: Option<&Cell>,
+    number_width: usize,
+    text_width: usize,
+  ) -> Vec<String> {
+    let Some(cell) = cell else {
+      return vec![];
+    };
+    layout(&cell.text, text_width, self.config.wrap)
+      .into_iter()
+      .enumerate()
+      .map(|(i, row)| {
+        let mut s = if i == 0 {
+          format!(
+            "{} {}",
+            format!("{:>number_width$}", cell.number)
+              .style(self.config.styles.separator),
+            cell.sign.style(cell.style),
+          )
+        } else {
+          " ".repeat(number_width + 2)
+        };
+        for (text, style) in row {
+          s += &text.style(style).to_string();
+        }
+        s
+      })
+      .collect()
+  }
+
   pub fn print_clusters(&mut self, clusters: &[DiffCluster]) -> Result<()> {
+    if self.config.format == Format::Html {
+      return self.print_html_clusters(clusters);
+    }
     for cluster in clusters {
       self.print_cluster(cluster)?;
     }
@@ -294,33 +1725,872 @@ impl<'a> Printer<'a> {
 
   fn print_cluster(&mut self, cluster: &DiffCluster) -> Result<()> {
     let total: usize = cluster.entries.values().sum();
-    let entry_count =
-      |n| format!("{} {}", n, if 1 == n { "entry" } else { "entries" });
     writeln!(
       self.writer,
       "{}",
       format!("=== cluster contains {}", entry_count(total))
-        .style(self.styles.header),
+        .style(self.config.styles.header),
     )?;
     for (entry, &count) in &cluster.entries {
       let lhs = self.display_name(entry.lhs_path.as_deref());
       let rhs = self.display_name(entry.rhs_path.as_deref());
-      write!(self.writer, "{}", "= ".style(self.styles.separator))?;
-      write!(self.writer, "{}", lhs.style(self.styles.old))?;
-      write!(self.writer, "{}", " => ".style(self.styles.separator))?;
-      write!(self.writer, "{}", rhs.style(self.styles.new))?;
+      write!(self.writer, "{}", "= ".style(self.config.styles.separator))?;
+      write!(self.writer, "{}", lhs.style(self.config.styles.old))?;
+      write!(self.writer, "{}", " => ".style(self.config.styles.separator))?;
+      write!(self.writer, "{}", rhs.style(self.config.styles.new))?;
       writeln!(
         self.writer,
         "{}",
-        format!(": {}", entry_count(count)).style(self.styles.separator)
+        format!(": {}", entry_count(count)).style(self.config.styles.separator)
       )?;
     }
     writeln!(
       self.writer,
       "{}",
-      "=== example diff: ".style(self.styles.separator)
+      "=== example diff: ".style(self.config.styles.separator)
     )?;
     self.print_diff(false, &cluster.exemplar_lhs, &cluster.exemplar_rhs)?;
     Ok(())
   }
+
+  /// Prints an HTML report with a section for each cluster, listing its
+  /// files and the diff of its exemplar.
+  fn print_html_clusters(&mut self, clusters: &[DiffCluster]) -> Result<()> {
+    let sections: Vec<_> = clusters
+      .iter()
+      .enumerate()
+      .map(|(i, cluster)| {
+        let total: usize = cluster.entries.values().sum();
+        let id = format!("cluster-{}", i + 1);
+        (id, format!("Cluster {}: {}", i + 1, entry_count(total)))
+      })
+      .collect();
+    let title = self.html_title();
+    html::start(self.writer, &title)?;
+    html::index(self.writer, &sections)?;
+    for ((id, name), cluster) in zip(&sections, clusters) {
+      let mut body = vec![];
+      writeln!(body, "<ul>")?;
+      for (entry, &count) in &cluster.entries {
+        let pair = self
+          .html_pair_name(entry.lhs_path.as_deref(), entry.rhs_path.as_deref());
+        writeln!(body, "<li>{pair}: {}</li>", entry_count(count))?;
+      }
+      writeln!(body, "</ul>")?;
+      writeln!(body, "<p class=\"separator\">Example diff:</p>")?;
+      let lhs = split_lines(&cluster.exemplar_lhs);
+      let rhs = split_lines(&cluster.exemplar_rhs);
+      if let Some((hunks, _)) = self.report_hunks(&lhs, &rhs, (0, 0)) {
+        let tables = html::hunks(&hunks, self.config.flag_normalized);
+        body.extend_from_slice(tables.as_bytes());
+      }
+      html::section(self.writer, id, name, &body)?;
+    }
+    html::end(self.writer)
+  }
+}
+
+/// Removes terminal escape sequences, like colors, from `line`.
+fn strip_ansi(line: &[u8]) -> Cow<'_, [u8]> {
+  if !line.contains(&0x1b) {
+    return Cow::Borrowed(line);
+  }
+  let mut out = Vec::with_capacity(line.len());
+  let mut i = 0;
+  while i < line.len() {
+    if line[i] == 0x1b && line.get(i + 1) == Some(&b'[') {
+      // A CSI sequence runs up to a byte in `@`..=`~`.
+      i += 2;
+      while i < line.len() && !(0x40..=0x7e).contains(&line[i]) {
+        i += 1;
+      }
+      i += 1;
+    } else {
+      out.push(line[i]);
+      i += 1;
+    }
+  }
+  Cow::Owned(out)
+}
+
+/// The line numbers printed before lines. Each line printed from a block
+/// advances them.
+#[derive(Clone, Copy)]
+struct Gutter {
+  /// How many digits to pad numbers to, or 0 for no gutter at all.
+  width: usize,
+  /// The old and new line numbers of the first line, or `None` to leave
+  /// that side blank.
+  lhs: Option<usize>,
+  rhs: Option<usize>,
+}
+
+impl Gutter {
+  const NONE: Gutter = Gutter { width: 0, lhs: None, rhs: None };
+
+  /// The gutter of the line `lhs` old lines and `rhs` new lines further on.
+  fn at(self, lhs: usize, rhs: usize) -> Gutter {
+    Gutter {
+      width: self.width,
+      lhs: self.lhs.map(|n| n + lhs),
+      rhs: self.rhs.map(|n| n + rhs),
+    }
+  }
+
+  /// The gutter of the `i`th line of a block.
+  fn nth(self, i: usize) -> Gutter {
+    self.at(i, i)
+  }
+
+  /// Leaves the number for the side that isn't `side` blank.
+  fn side(self, side: Side) -> Gutter {
+    match side {
+      Side::Lhs => Gutter { rhs: None, ..self },
+      Side::Rhs => Gutter { lhs: None, ..self },
+    }
+  }
+}
+
+/// One line of one side of side-by-side output.
+struct Cell<'b> {
+  number: usize,
+  sign: &'static str,
+  style: Style,
+  text: Spans<'b>,
+}
+
+/// An old line and the new line across from it. Either may be missing.
+type Row<'b> = (Option<Cell<'b>>, Option<Cell<'b>>);
+
+/// A line of text in pieces that did or didn't change.
+type Pieces<'b> = Vec<(Cow<'b, str>, bool)>;
+
+/// A line of a replaced block, and the index of the line on the other side
+/// it was edited into or from, if any.
+struct ChangedLine<'b> {
+  pieces: Pieces<'b>,
+  pair: Option<usize>,
+}
+
+impl<'b> ChangedLine<'b> {
+  fn whole(line: &'b [u8], changed: bool) -> ChangedLine<'b> {
+    ChangedLine {
+      pieces: vec![(String::from_utf8_lossy(line), changed)],
+      pair: None,
+    }
+  }
+
+  fn styled(self, mutation: Style, matching: Style) -> Spans<'b> {
+    style_pieces(self.pieces, mutation, matching)
+  }
+}
+
+/// Splits a token level diff of some lines into the pieces of each line on
+/// one `side`, marking the ones that changed.
+fn changed_pieces<'b>(
+  tokens: &[&'b [u8]],
+  diffs: &[DiffItem],
+  side: Side,
+) -> Vec<Pieces<'b>> {
+  let mut lines = vec![vec![]];
+  for d in diffs {
+    let changed = !matches!(d, Match { .. });
+    for &t in &tokens[d.side(side)] {
+      if t == b"\n" {
+        lines.push(vec![]);
+      } else {
+        lines
+          .last_mut()
+          .unwrap()
+          .push((String::from_utf8_lossy(t), changed));
+      }
+    }
+  }
+  lines
+}
+
+/// Splits a token level diff of some lines into the styled pieces of each
+/// line on one `side`.
+fn mutation_spans<'b>(
+  tokens: &[&'b [u8]],
+  diffs: &[DiffItem],
+  side: Side,
+  mutation: Style,
+  matching: Style,
+) -> Vec<Spans<'b>> {
+  changed_pieces(tokens, diffs, side)
+    .into_iter()
+    .map(|pieces| style_pieces(pieces, mutation, matching))
+    .collect()
+}
+
+fn style_pieces(pieces: Pieces, mutation: Style, matching: Style) -> Spans {
+  pieces
+    .into_iter()
+    .map(|(t, changed)| (t, if changed { mutation } else { matching }))
+    .collect()
+}
+
+fn whole_line(line: &[u8], style: Style) -> Spans<'_> {
+  vec![(String::from_utf8_lossy(line), style)]
+}
+
+/// Prints `event` with a `Printer` of its own, returning what it printed.
+fn render(config: &Config, event: FilePairEvent) -> Result<Vec<u8>> {
+  let mut out = vec![];
+  let mut p = Printer {
+    writer: &mut out,
+    config: config.clone(),
+    budget: RefCell::default(),
+  };
+  p.print_file_pair_event(event).map(|()| out)
+}
+
+/// Whether the last line of the file `content` is missing its newline.
+fn no_newline(content: &[u8]) -> bool {
+  !content.is_empty() && !content.ends_with(b"\n")
+}
+
+fn entry_count(n: usize) -> String {
+  format!("{} {}", n, if 1 == n { "entry" } else { "entries" })
+}
+
+/// Formats a byte count for people, e.g. `1.5 GiB`.
+fn format_size(bytes: u64) -> String {
+  let units = ["bytes", "KiB", "MiB", "GiB", "TiB"];
+  let mut size = bytes as f64;
+  let mut unit = 0;
+  while size >= 1024.0 && unit + 1 < units.len() {
+    size /= 1024.0;
+    unit += 1;
+  }
+  if unit == 0 {
+    format!("{bytes} bytes")
+  } else {
+    format!("{size:.1} {}", units[unit])
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Prints the diff of `lhs` against `rhs` without colors, with the printer
+  /// set up by `setup`.
+  fn output(
+    lhs: &str,
+    rhs: &str,
+    setup: impl FnOnce(Printer) -> Printer,
+  ) -> String {
+    styled_output(lhs, rhs, Styles::default(), setup)
+  }
+
+  /// Like `output`, but printing with `styles`.
+  fn styled_output(
+    lhs: &str,
+    rhs: &str,
+    styles: Styles,
+    setup: impl FnOnce(Printer) -> Printer,
+  ) -> String {
+    let mut out = vec![];
+    let mut p = setup(Printer::default(&mut out, 3, PathBuf::new()));
+    p.config.styles = styles;
+    p.print_file_pair_event(FilePairEvent::TextDiff {
+      lhs_path: Some("a.txt".into()),
+      rhs_path: Some("b.txt".into()),
+      lhs_content: lhs.into(),
+      rhs_content: rhs.into(),
+    })
+    .unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  fn text_event(
+    lhs_path: &str,
+    rhs_path: &str,
+    lhs: &str,
+    rhs: &str,
+  ) -> FilePairEvent {
+    FilePairEvent::TextDiff {
+      lhs_path: Some(lhs_path.into()),
+      rhs_path: Some(rhs_path.into()),
+      lhs_content: lhs.into(),
+      rhs_content: rhs.into(),
+    }
+  }
+
+  /// Prints `events` without colors on `jobs` threads, with the printer set
+  /// up by `setup`.
+  fn events_output(
+    events: Vec<FilePairEvent>,
+    jobs: usize,
+    setup: impl FnOnce(Printer) -> Printer,
+  ) -> String {
+    let mut out = vec![];
+    let mut p = setup(Printer::default(&mut out, 3, PathBuf::new()));
+    p.config.styles = Styles::default();
+    p.print_file_pair_events(events.into_iter(), jobs).unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  /// The part of an HTML report after its `<style>` element.
+  fn html_body(report: &str) -> &str {
+    report.split_once("<body>\n").unwrap().1
+  }
+
+  #[test]
+  fn json_final_newline() {
+    assert_eq!(
+      output("a\n", "a", |p| p.with_format(Format::JsonLines)),
+      concat!(
+        r#"{"version":1,"type":"text","old_path":"a.txt","new_path":"b.txt","#,
+        r#""budget_exhausted":false,"old_no_newline":false,"#,
+        r#""new_no_newline":true,"hunks":[]}"#,
+        "\n",
+      )
+    );
+    let ignore_space = Normalization {
+      ignore_space_change: true,
+      ..Default::default()
+    };
+    let ignored = output("a\n", "a", |p| {
+      p.with_format(Format::JsonLines)
+        .with_normalization(ignore_space)
+    });
+    assert_eq!(ignored, "");
+  }
+
+  #[test]
+  fn line_numbers() {
+    // The gutter is as wide as the largest line number in any hunk, and
+    // context lines show both numbers even where they have drifted apart.
+    let lhs = "a\n\nb\nc\nd\ne\nf\ng\nh\ni\nj\n";
+    let rhs = "a\nb\nc\nd\ne\nf\ng\nh\ni\nJ\n";
+    assert_eq!(
+      output(lhs, rhs, |p| p.with_line_numbers(true)),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,5 +1,4 @@\n",
+        " 1  1  a\n",
+        " 2    -\n",
+        " 3  2  b\n",
+        " 4  3  c\n",
+        " 5  4  d\n",
+        "@@ -8,4 +7,4 @@\n",
+        " 8  7  g\n",
+        " 9  8  h\n",
+        "10  9  i\n",
+        "11    -j\n",
+        "   10 +J\n",
+      )
+    );
+    // A hunk left with only context once blank lines are ignored is dropped,
+    // without throwing off the numbers after it.
+    let blank = Normalization {
+      ignore_blank_lines: true,
+      ..Default::default()
+    };
+    assert_eq!(
+      output(lhs, rhs, |p| {
+        p.with_line_numbers(true).with_normalization(blank)
+      }),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -8,4 +7,4 @@\n",
+        " 8  7  g\n",
+        " 9  8  h\n",
+        "10  9  i\n",
+        "11    -j\n",
+        "   10 +J\n",
+      )
+    );
+    // Paired lines are numbered through `print_mutation_side`.
+    assert_eq!(
+      output(
+        "keep\nlet a = 1;\nlet b = 2;\n",
+        "keep\nsomething else entirely\nlet a = 10;\n",
+        |p| p.with_line_numbers(true)
+      ),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,3 +1,3 @@\n",
+        "1 1  keep\n",
+        "  2 +something else entirely\n",
+        "2   -let a = 1;\n",
+        "  3 +let a = 10;\n",
+        "3   -let b = 2;\n",
+      )
+    );
+  }
+
+  #[test]
+  fn recolors_unified_diffs() {
+    let input = concat!(
+      "commit 123\n",
+      "\n",
+      "    Message\n",
+      "\n",
+      "diff --git a/x b/x\n",
+      "--- a/x\n",
+      "+++ b/x\n",
+      "@@ -1,3 +1,3 @@\n",
+      " a\n",
+      "-let b = 1;\n",
+      "+let b = 2;\n",
+      " \x1b[32mc\x1b[m\n",
+      "\\ No newline at end of file\n",
+    );
+    let mut out = vec![];
+    let mut p = Printer::default(&mut out, 3, PathBuf::new());
+    p.config.styles = Styles::default();
+    p.print_unified_diff(&mut input.as_bytes()).unwrap();
+    // Existing colors are stripped before the diff is colored again.
+    assert_eq!(
+      String::from_utf8(out).unwrap(),
+      input.replace("\x1b[32m", "").replace("\x1b[m", "")
+    );
+  }
+
+  #[test]
+  fn budget_exhausted() {
+    let out = |budget| {
+      output("a\nb\nc\n", "a\nB\nc\n", |p| p.with_budget(budget, None))
+    };
+    assert_eq!(
+      out(Some(1)),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "Diff budget exceeded, changes may not be minimal\n",
+        "@@ -1,3 +1,3 @@\n",
+        " a\n",
+        "-b\n",
+        "+B\n",
+        " c\n",
+      )
+    );
+    assert!(!out(None).contains("Diff budget exceeded"));
+  }
+
+  #[test]
+  fn paired_mutations() {
+    // Each changed line is printed next to the line it was edited into.
+    assert_eq!(
+      output(
+        "keep\nlet a = 1;\nlet b = 2;\n",
+        "keep\nsomething else entirely\nlet a = 10;\n",
+        |p| p
+      ),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,3 +1,3 @@\n",
+        " keep\n",
+        "+something else entirely\n",
+        "-let a = 1;\n",
+        "+let a = 10;\n",
+        "-let b = 2;\n",
+      )
+    );
+    // Lines with nothing in common stay in blocks.
+    assert_eq!(
+      output("keep\nx y\nz w\n", "keep\n1 2\n3 4\n", |p| p),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,3 +1,3 @@\n",
+        " keep\n",
+        "-x y\n",
+        "-z w\n",
+        "+1 2\n",
+        "+3 4\n",
+      )
+    );
+  }
+
+  #[test]
+  fn flag_normalized() {
+    let eol = Normalization {
+      ignore_space_at_eol: true,
+      ..Default::default()
+    };
+    let out = |flag| {
+      output("a\nb\nc\n", "a\nb \nC\n", |p| {
+        p.with_normalization(eol).with_flag_normalized(flag)
+      })
+    };
+    assert_eq!(
+      out(true),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,3 +1,3 @@\n",
+        " a\n",
+        "~b \n",
+        "-c\n",
+        "+C\n",
+      )
+    );
+    assert_eq!(
+      out(false),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,3 +1,3 @@\n",
+        " a\n",
+        " b\n",
+        "-c\n",
+        "+C\n",
+      )
+    );
+  }
+
+  #[test]
+  fn ignore_blank_lines() {
+    let blank = Normalization {
+      ignore_blank_lines: true,
+      ..Default::default()
+    };
+    // Files that only differ in blank lines print nothing.
+    assert_eq!(
+      output("a\nb\n\nc\n", "a\nb\nc\n", |p| p.with_normalization(blank)),
+      ""
+    );
+    // Blank lines are still shown within hunks that have other changes.
+    assert_eq!(
+      output("a\n\nb\nc\n", "a\nb\nC\n", |p| p.with_normalization(blank)),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,4 +1,3 @@\n",
+        " a\n",
+        "-\n",
+        " b\n",
+        "-c\n",
+        "+C\n",
+      )
+    );
+  }
+
+  #[test]
+  fn side_by_side() {
+    let sbs = |lhs, rhs| output(lhs, rhs, |p| p.with_side_by_side(40, false));
+    // Inserted and deleted lines leave a gap across from them.
+    assert_eq!(
+      sbs("a\nb\nc\nd\n", "a\nB\nc\nd\ne\n"),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,4 +1,5 @@\n",
+        "1  a               │ 1  a               \n",
+        "2 -b               │ 2 +B               \n",
+        "3  c               │ 3  c               \n",
+        "4  d               │ 4  d               \n",
+        "                   │ 5 +e               \n",
+      )
+    );
+    assert_eq!(
+      sbs("a\nb\nc\n", "a\nc\n"),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,3 +1,2 @@\n",
+        "1  a               │ 1  a               \n",
+        "2 -b               │                    \n",
+        "3  c               │ 2  c               \n",
+      )
+    );
+    // Wide characters take two columns each.
+    assert_eq!(
+      sbs("a\n日本語のテキスト\n", "a\n日本語テキスト\n"),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,2 +1,2 @@\n",
+        "1  a               │ 1  a               \n",
+        "2 -日本語のテキスト│ 2 +日本語テキスト  \n",
+      )
+    );
+    // Long lines are cut short, or wrapped onto more rows.
+    let (lhs, rhs) =
+      ("a\nthe quick brown fox jumps\n", "a\nthe quick brown cat jumps\n");
+    assert_eq!(
+      sbs(lhs, rhs),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,2 +1,2 @@\n",
+        "1  a               │ 1  a               \n",
+        "2 -the quick brown…│ 2 +the quick brown…\n",
+      )
+    );
+    assert_eq!(
+      output(lhs, rhs, |p| p.with_side_by_side(40, true)),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,2 +1,2 @@\n",
+        "1  a               │ 1  a               \n",
+        "2 -the quick brown │ 2 +the quick brown \n",
+        "   fox jumps       │    cat jumps       \n",
+      )
+    );
+  }
+
+  #[test]
+  fn side_by_side_moves() {
+    let styles = Styles {
+      moved_old: owo_colors::Style::new().bold(),
+      moved_new: owo_colors::Style::new().italic(),
+      ..Default::default()
+    };
+    assert_eq!(
+      styled_output(
+        "a1\na2\na3\nk\nb1\nb2\nb3\n",
+        "b1\nb2\nb3\nk\na1\na2\na3\n",
+        styles,
+        |p| p.with_side_by_side(40, false).with_moves(true)
+      ),
+      concat!(
+        "--- a.txt\n",
+        "+++ b.txt\n",
+        "@@ -1,7 +1,7 @@\n",
+        "1 -\x1b[1ma1\x1b[0m              │                    \n",
+        "2 -\x1b[1ma2\x1b[0m              │                    \n",
+        "3 -\x1b[1ma3\x1b[0m              │                    \n",
+        "4 -k               │                    \n",
+        "5  b1              │ 1  b1              \n",
+        "6  b2              │ 2  b2              \n",
+        "7  b3              │ 3  b3              \n",
+        "                   │ 4 +k               \n",
+        "                   │ 5 +\x1b[3ma1\x1b[0m              \n",
+        "                   │ 6 +\x1b[3ma2\x1b[0m              \n",
+        "                   │ 7 +\x1b[3ma3\x1b[0m              \n",
+      )
+    );
+  }
+
+  #[test]
+  fn html_report() {
+    let events = vec![
+      text_event("a<b>.txt", "a<b>.txt", "x\ny\n", "x\nY\n"),
+      FilePairEvent::Binary {
+        lhs_path: Some("b&.bin".into()),
+        rhs_path: Some("b&.bin".into()),
+      },
+      // Whitespace is ignored, so this one has no section.
+      text_event("same.txt", "same.txt", "x \n", "x\n"),
+      // Tests run in the crate root.
+      FilePairEvent::TypeMismatch {
+        lhs_path: "src".into(),
+        rhs_path: "Cargo.toml".into(),
+      },
+      FilePairEvent::IoError {
+        lhs_path: Some("e.txt".into()),
+        rhs_path: None,
+        err: "<oops>".into(),
+      },
+      text_event("c.txt", "d.txt", "1\n", "2\n"),
+    ];
+    let ignore_space = Normalization {
+      ignore_all_space: true,
+      ..Default::default()
+    };
+    let report = events_output(events, 1, |p| {
+      p.with_format(Format::Html).with_normalization(ignore_space)
+    });
+    assert_eq!(
+      html_body(&report),
+      concat!(
+        "<h1>pratdiff  </h1>\n",
+        "<nav>\n",
+        "<ul>\n",
+        "<li><a href=\"#file-1\">a&lt;b&gt;.txt</a></li>\n",
+        "<li><a href=\"#file-2\">c.txt → d.txt</a></li>\n",
+        "<li><a href=\"#others\">Binary files, mismatches and errors</a></li>\n",
+        "</ul>\n",
+        "</nav>\n",
+        "<details class=\"section\" id=\"file-1\" open>\n",
+        "<summary>a&lt;b&gt;.txt</summary>\n",
+        "<table class=\"hunk\">\n",
+        "<tr><td class=\"separator\" colspan=\"4\">@@ -1,2 +1,2 @@</td></tr>\n",
+        "<tr class=\"context\"><td class=\"number\">1</td><td class=\"number\">1</td><td class=\"sign both\"> </td><td class=\"text\"><span class=\"both\">x</span></td></tr>\n",
+        "<tr class=\"deleted\"><td class=\"number\">2</td><td class=\"number\"></td><td class=\"sign old\">-</td><td class=\"text\"><span class=\"old\">y</span></td></tr>\n",
+        "<tr class=\"inserted\"><td class=\"number\"></td><td class=\"number\">2</td><td class=\"sign new\">+</td><td class=\"text\"><span class=\"new\">Y</span></td></tr>\n",
+        "</table>\n",
+        "</details>\n",
+        "<details class=\"section\" id=\"file-2\" open>\n",
+        "<summary>c.txt → d.txt</summary>\n",
+        "<table class=\"hunk\">\n",
+        "<tr><td class=\"separator\" colspan=\"4\">@@ -1,1 +1,1 @@</td></tr>\n",
+        "<tr class=\"deleted\"><td class=\"number\">1</td><td class=\"number\"></td><td class=\"sign old\">-</td><td class=\"text\"><span class=\"old\">1</span></td></tr>\n",
+        "<tr class=\"inserted\"><td class=\"number\"></td><td class=\"number\">1</td><td class=\"sign new\">+</td><td class=\"text\"><span class=\"new\">2</span></td></tr>\n",
+        "</table>\n",
+        "</details>\n",
+        "<details class=\"section\" id=\"others\" open>\n",
+        "<summary>Binary files, mismatches and errors</summary>\n",
+        "<p>Binary files b&amp;.bin and b&amp;.bin differ</p>\n",
+        "<p>src is a directory and Cargo.toml is a file</p>\n",
+        "<p>Error diffing e.txt and /dev/null: &lt;oops&gt;</p>\n",
+        "</details>\n",
+        "</body>\n",
+        "</html>\n",
+      )
+    );
+  }
+
+  #[test]
+  fn html_clusters() {
+    let clusters = DiffCluster::cluster(
+      [
+        text_event("x/a&.txt", "y/a&.txt", "k\nfoo\n", "k\nbar\n"),
+        text_event("x/b.txt", "y/b.txt", "j\nfoo\n", "j\nbar\n"),
+        text_event("x/c.txt", "y/c.txt", "1\n", "2\n"),
+      ]
+      .into_iter(),
+      Algorithm::default(),
+      Intraline::default(),
+    );
+    let mut out = vec![];
+    let mut p =
+      Printer::default(&mut out, 3, PathBuf::new()).with_format(Format::Html);
+    p.print_clusters(&clusters).unwrap();
+    assert_eq!(
+      html_body(&String::from_utf8(out).unwrap()),
+      concat!(
+        "<h1>pratdiff  </h1>\n",
+        "<nav>\n",
+        "<ul>\n",
+        "<li><a href=\"#cluster-1\">Cluster 1: 2 entries</a></li>\n",
+        "<li><a href=\"#cluster-2\">Cluster 2: 1 entry</a></li>\n",
+        "</ul>\n",
+        "</nav>\n",
+        "<details class=\"section\" id=\"cluster-1\" open>\n",
+        "<summary>Cluster 1: 2 entries</summary>\n",
+        "<ul>\n",
+        "<li>x/a&amp;.txt → y/a&amp;.txt: 1 entry</li>\n",
+        "<li>x/b.txt → y/b.txt: 1 entry</li>\n",
+        "</ul>\n",
+        "<p class=\"separator\">Example diff:</p>\n",
+        "<table class=\"hunk\">\n",
+        "<tr><td class=\"separator\" colspan=\"4\">@@ -1,1 +1,1 @@</td></tr>\n",
+        "<tr class=\"deleted\"><td class=\"number\">1</td><td class=\"number\"></td><td class=\"sign old\">-</td><td class=\"text\"><span class=\"old\">foo</span></td></tr>\n",
+        "<tr class=\"inserted\"><td class=\"number\"></td><td class=\"number\">1</td><td class=\"sign new\">+</td><td class=\"text\"><span class=\"new\">bar</span></td></tr>\n",
+        "</table>\n",
+        "</details>\n",
+        "<details class=\"section\" id=\"cluster-2\" open>\n",
+        "<summary>Cluster 2: 1 entry</summary>\n",
+        "<ul>\n",
+        "<li>x/c.txt → y/c.txt: 1 entry</li>\n",
+        "</ul>\n",
+        "<p class=\"separator\">Example diff:</p>\n",
+        "<table class=\"hunk\">\n",
+        "<tr><td class=\"separator\" colspan=\"4\">@@ -1,1 +1,1 @@</td></tr>\n",
+        "<tr class=\"deleted\"><td class=\"number\">1</td><td class=\"number\"></td><td class=\"sign old\">-</td><td class=\"text\"><span class=\"old\">1</span></td></tr>\n",
+        "<tr class=\"inserted\"><td class=\"number\"></td><td class=\"number\">1</td><td class=\"sign new\">+</td><td class=\"text\"><span class=\"new\">2</span></td></tr>\n",
+        "</table>\n",
+        "</details>\n",
+        "</body>\n",
+        "</html>\n",
+      )
+    );
+  }
+
+  #[test]
+  fn json_document() {
+    let events = vec![
+      // Whitespace is ignored, so this one has no record.
+      text_event("same.txt", "same.txt", "x \n", "x\n"),
+      text_event("a.txt", "a.txt", "x\n", "y\n"),
+      FilePairEvent::Binary {
+        lhs_path: Some("b.bin".into()),
+        rhs_path: None,
+      },
+    ];
+    let ignore_space = Normalization {
+      ignore_all_space: true,
+      ..Default::default()
+    };
+    assert_eq!(
+      events_output(events, 1, |p| {
+        p.with_format(Format::Json).with_normalization(ignore_space)
+      }),
+      concat!(
+        r#"{"version":1,"files":["#,
+        "\n",
+        r#"{"type":"text","old_path":"a.txt","new_path":"a.txt","#,
+        r#""budget_exhausted":false,"old_no_newline":false,"#,
+        r#""new_no_newline":false,"hunks":[{"old_start":1,"old_lines":1,"#,
+        r#""new_start":1,"new_lines":1,"ops":[{"op":"replace","#,
+        r#""old":[{"line":1,"text":"x","changed":[[0,1]]}],"#,
+        r#""new":[{"line":1,"text":"y","changed":[[0,1]]}]}]}]},"#,
+        "\n",
+        r#"{"type":"binary","old_path":"b.bin","new_path":null}"#,
+        "\n]}\n",
+      )
+    );
+    assert_eq!(
+      events_output(vec![], 1, |p| p.with_format(Format::Json)),
+      "{\"version\":1,\"files\":[\n]}\n"
+    );
+  }
+
+  #[test]
+  fn parallel_output_matches_serial() {
+    let events = || {
+      (0..20).map(|i| match i % 4 {
+        0 => FilePairEvent::Binary {
+          lhs_path: Some(format!("{i}.bin").into()),
+          rhs_path: Some(format!("{i}.bin").into()),
+        },
+        // Only differs in whitespace, so prints nothing.
+        1 => {
+          text_event(&format!("{i}.txt"), &format!("{i}.txt"), "x \n", "x\n")
+        }
+        _ => {
+          let lhs: String = (0..i * 10).map(|n| format!("{n}\n")).collect();
+          text_event(
+            &format!("{i}.txt"),
+            &format!("{i}.txt"),
+            &lhs,
+            &lhs.replace("5\n", "five\n"),
+          )
+        }
+      })
+    };
+    let ignore_space = Normalization {
+      ignore_all_space: true,
+      ..Default::default()
+    };
+    for format in [Format::Color, Format::Json, Format::Html] {
+      let out = |jobs| {
+        events_output(events().collect(), jobs, |p| {
+          p.with_format(format).with_normalization(ignore_space)
+        })
+      };
+      assert_eq!(out(4), out(1), "{format:?}");
+    }
+  }
+
+  #[test]
+  fn parallel_write_errors() {
+    struct Broken;
+    impl Write for Broken {
+      fn write(&mut self, _: &[u8]) -> Result<usize> {
+        Err(std::io::ErrorKind::BrokenPipe.into())
+      }
+      fn flush(&mut self) -> Result<()> {
+        Ok(())
+      }
+    }
+    let events =
+      (0..20).map(|i| text_event("a.txt", "b.txt", &format!("{i}\n"), "x\n"));
+    let mut writer = Broken;
+    let mut p = Printer::default(&mut writer, 3, PathBuf::new());
+    let err = p.print_file_pair_events(events, 4).unwrap_err();
+    assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+  }
 }
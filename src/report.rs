@@ -0,0 +1,43 @@
+use std::borrow::Cow;
+
+/// A hunk described line by line, for formats that write diffs out as data
+/// rather than print them. Line numbers count from 1.
+pub(crate) struct Hunk<'a> {
+  pub old_start: usize,
+  pub old_lines: usize,
+  pub new_start: usize,
+  pub new_lines: usize,
+  pub ops: Vec<Op<'a>>,
+}
+
+/// A run of lines that were all matched up, deleted, inserted, replaced or
+/// moved together.
+pub(crate) struct Op<'a> {
+  pub kind: OpKind,
+  pub old: Vec<Line<'a>>,
+  pub new: Vec<Line<'a>>,
+}
+
+pub(crate) enum OpKind {
+  Equal,
+  Delete,
+  Insert,
+  Replace,
+  /// Old lines that moved to `to` in the new file.
+  MovedOut {
+    to: usize,
+  },
+  /// New lines that moved from `from` in the old file.
+  MovedIn {
+    from: usize,
+  },
+}
+
+pub(crate) struct Line<'a> {
+  pub number: usize,
+  /// The text, in pieces that did or didn't change.
+  pub pieces: Vec<(Cow<'a, str>, bool)>,
+  /// The number of the line on the other side this one was edited into or
+  /// from.
+  pub paired_with: Option<usize>,
+}
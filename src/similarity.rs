@@ -0,0 +1,33 @@
+/// Deleted and inserted lines are only paired up as edits of each other if
+/// at least this fraction of their non-whitespace tokens are shared. Tokens
+/// are counted regardless of order, which makes lines look more alike than
+/// they are, so this is stricter than `MIN_HIGHLIGHT_SIMILARITY`.
+pub(crate) const MIN_PAIR_SIMILARITY: f64 = 0.5;
+
+/// Token diffs whose sides have less than this fraction of their
+/// non-whitespace text in common are highlighted as whole lines, since the
+/// few pieces left matching would only be noise.
+pub(crate) const MIN_HIGHLIGHT_SIMILARITY: f64 = 0.3;
+
+/// How alike two sides of `total` items between them are if `common` items
+/// of each side have a counterpart on the other, from 0 (nothing in common)
+/// to 1 (equal). Two empty sides are equal.
+pub(crate) fn similarity(common: usize, total: usize) -> f64 {
+  if total == 0 {
+    return 1.0;
+  }
+  (2 * common) as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn ratios() {
+    assert_eq!(similarity(0, 0), 1.0);
+    assert_eq!(similarity(0, 4), 0.0);
+    assert_eq!(similarity(1, 4), 0.5);
+    assert_eq!(similarity(3, 6), 1.0);
+  }
+}
@@ -0,0 +1,385 @@
+use crate::diff::DiffItem;
+use crate::diff::DiffItem::*;
+use crate::diff::Diffs;
+
+// --- Indent heuristic ---
+//
+// A block of inserted (or deleted) lines can often be slid up or down without
+// changing the diff's meaning, e.g. when the line just above the block is the
+// same as its last line. The algorithm picks the position as a side effect of
+// how it matches things up, which regularly splits blocks mid-function.
+//
+// This pass tries every position each such block can slide to and scores the
+// two cuts it would make using the blank lines and indentation around them,
+// following the indent heuristic git uses. Lower scores are better.
+
+/// Indentation is capped at this many columns.
+const MAX_INDENT: i32 = 200;
+/// At most this many blank lines are counted around a cut.
+const MAX_BLANKS: i32 = 20;
+/// Blocks are never slid further than this from their original position.
+const MAX_SLIDING: usize = 100;
+
+const START_OF_FILE_PENALTY: i32 = 1;
+const END_OF_FILE_PENALTY: i32 = 21;
+const TOTAL_BLANK_WEIGHT: i32 = -30;
+const POST_BLANK_WEIGHT: i32 = 6;
+const RELATIVE_INDENT_PENALTY: i32 = -4;
+const RELATIVE_INDENT_WITH_BLANK_PENALTY: i32 = 10;
+const RELATIVE_OUTDENT_PENALTY: i32 = 24;
+const RELATIVE_OUTDENT_WITH_BLANK_PENALTY: i32 = 17;
+const RELATIVE_DEDENT_PENALTY: i32 = 23;
+const RELATIVE_DEDENT_WITH_BLANK_PENALTY: i32 = 17;
+const INDENT_WEIGHT: i32 = 60;
+
+/// Slides pure insertions and deletions in `diffs` to the positions where
+/// their boundaries line up best with blank lines and indentation.
+pub fn slide_boundaries(
+  lhs: &[&[u8]],
+  rhs: &[&[u8]],
+  diffs: &[DiffItem],
+) -> Vec<DiffItem> {
+  // Surround every mutation with a (possibly empty) match, so there is always
+  // somewhere for the lines a block slides over to go.
+  let mut padded = vec![];
+  for d in diffs {
+    if !matches!(d, Match { .. }) {
+      if !matches!(padded.last(), Some(Match { .. })) {
+        padded.push(Match {
+          lhs: d.lhs().start..d.lhs().start,
+          rhs: d.rhs().start..d.rhs().start,
+        });
+      }
+      padded.push(d.clone());
+      padded.push(Match {
+        lhs: d.lhs().end..d.lhs().end,
+        rhs: d.rhs().end..d.rhs().end,
+      });
+    } else if let (Some(Match { lhs, rhs }), Match { lhs: l, rhs: r }) =
+      (padded.last_mut(), d)
+    {
+      lhs.end = l.end;
+      rhs.end = r.end;
+    } else {
+      padded.push(d.clone());
+    }
+  }
+  let mut diffs = padded;
+  for i in 0..diffs.len() {
+    let Mutation { lhs: l, rhs: r } = &diffs[i] else {
+      continue;
+    };
+    let (lines, block) = match (l.is_empty(), r.is_empty()) {
+      (true, false) => (rhs, r.clone()),
+      (false, true) => (lhs, l.clone()),
+      _ => continue,
+    };
+    let before = match i.checked_sub(1).map(|p| &diffs[p]) {
+      Some(Match { lhs, .. }) => lhs.len(),
+      _ => 0,
+    };
+    let after = match diffs.get(i + 1) {
+      Some(Match { lhs, .. }) => lhs.len(),
+      _ => 0,
+    };
+
+    let up = (1..=before.min(block.start))
+      .take_while(|&k| lines[block.start - k] == lines[block.end - k])
+      .count();
+    let down = (0..after.min(lines.len() - block.end))
+      .take_while(|&k| lines[block.start + k] == lines[block.end + k])
+      .count();
+    if up == 0 && down == 0 {
+      continue;
+    }
+
+    // Candidates are identified by where the block would end. Ties go to the
+    // lowest position, as the diff algorithms tend to put blocks there.
+    let lowest = block.end + down;
+    let highest = (block.end - up).max(lowest.saturating_sub(MAX_SLIDING));
+    let mut best = lowest;
+    let mut best_score = None;
+    for end in highest..=lowest {
+      let mut score = Score::default();
+      score.add_split(lines, end);
+      score.add_split(lines, end - block.len());
+      if best_score.as_ref().is_none_or(|b| score.cmp(b) <= 0) {
+        best = end;
+        best_score = Some(score);
+      }
+    }
+    shift(&mut diffs, i, best as isize - block.end as isize);
+  }
+
+  let mut out = Diffs::default();
+  for d in &diffs {
+    match d {
+      Match { lhs, .. } => out.add_match(lhs.len()),
+      Mutation { lhs, rhs } => out.add_mutation(lhs.len(), rhs.len()),
+      Moved { lhs, rhs, counterpart } => {
+        out.add_moved(lhs.len(), rhs.len(), counterpart.clone())
+      }
+    }
+  }
+  out.vec
+}
+
+/// Moves the mutation at `i` by `delta` lines, growing one neighbouring match
+/// and shrinking the other.
+fn shift(diffs: &mut [DiffItem], i: usize, delta: isize) {
+  if delta == 0 {
+    return;
+  }
+  let move_by = |r: &mut std::ops::Range<usize>, start: bool, end: bool| {
+    if start {
+      r.start = r.start.strict_add_signed(delta);
+    }
+    if end {
+      r.end = r.end.strict_add_signed(delta);
+    }
+  };
+  if let Some(Match { lhs, rhs }) = i.checked_sub(1).map(|p| &mut diffs[p]) {
+    move_by(lhs, false, true);
+    move_by(rhs, false, true);
+  }
+  if let Mutation { lhs, rhs } = &mut diffs[i] {
+    move_by(lhs, true, true);
+    move_by(rhs, true, true);
+  }
+  if let Some(Match { lhs, rhs }) = diffs.get_mut(i + 1) {
+    move_by(lhs, true, false);
+    move_by(rhs, true, false);
+  }
+}
+
+/// Returns the indentation of `line` in columns, or `None` if it is blank.
+fn indent_of(line: &[u8]) -> Option<i32> {
+  let mut n = 0;
+  for &b in line {
+    match b {
+      b' ' => n += 1,
+      b'\t' => n += 8 - n % 8,
+      b if b.is_ascii_whitespace() => {}
+      _ => return Some(n),
+    }
+    if n >= MAX_INDENT {
+      return Some(MAX_INDENT);
+    }
+  }
+  None
+}
+
+/// What the lines around a cut just before `lines[split]` look like.
+struct Split {
+  end_of_file: bool,
+  /// Indentation of the line right after the cut.
+  indent: Option<i32>,
+  /// Blank lines right before the cut.
+  pre_blank: i32,
+  /// Indentation of the first non-blank line before the cut.
+  pre_indent: Option<i32>,
+  /// Blank lines after the line right after the cut.
+  post_blank: i32,
+  /// Indentation of the first non-blank line after that.
+  post_indent: Option<i32>,
+}
+
+impl Split {
+  fn measure(lines: &[&[u8]], split: usize) -> Split {
+    let end_of_file = split >= lines.len();
+    let indent = lines.get(split).and_then(|l| indent_of(l));
+
+    let mut pre_blank = 0;
+    let mut pre_indent = None;
+    for l in lines[..split.min(lines.len())].iter().rev() {
+      pre_indent = indent_of(l);
+      if pre_indent.is_some() {
+        break;
+      }
+      pre_blank += 1;
+      if pre_blank == MAX_BLANKS {
+        pre_indent = Some(0);
+        break;
+      }
+    }
+
+    let mut post_blank = 0;
+    let mut post_indent = None;
+    for l in lines.iter().skip(split + 1) {
+      post_indent = indent_of(l);
+      if post_indent.is_some() {
+        break;
+      }
+      post_blank += 1;
+      if post_blank == MAX_BLANKS {
+        post_indent = Some(0);
+        break;
+      }
+    }
+
+    Split {
+      end_of_file,
+      indent,
+      pre_blank,
+      pre_indent,
+      post_blank,
+      post_indent,
+    }
+  }
+}
+
+#[derive(Default)]
+struct Score {
+  effective_indent: i32,
+  penalty: i32,
+}
+
+impl Score {
+  fn add_split(&mut self, lines: &[&[u8]], split: usize) {
+    let m = Split::measure(lines, split);
+    if m.pre_indent.is_none() && m.pre_blank == 0 {
+      self.penalty += START_OF_FILE_PENALTY;
+    }
+    if m.end_of_file {
+      self.penalty += END_OF_FILE_PENALTY;
+    }
+
+    let post_blank = if m.indent.is_none() { 1 + m.post_blank } else { 0 };
+    let total_blank = m.pre_blank + post_blank;
+    self.penalty += TOTAL_BLANK_WEIGHT * total_blank;
+    self.penalty += POST_BLANK_WEIGHT * post_blank;
+
+    let indent = m.indent.or(m.post_indent);
+    let any_blanks = total_blank != 0;
+    self.effective_indent += indent.unwrap_or(-1);
+
+    let (Some(indent), Some(pre_indent)) = (indent, m.pre_indent) else {
+      return;
+    };
+    if indent > pre_indent {
+      self.penalty += if any_blanks {
+        RELATIVE_INDENT_WITH_BLANK_PENALTY
+      } else {
+        RELATIVE_INDENT_PENALTY
+      };
+    } else if indent < pre_indent {
+      let outdent = m.post_indent.is_some_and(|p| p > indent);
+      self.penalty += match (outdent, any_blanks) {
+        (true, true) => RELATIVE_OUTDENT_WITH_BLANK_PENALTY,
+        (true, false) => RELATIVE_OUTDENT_PENALTY,
+        (false, true) => RELATIVE_DEDENT_WITH_BLANK_PENALTY,
+        (false, false) => RELATIVE_DEDENT_PENALTY,
+      };
+    }
+  }
+
+  fn cmp(&self, other: &Score) -> i32 {
+    let indents = (self.effective_indent - other.effective_indent).signum();
+    INDENT_WEIGHT * indents + (self.penalty - other.penalty)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::diff::Algorithm;
+  use crate::diff::Side;
+  use crate::diff::diff;
+  use crate::tokens::split_lines;
+
+  fn changed<'a>(
+    lines: &[&'a [u8]],
+    diffs: &[DiffItem],
+    side: Side,
+  ) -> Vec<&'a str> {
+    diffs
+      .iter()
+      .filter(|d| matches!(d, Mutation { .. }))
+      .flat_map(|d| d.side(side))
+      .map(|i| std::str::from_utf8(lines[i]).unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn indent_levels() {
+    assert_eq!(indent_of(b"x"), Some(0));
+    assert_eq!(indent_of(b"    x"), Some(4));
+    assert_eq!(indent_of(b"  \tx"), Some(8));
+    assert_eq!(indent_of(b"  \t "), None);
+    assert_eq!(indent_of(b""), None);
+  }
+
+  #[test]
+  fn slide_inserted_function() {
+    let old = split_lines(include_bytes!("testdata/old/slide.rs"));
+    let new = split_lines(include_bytes!("testdata/new/slide.rs"));
+    // The highest position the new function can be shown at.
+    let diffs = [
+      Match { lhs: 0..2, rhs: 0..2 },
+      Mutation { lhs: 2..2, rhs: 2..7 },
+      Match { lhs: 2..9, rhs: 7..14 },
+    ];
+    assert_eq!(
+      changed(&new, &diffs, Side::Rhs),
+      &["    x", "}", "", "fn beta() -> u32 {", "    let x = 2;"]
+    );
+    let expected = ["fn beta() -> u32 {", "    let x = 2;", "    x", "}", ""];
+    assert_eq!(
+      changed(&new, &slide_boundaries(&old, &new, &diffs), Side::Rhs),
+      expected
+    );
+
+    // The same function deleted instead.
+    let diffs = [
+      Match { lhs: 0..2, rhs: 0..2 },
+      Mutation { lhs: 2..7, rhs: 2..2 },
+      Match { lhs: 7..14, rhs: 2..9 },
+    ];
+    assert_eq!(
+      changed(&new, &slide_boundaries(&new, &old, &diffs), Side::Lhs),
+      expected
+    );
+  }
+
+  #[test]
+  fn slide_keeps_diff_algorithm_output() {
+    let old = split_lines(include_bytes!("testdata/old/slide.rs"));
+    let new = split_lines(include_bytes!("testdata/new/slide.rs"));
+    for algorithm in [Algorithm::Patience, Algorithm::Myers] {
+      let diffs = diff(&old, &new, algorithm);
+      assert_eq!(slide_boundaries(&old, &new, &diffs), diffs);
+    }
+  }
+
+  #[test]
+  fn slide_if_block() {
+    let lhs = split_lines(b"if x {\n  foo();\n}\nbar();\n");
+    let rhs =
+      split_lines(b"if x {\n  foo();\n}\nif y {\n  foo();\n}\nbar();\n");
+    // Put the block at its highest position to start with.
+    let diffs = [
+      Match { lhs: 0..2, rhs: 0..2 },
+      Mutation { lhs: 2..2, rhs: 2..5 },
+      Match { lhs: 2..4, rhs: 5..7 },
+    ];
+    assert_eq!(
+      slide_boundaries(&lhs, &rhs, &diffs),
+      &[
+        Match { lhs: 0..3, rhs: 0..3 },
+        Mutation { lhs: 3..3, rhs: 3..6 },
+        Match { lhs: 3..4, rhs: 6..7 },
+      ]
+    );
+  }
+
+  #[test]
+  fn slide_deletion_merges_neighbours() {
+    let lhs = split_lines(b"a\nb\na\nb\nc\n");
+    let rhs = split_lines(b"a\nb\nc\n");
+    let diffs =
+      [Mutation { lhs: 0..2, rhs: 0..0 }, Match { lhs: 2..5, rhs: 0..3 }];
+    let slid = slide_boundaries(&lhs, &rhs, &diffs);
+    assert_eq!(slid.len(), 3, "{slid:?}");
+    assert_eq!(slid[1].lhs().len(), 2);
+  }
+}
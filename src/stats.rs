@@ -0,0 +1,273 @@
+use std::iter::Sum;
+use std::iter::zip;
+use std::ops::AddAssign;
+use std::path::PathBuf;
+
+use crate::diff::Algorithm;
+use crate::diff::DiffItem;
+use crate::diff::DiffItem::*;
+use crate::diff::diff;
+use crate::files::FilePairEvent;
+use crate::intraline::TokenDiffer;
+use crate::similarity;
+use crate::tokens::is_whitespace_token;
+use crate::tokens::split_lines;
+
+/// Summary numbers for a diff, or the sum of them over many diffs.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct DiffStats {
+  /// File pairs with at least one change.
+  pub files_changed: usize,
+  /// Lines present on both sides.
+  pub unchanged: usize,
+  /// Lines only present on the rhs.
+  pub inserted: usize,
+  /// Lines only present on the lhs.
+  pub deleted: usize,
+  /// Lines edited in place, counted once per pair of old and new line.
+  pub modified: usize,
+  /// Lines in moved blocks, counted on the lhs.
+  pub moved: usize,
+  /// Non-whitespace tokens deleted or inserted within changed lines. Only
+  /// filled in by `from_lines`.
+  pub tokens_changed: usize,
+}
+
+impl DiffStats {
+  /// Computes stats from line diffs alone. Without looking at the lines, a
+  /// mutation replacing `l` lines with `r` counts `min(l, r)` of them as
+  /// modified.
+  pub fn from_diffs(diffs: &[DiffItem]) -> DiffStats {
+    let mut stats = DiffStats::default();
+    for d in diffs {
+      match d {
+        Match { lhs, .. } => stats.unchanged += lhs.len(),
+        Mutation { lhs, rhs } => {
+          let modified = lhs.len().min(rhs.len());
+          stats.modified += modified;
+          stats.deleted += lhs.len() - modified;
+          stats.inserted += rhs.len() - modified;
+        }
+        Moved { lhs, .. } => stats.moved += lhs.len(),
+      }
+    }
+    stats.files_changed = stats.changed() as usize;
+    stats
+  }
+
+  /// Computes stats from line diffs and the lines they refer to. Changed
+  /// lines are paired up and token diffed the way the printer does with its
+  /// default settings, so `modified` only counts lines that resemble what
+  /// they became and `tokens_changed` the tokens it would highlight.
+  pub fn from_lines(
+    lhs: &[&[u8]],
+    rhs: &[&[u8]],
+    diffs: &[DiffItem],
+    algorithm: Algorithm,
+  ) -> DiffStats {
+    let differ = TokenDiffer { algorithm, ..Default::default() };
+    let mut stats = DiffStats::from_diffs(diffs);
+    for d in diffs {
+      let Mutation { lhs: l, rhs: r } = d else {
+        continue;
+      };
+      let (lhs, rhs) = (&lhs[l.clone()], &rhs[r.clone()]);
+      let Some(pairs) = differ.pair_lines(lhs, rhs) else {
+        stats.tokens_changed += tokens_changed(&differ, lhs, rhs);
+        continue;
+      };
+      let mut modified = 0;
+      for p in pairs {
+        match p {
+          Match { lhs: pl, rhs: pr } => {
+            modified += pl.len();
+            for (i, j) in zip(pl, pr) {
+              stats.tokens_changed +=
+                tokens_changed(&differ, &lhs[i..=i], &rhs[j..=j]);
+            }
+          }
+          // Lines without a counterpart are highlighted whole.
+          Mutation { lhs: pl, rhs: pr } | Moved { lhs: pl, rhs: pr, .. } => {
+            stats.tokens_changed += tokens_changed(&differ, &lhs[pl], &rhs[pr]);
+          }
+        }
+      }
+      let default = l.len().min(r.len());
+      stats.modified = stats.modified - default + modified;
+      stats.deleted += default - modified;
+      stats.inserted += default - modified;
+    }
+    stats
+  }
+
+  /// Diffs every text file pair in `events`, skipping pairs that can not be
+  /// diffed as text.
+  pub fn for_events(
+    events: impl Iterator<Item = FilePairEvent>,
+    algorithm: Algorithm,
+  ) -> Vec<FileStats> {
+    let mut out = vec![];
+    for event in events {
+      let (lhs_path, rhs_path, stats) = match event {
+        FilePairEvent::TextDiff {
+          lhs_path,
+          rhs_path,
+          lhs_content,
+          rhs_content,
+        } => {
+          let lhs = split_lines(&lhs_content);
+          let rhs = split_lines(&rhs_content);
+          let diffs = diff(&lhs, &rhs, algorithm);
+          (
+            lhs_path,
+            rhs_path,
+            DiffStats::from_lines(&lhs, &rhs, &diffs, algorithm),
+          )
+        }
+        FilePairEvent::LargeTextDiff { lhs_path, rhs_path, lhs, rhs } => {
+          let (lhs_lines, _) = lhs.lines(0);
+          let (rhs_lines, _) = rhs.lines(0);
+          let diffs = diff(&lhs_lines, &rhs_lines, algorithm);
+          let mut stats =
+            DiffStats::from_lines(&lhs_lines, &rhs_lines, &diffs, algorithm);
+          stats.unchanged += lhs.trimmed_lines();
+          (lhs_path, rhs_path, stats)
+        }
+        _ => continue,
+      };
+      out.push(FileStats { lhs_path, rhs_path, stats });
+    }
+    out
+  }
+
+  /// How alike the two sides are, from 0 (nothing in common) to 1 (equal).
+  /// Moved lines count as common.
+  pub fn similarity(&self) -> f64 {
+    let lhs = self.unchanged + self.deleted + self.modified + self.moved;
+    let rhs = self.unchanged + self.inserted + self.modified + self.moved;
+    similarity::similarity(self.unchanged + self.moved, lhs + rhs)
+  }
+
+  fn changed(&self) -> bool {
+    self.inserted + self.deleted + self.modified + self.moved > 0
+  }
+}
+
+impl AddAssign for DiffStats {
+  fn add_assign(&mut self, other: DiffStats) {
+    self.files_changed += other.files_changed;
+    self.unchanged += other.unchanged;
+    self.inserted += other.inserted;
+    self.deleted += other.deleted;
+    self.modified += other.modified;
+    self.moved += other.moved;
+    self.tokens_changed += other.tokens_changed;
+  }
+}
+
+impl Sum for DiffStats {
+  fn sum<I: Iterator<Item = DiffStats>>(iter: I) -> DiffStats {
+    let mut total = DiffStats::default();
+    for s in iter {
+      total += s;
+    }
+    total
+  }
+}
+
+/// The stats of a single file pair.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct FileStats {
+  pub lhs_path: Option<PathBuf>,
+  pub rhs_path: Option<PathBuf>,
+  pub stats: DiffStats,
+}
+
+/// Counts the non-whitespace tokens `differ` finds deleted or inserted
+/// between `lhs` and `rhs`.
+fn tokens_changed(differ: &TokenDiffer, lhs: &[&[u8]], rhs: &[&[u8]]) -> usize {
+  let lhs_tokens = differ.tokenize(lhs);
+  let rhs_tokens = differ.tokenize(rhs);
+  let changed = |tokens: &[&[u8]]| {
+    tokens.iter().filter(|t| !is_whitespace_token(t)).count()
+  };
+  differ
+    .diff_tokens(&lhs_tokens, &rhs_tokens)
+    .into_iter()
+    .map(|d| match d {
+      Match { .. } => 0,
+      Mutation { lhs, rhs } | Moved { lhs, rhs, .. } => {
+        changed(&lhs_tokens[lhs]) + changed(&rhs_tokens[rhs])
+      }
+    })
+    .sum()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn stats(lhs: &[u8], rhs: &[u8]) -> DiffStats {
+    let lhs = split_lines(lhs);
+    let rhs = split_lines(rhs);
+    let diffs = diff(&lhs, &rhs, Algorithm::Patience);
+    DiffStats::from_lines(&lhs, &rhs, &diffs, Algorithm::Patience)
+  }
+
+  #[test]
+  fn from_diffs() {
+    let diffs = [
+      Match { lhs: 0..2, rhs: 0..2 },
+      Mutation { lhs: 2..5, rhs: 2..3 },
+      Match { lhs: 5..6, rhs: 3..4 },
+      Mutation { lhs: 6..6, rhs: 4..6 },
+    ];
+    assert_eq!(
+      DiffStats::from_diffs(&diffs),
+      DiffStats {
+        files_changed: 1,
+        unchanged: 3,
+        inserted: 2,
+        deleted: 2,
+        modified: 1,
+        ..Default::default()
+      }
+    );
+  }
+
+  #[test]
+  fn from_lines() {
+    assert_eq!(
+      stats(
+        b"let a = 1;\nlet b = 2;\nkeep\n",
+        b"let a = 10;\nsomething else entirely\nkeep\n"
+      ),
+      DiffStats {
+        files_changed: 1,
+        unchanged: 1,
+        inserted: 1,
+        deleted: 1,
+        modified: 1,
+        tokens_changed: 10,
+        ..Default::default()
+      }
+    );
+  }
+
+  #[test]
+  fn similarity() {
+    assert_eq!(stats(b"", b"").similarity(), 1.0);
+    assert_eq!(stats(b"a\nb\n", b"a\nb\n").similarity(), 1.0);
+    assert_eq!(stats(b"a\nb\n", b"c\nd\n").similarity(), 0.0);
+    assert_eq!(stats(b"a\nb\nc\n", b"a\nc\n").similarity(), 0.8);
+  }
+
+  #[test]
+  fn sum() {
+    let one = stats(b"a\nb\n", b"a\nc\n");
+    let total: DiffStats = [one, one].into_iter().sum();
+    assert_eq!(total.files_changed, 2);
+    assert_eq!(total.unchanged, 2);
+    assert_eq!(total.deleted + total.modified, 2);
+  }
+}
@@ -1,7 +1,7 @@
 use owo_colors::Style;
 
 /// Defaults to using the terminal default colors.
-#[derive(Default)]
+#[derive(Clone, Default)]
 pub struct Styles {
   pub header: Style,
   pub separator: Style,
@@ -10,6 +10,9 @@ pub struct Styles {
   pub old_dim: Style,
   pub new: Style,
   pub new_dim: Style,
+  pub moved_old: Style,
+  pub moved_new: Style,
+  pub normalized: Style,
 }
 
 impl Styles {
@@ -24,6 +27,9 @@ impl Styles {
       new: Style::new().green(),
       old_dim: Style::new().dimmed(),
       new_dim: Style::new().default_color(),
+      moved_old: Style::new().magenta(),
+      moved_new: Style::new().blue(),
+      normalized: Style::new().yellow(),
     }
   }
 }
@@ -0,0 +1,14 @@
+fn alpha() -> u32 {
+    let x = 1;
+    x
+}
+
+fn beta() -> u32 {
+    let x = 2;
+    x
+}
+
+fn gamma() -> u32 {
+    let x = 3;
+    x
+}
@@ -0,0 +1,9 @@
+fn alpha() -> u32 {
+    let x = 1;
+    x
+}
+
+fn gamma() -> u32 {
+    let x = 3;
+    x
+}
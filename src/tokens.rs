@@ -1,3 +1,4 @@
+use unicode_normalization::UnicodeNormalization;
 use unicode_segmentation::UnicodeSegmentation;
 
 /// Split `content` into lines, stripping line endings (`\r\n`, `\n`, `\r`).
@@ -40,23 +41,111 @@ pub fn is_whitespace_token(token: &[u8]) -> bool {
   !token.is_empty() && token.iter().all(|b| (*b as char).is_ascii_whitespace())
 }
 
+/// How finely changed lines are split up to highlight edits within them.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+#[cfg_attr(feature = "cli", derive(clap::ValueEnum))]
+pub enum Intraline {
+  /// Every character on its own.
+  Char,
+  /// Every user-perceived character (grapheme cluster) on its own.
+  Grapheme,
+  /// Unicode word boundaries, keeping runs of letters, numbers and spaces
+  /// together.
+  Word,
+  /// Identifiers, numbers, runs of whitespace and single other characters.
+  #[default]
+  Token,
+  /// Whole lines, so edits are not highlighted within them.
+  None,
+}
+
+impl Intraline {
+  /// Splits `lines` into tokens, with a `"\n"` token between lines. With
+  /// `canonical` set, precomposed and decomposed forms of the same text
+  /// split into the same tokens.
+  pub fn tokenize<'a>(
+    self,
+    lines: &[&'a [u8]],
+    canonical: bool,
+  ) -> Vec<&'a [u8]> {
+    let mut v = vec![];
+    for &l in lines {
+      match self {
+        Intraline::Char if !canonical => split_utf8(l, &mut v, |s| {
+          s.char_indices()
+            .map(|(i, c)| &s[i..i + c.len_utf8()])
+            .collect()
+        }),
+        // Splitting combining marks off would tell apart text that only
+        // differs in its encoding, so fall back to graphemes.
+        Intraline::Char | Intraline::Grapheme => {
+          split_utf8(l, &mut v, |s| s.graphemes(true).collect())
+        }
+        Intraline::Word => {
+          split_utf8(l, &mut v, |s| s.split_word_bounds().collect())
+        }
+        Intraline::Token => v.extend(ByteTokenIter::new(l, canonical)),
+        Intraline::None => v.push(l),
+      }
+      v.push(b"\n");
+    }
+    v.pop();
+    v
+  }
+}
+
 pub fn tokenize_lines<'a>(lines: &[&'a [u8]]) -> Vec<&'a [u8]> {
-  let mut v: Vec<_> = lines
-    .iter()
-    .flat_map(|l| ByteTokenIter::new(l).chain([b"\n" as &[u8]]))
-    .collect();
-  v.pop();
-  v
+  Intraline::Token.tokenize(lines, false)
+}
+
+/// Like `tokenize_lines`, but classifies each grapheme by the first character
+/// of its canonical decomposition. This way "é" tokenizes the same whether it
+/// is written as one code point or as "e" plus a combining accent.
+pub fn tokenize_lines_canonical<'a>(lines: &[&'a [u8]]) -> Vec<&'a [u8]> {
+  Intraline::Token.tokenize(lines, true)
+}
+
+/// Appends the pieces `split` cuts the valid UTF-8 runs of `content` into,
+/// and each invalid sequence as a token of its own.
+fn split_utf8<'a>(
+  mut content: &'a [u8],
+  tokens: &mut Vec<&'a [u8]>,
+  split: impl Fn(&'a str) -> Vec<&'a str>,
+) {
+  while !content.is_empty() {
+    let valid = valid_prefix(content);
+    tokens.extend(split(valid).into_iter().map(str::as_bytes));
+    content = &content[valid.len()..];
+    if !content.is_empty() {
+      let n = std::str::from_utf8(content)
+        .err()
+        .and_then(|e| e.error_len())
+        .unwrap_or(1);
+      let (token, rest) = content.split_at(n);
+      tokens.push(token);
+      content = rest;
+    }
+  }
 }
 
 struct ByteTokenIter<'a> {
   content: &'a [u8],
   valid: &'a str,
+  decompose: bool,
 }
 
 impl<'a> ByteTokenIter<'a> {
-  fn new(content: &'a [u8]) -> Self {
-    ByteTokenIter { content, valid: valid_prefix(content) }
+  fn new(content: &'a [u8], decompose: bool) -> Self {
+    ByteTokenIter {
+      content,
+      valid: valid_prefix(content),
+      decompose,
+    }
+  }
+
+  /// The character used to decide which kind of token `grapheme` belongs to.
+  fn lead(&self, grapheme: &str) -> Option<char> {
+    if self.decompose { grapheme.nfd().next() } else { grapheme.chars().next() }
   }
 }
 
@@ -92,12 +181,12 @@ impl<'a> Iterator for ByteTokenIter<'a> {
 
     let mut graphemes = self.valid.grapheme_indices(true);
     let (_, first) = graphemes.next().unwrap();
-    let first_char = first.chars().next().unwrap();
+    let first_char = self.lead(first).unwrap();
 
     let scan = |pred: &dyn Fn(char) -> bool| {
       let mut end = first.len();
       for (off, g) in graphemes {
-        if g.chars().next().is_some_and(pred) {
+        if self.lead(g).is_some_and(pred) {
           end = off + g.len();
         } else {
           break;
@@ -169,6 +258,14 @@ mod tests {
     );
   }
 
+  #[test]
+  fn tokenize_canonical() {
+    assert_eq!(
+      tokenize_lines_canonical(&[b"caf\xc3\xa9 cafe\xcc\x81"]),
+      &[b"caf\xc3\xa9" as &[u8], b" ", b"cafe\xcc\x81"],
+    );
+  }
+
   #[test]
   fn tokenize() {
     assert_eq!(
@@ -192,4 +289,62 @@ mod tests {
       ],
     );
   }
+
+  fn intraline(mode: Intraline, line: &str) -> Vec<&str> {
+    mode
+      .tokenize(&[line.as_bytes()], false)
+      .into_iter()
+      .map(|t| std::str::from_utf8(t).unwrap())
+      .collect()
+  }
+
+  #[test]
+  fn intraline_char() {
+    assert_eq!(intraline(Intraline::Char, "ab=1"), &["a", "b", "=", "1"]);
+    assert_eq!(intraline(Intraline::Char, "e\u{301}"), &["e", "\u{301}"]);
+    assert_eq!(
+      Intraline::Char.tokenize(&[b"e\xcc\x81"], true),
+      &[b"e\xcc\x81" as &[u8]],
+    );
+    assert_eq!(
+      Intraline::Char.tokenize(&[b"a\xffb"], false),
+      &[b"a" as &[u8], b"\xff", b"b"],
+    );
+  }
+
+  #[test]
+  fn intraline_grapheme() {
+    assert_eq!(
+      intraline(Intraline::Grapheme, "e\u{301}x 日本"),
+      &["e\u{301}", "x", " ", "日", "本"],
+    );
+  }
+
+  #[test]
+  fn intraline_word() {
+    assert_eq!(
+      intraline(Intraline::Word, "can't stop  3.14 foo_bar()"),
+      &["can't", " ", "stop", "  ", "3.14", " ", "foo_bar", "(", ")"],
+    );
+    assert_eq!(
+      intraline(Intraline::Word, "カタカナ です"),
+      &["カタカナ", " ", "で", "す"],
+    );
+  }
+
+  #[test]
+  fn intraline_token() {
+    assert_eq!(
+      Intraline::Token.tokenize(&[b"x += 12", b"y"], false),
+      &[b"x" as &[u8], b" ", b"+", b"=", b" ", b"12", b"\n", b"y"],
+    );
+  }
+
+  #[test]
+  fn intraline_none() {
+    assert_eq!(
+      Intraline::None.tokenize(&[b"x += 12", b"", b"y"], false),
+      &[b"x += 12" as &[u8], b"\n", b"", b"\n", b"y"],
+    );
+  }
 }
@@ -0,0 +1,362 @@
+use std::fs;
+use std::io::Result;
+use std::io::Write;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
+
+use crate::hunks::Hunk;
+use crate::patch::PatchHunk;
+use crate::patch::offset;
+
+/// Which kind of file headers a patch gets.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub(crate) enum Headers {
+  /// `diff --git` headers and `a/` and `b/` prefixes, like `git diff`.
+  Git,
+  /// `---` and `+++` lines with modification times, like `diff -u`.
+  Gnu,
+}
+
+/// One side of a file patch.
+pub(crate) struct Side<'a> {
+  /// The name to write in the headers, or `None` if the file is missing.
+  pub name: Option<PathBuf>,
+  /// Where the file is on disk, for its mode and modification time.
+  pub path: Option<&'a Path>,
+  /// The lines, as split by `patch_lines`.
+  pub lines: &'a [&'a [u8]],
+  /// The line number `lines` starts at.
+  pub first_line: usize,
+}
+
+/// Splits `text` into lines after each `\n`, the way diff and patch see
+/// lines, keeping the `\n`s so a missing final newline is a difference.
+pub(crate) fn patch_lines(text: &[u8]) -> Vec<&[u8]> {
+  text.split_inclusive(|&b| b == b'\n').collect()
+}
+
+/// Writes a patch of `hunks` turning `old` into `new`, exactly as `git diff`
+/// or `diff -u` would so that `git apply` and `patch` accept it.
+pub(crate) fn write_patch(
+  out: &mut dyn Write,
+  headers: Headers,
+  old: &Side,
+  new: &Side,
+  hunks: &[Hunk],
+) -> Result<()> {
+  let (old_mode, new_mode) = (mode(old), mode(new));
+  let only_content = old_mode.is_some() && old_mode == new_mode;
+  if hunks.is_empty() && (headers == Headers::Gnu || only_content) {
+    return Ok(());
+  }
+  if headers == Headers::Git {
+    write_git_header(out, old, new)?;
+    if hunks.is_empty() {
+      return Ok(());
+    }
+  }
+  for (marker, side, prefix) in [("---", old, "a"), ("+++", new, "b")] {
+    let name = match (headers, &side.name) {
+      (_, None) => "/dev/null".into(),
+      (Headers::Git, Some(name)) => {
+        quote(&format!("{prefix}/{}", name.display()))
+      }
+      (Headers::Gnu, Some(name)) => quote(&name.display().to_string()),
+    };
+    match headers {
+      Headers::Git => writeln!(out, "{marker} {name}")?,
+      Headers::Gnu => writeln!(out, "{marker} {name}\t{}", timestamp(side))?,
+    }
+  }
+
+  let mut buf = vec![];
+  for h in hunks {
+    let (l, r) = (h.lhs(), h.rhs());
+    let text = |lines: &[&[u8]]| -> Vec<Vec<u8>> {
+      lines
+        .iter()
+        .map(|l| l.strip_suffix(b"\n").unwrap_or(l).to_vec())
+        .collect()
+    };
+    // Only a file's last line can be missing its newline.
+    let no_newline =
+      |lines: &[&[u8]]| lines.last().is_some_and(|l| !l.ends_with(b"\n"));
+    let diffs = h.diffs.iter().cloned();
+    PatchHunk {
+      hunk: Hunk {
+        diffs: diffs
+          .map(|d| offset(d, old.first_line, new.first_line))
+          .collect(),
+      },
+      old_lines: text(&old.lines[l.clone()]),
+      new_lines: text(&new.lines[r.clone()]),
+      old_no_newline: no_newline(&old.lines[l]),
+      new_no_newline: no_newline(&new.lines[r]),
+      heading: vec![],
+    }
+    .write_unified(&mut buf);
+  }
+  out.write_all(&buf)
+}
+
+/// Writes that two files differ without showing how.
+pub(crate) fn write_binary(
+  out: &mut dyn Write,
+  headers: Headers,
+  old: &Side,
+  new: &Side,
+) -> Result<()> {
+  let name = |side: &Side, prefix: &str| match (&side.name, headers) {
+    (None, _) => "/dev/null".into(),
+    (Some(name), Headers::Git) => {
+      quote(&format!("{prefix}/{}", name.display()))
+    }
+    (Some(name), Headers::Gnu) => quote(&name.display().to_string()),
+  };
+  if headers == Headers::Git {
+    write_git_header(out, old, new)?;
+  }
+  writeln!(out, "Binary files {} and {} differ", name(old, "a"), name(new, "b"))
+}
+
+/// Writes the `diff --git` line, followed by lines for files being created
+/// or deleted and mode changes.
+fn write_git_header(out: &mut dyn Write, old: &Side, new: &Side) -> Result<()> {
+  // A missing side is named after the other, as git does.
+  let old_name = old.name.as_ref().or(new.name.as_ref()).unwrap();
+  let new_name = new.name.as_ref().or(old.name.as_ref()).unwrap();
+  writeln!(
+    out,
+    "diff --git {} {}",
+    quote(&format!("a/{}", old_name.display())),
+    quote(&format!("b/{}", new_name.display())),
+  )?;
+  match (mode(old), mode(new)) {
+    (None, Some(mode)) => writeln!(out, "new file mode {mode:o}"),
+    (Some(mode), None) => writeln!(out, "deleted file mode {mode:o}"),
+    (Some(old), Some(new)) if old != new => {
+      writeln!(out, "old mode {old:o}")?;
+      writeln!(out, "new mode {new:o}")
+    }
+    _ => Ok(()),
+  }
+}
+
+/// The file mode git would record for `side`, if it exists.
+fn mode(side: &Side) -> Option<u32> {
+  side.name.as_ref()?;
+  let Some(meta) = side.path.and_then(|p| fs::symlink_metadata(p).ok()) else {
+    // Like stdin.
+    return Some(0o100644);
+  };
+  if meta.file_type().is_symlink() {
+    return Some(0o120000);
+  }
+  cfg_if::cfg_if! {
+    if #[cfg(unix)] {
+      use std::os::unix::fs::PermissionsExt;
+      let executable = meta.permissions().mode() & 0o111 != 0;
+    } else {
+      let executable = false;
+    }
+  }
+  Some(if executable { 0o100755 } else { 0o100644 })
+}
+
+/// Quotes `name` the way git does if it has characters that don't survive
+/// in a patch as is.
+fn quote(name: &str) -> String {
+  if !name
+    .chars()
+    .any(|c| c.is_ascii_control() || c == '"' || c == '\\')
+  {
+    return name.into();
+  }
+  let mut out = String::from("\"");
+  for c in name.chars() {
+    match c {
+      '"' => out.push_str("\\\""),
+      '\\' => out.push_str("\\\\"),
+      '\t' => out.push_str("\\t"),
+      '\n' => out.push_str("\\n"),
+      '\r' => out.push_str("\\r"),
+      c if c.is_ascii_control() => out.push_str(&format!("\\{:03o}", c as u32)),
+      c => out.push(c),
+    }
+  }
+  out.push('"');
+  out
+}
+
+/// The modification time `diff -u` shows for `side`, in UTC. Missing files
+/// get the epoch, which `patch` reads as the file not existing.
+fn timestamp(side: &Side) -> String {
+  let time = match (&side.name, side.path) {
+    (None, _) => UNIX_EPOCH,
+    (Some(_), path) => path
+      .and_then(|p| fs::metadata(p).ok()?.modified().ok())
+      .unwrap_or_else(SystemTime::now),
+  };
+  let since = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+  let secs = since.as_secs();
+  let (year, month, day) = civil_from_days((secs / 86400) as i64);
+  format!(
+    "{year:04}-{month:02}-{day:02} {:02}:{:02}:{:02}.{:09} +0000",
+    secs / 3600 % 24,
+    secs / 60 % 60,
+    secs % 60,
+    since.subsec_nanos()
+  )
+}
+
+/// Converts days since 1970-01-01 to a year, month and day, with Howard
+/// Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+  let z = days + 719468;
+  let era = z.div_euclid(146097);
+  let doe = z.rem_euclid(146097);
+  let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+  let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+  let mp = (5 * doy + 2) / 153;
+  let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+  let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+  let year = yoe + era * 400 + i64::from(month <= 2);
+  (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::apply::ApplyOptions;
+  use crate::apply::apply;
+  use crate::files::FilePairEvent;
+  use crate::patch::Patch;
+  use crate::printer::Format;
+  use crate::printer::Printer;
+
+  fn unified(
+    format: Format,
+    context: usize,
+    lhs: Option<&str>,
+    rhs: Option<&str>,
+  ) -> String {
+    let mut out = vec![];
+    Printer::default(&mut out, context, PathBuf::new())
+      .with_format(format)
+      .print_file_pair_event(FilePairEvent::TextDiff {
+        lhs_path: lhs.map(|_| "f.txt".into()),
+        rhs_path: rhs.map(|_| "f.txt".into()),
+        lhs_content: lhs.unwrap_or_default().into(),
+        rhs_content: rhs.unwrap_or_default().into(),
+      })
+      .unwrap();
+    String::from_utf8(out).unwrap()
+  }
+
+  /// Checks that the patch from `lhs` to `rhs` parses and applies back to
+  /// `rhs`.
+  fn round_trip(lhs: Option<&str>, rhs: Option<&str>) {
+    for format in [Format::Unified, Format::GnuUnified] {
+      let out = unified(format, 3, lhs, rhs);
+      let patch = Patch::parse(out.as_bytes()).unwrap();
+      let dir = tempfile::tempdir().unwrap();
+      let path = dir.path().join("f.txt");
+      if let Some(lhs) = lhs {
+        fs::write(&path, lhs).unwrap();
+      }
+      let reports = apply(&patch, dir.path(), &ApplyOptions::default());
+      assert!(reports.iter().all(|r| r.is_ok()), "{out}\n{reports:?}");
+      match rhs {
+        Some(rhs) => {
+          assert_eq!(fs::read_to_string(&path).unwrap(), rhs, "{out}")
+        }
+        None => assert!(!path.exists(), "{out}"),
+      }
+    }
+  }
+
+  #[test]
+  fn round_trips() {
+    let long: String = (0..20).map(|i| format!("{i}\n")).collect();
+    let edited = long.replace("2\n", "two\n").replace("17\n", "17\n18½\n");
+    let cases = [
+      (Some("a\nb\nc\n"), Some("a\nB\nc\n")),
+      (Some(&long), Some(&edited)),
+      (Some("a\nb"), Some("a\nb\n")),
+      (Some("a\nb\n"), Some("a\nb")),
+      (Some("a"), Some("a\nb\n")),
+      (Some("a\n"), Some("a\nb")),
+      (Some("a\nb\nc\nd\ne\n"), Some("a\nB\nc\nd\ne\nf")),
+      (Some("a\nb\nc\nd\ne"), Some("a\nB\nc\nd\ne\nf\n")),
+      (Some("x"), Some("y")),
+      (Some("a\r\nb\r\n"), Some("a\r\nc\r\n")),
+      (None, Some("new\nfile")),
+      (Some("old\n"), None),
+      (
+        Some(include_str!("testdata/old/eof-newline.txt")),
+        Some(include_str!("testdata/new/eof-newline.txt")),
+      ),
+    ];
+    for (lhs, rhs) in cases {
+      round_trip(lhs, rhs);
+    }
+  }
+
+  #[test]
+  fn git_output() {
+    assert_eq!(
+      unified(Format::Unified, 0, Some("b\nc"), Some("a\nb\nc\n")),
+      "diff --git a/f.txt b/f.txt
+--- a/f.txt
++++ b/f.txt
+@@ -0,0 +1 @@
++a
+@@ -2 +3 @@
+-c
+\\ No newline at end of file
++c
+"
+    );
+    assert_eq!(
+      unified(Format::Unified, 3, None, Some("a\n")),
+      "diff --git a/f.txt b/f.txt
+new file mode 100644
+--- /dev/null
++++ b/f.txt
+@@ -0,0 +1 @@
++a
+"
+    );
+    assert_eq!(
+      unified(Format::Unified, 3, None, Some("")),
+      "diff --git a/f.txt b/f.txt\nnew file mode 100644\n"
+    );
+  }
+
+  #[test]
+  fn gnu_output() {
+    let out = unified(Format::GnuUnified, 3, Some("a\n"), None);
+    let (header, hunk) = out.split_at(out.find("@@").unwrap());
+    let [old, new] = header.lines().collect::<Vec<_>>()[..] else {
+      panic!("{header}")
+    };
+    assert!(old.starts_with("--- f.txt\t"), "{old}");
+    assert_eq!(new, "+++ /dev/null\t1970-01-01 00:00:00.000000000 +0000");
+    assert_eq!(hunk, "@@ -1 +0,0 @@\n-a\n");
+  }
+
+  #[test]
+  fn quoting() {
+    assert_eq!(quote("a/b c.txt"), "a/b c.txt");
+    assert_eq!(quote("a/\"q\"\t\u{1}"), r#""a/\"q\"\t\001""#);
+  }
+
+  #[test]
+  fn dates() {
+    assert_eq!(civil_from_days(0), (1970, 1, 1));
+    assert_eq!(civil_from_days(19723), (2024, 1, 1));
+    assert_eq!(civil_from_days(19782), (2024, 2, 29));
+  }
+}